@@ -1,17 +1,32 @@
-use clap::{App, Arg, ArgMatches};
-use regex::RegexBuilder;
+mod passwd_file;
 
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
-    Argon2,
-};
+use clap::{App, Arg, ArgMatches};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
 
+use passwd_file::PasswdFile;
+use ratelmq::broker::authentication::password::{hash_password, HashAlgorithm, HashedPassword};
 use ratelmq::config::build_info::BUILD_INFO;
 
 const ARGUMENT_NAME_FILE: &str = "file";
+const ARGUMENT_NAME_CREATE: &str = "create";
 const ARGUMENT_NAME_USER: &str = "user";
 const ARGUMENT_NAME_PASSWORD: &str = "password";
 const ARGUMENT_NAME_DELETE: &str = "delete";
+const ARGUMENT_NAME_VERIFY: &str = "verify";
+const ARGUMENT_NAME_LIST: &str = "list";
+const ARGUMENT_NAME_BATCH: &str = "batch";
+const ARGUMENT_NAME_CSV: &str = "csv";
+const ARGUMENT_NAME_ALGORITHM: &str = "algorithm";
+const ARGUMENT_NAME_ITERATIONS: &str = "iterations";
+const ARGUMENT_NAME_MEMORY: &str = "memory";
+const ARGUMENT_NAME_PARALLELISM: &str = "parallelism";
+const ARGUMENT_NAME_COST: &str = "cost";
+
+/// Exit code of a failed verification, errors exit with 2.
+const EXIT_CODE_MISMATCH: i32 = 1;
+const EXIT_CODE_ERROR: i32 = 2;
 
 fn main() {
     env_logger::init();
@@ -20,41 +35,180 @@ fn main() {
 
     let arguments = build_arguments(version);
 
-    let file_name = arguments.value_of(ARGUMENT_NAME_FILE).unwrap();
+    match run(&arguments) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(EXIT_CODE_MISMATCH),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(EXIT_CODE_ERROR);
+        }
+    }
+}
+
+/// Returns false when the verified password does not match.
+fn run(arguments: &ArgMatches) -> Result<bool, String> {
+    let path = Path::new(arguments.value_of(ARGUMENT_NAME_FILE).unwrap());
+    let create = arguments.is_present(ARGUMENT_NAME_CREATE);
+
+    let mut file = PasswdFile::load(path, create).map_err(|e| {
+        format!(
+            "cannot read {}: {} (use --{} to create it)",
+            path.display(),
+            e,
+            ARGUMENT_NAME_CREATE
+        )
+    })?;
+
+    if arguments.is_present(ARGUMENT_NAME_LIST) {
+        for user_name in file.users() {
+            println!("{}", user_name);
+        }
+        return Ok(true);
+    }
+
+    if let Some(source) = arguments.value_of(ARGUMENT_NAME_BATCH) {
+        let algorithm = hash_algorithm(arguments)?;
+        let entries = read_batch(source, arguments.is_present(ARGUMENT_NAME_CSV))?;
+
+        // all entries are hashed before anything is written, so an invalid one changes nothing
+        for (user_name, password) in &entries {
+            file.set(user_name, &hash(password, algorithm)?);
+        }
+        save(&file, path)?;
+
+        println!("Imported {} users", entries.len());
+        return Ok(true);
+    }
+
     let user_name = arguments.value_of(ARGUMENT_NAME_USER).unwrap();
     let password = arguments.value_of(ARGUMENT_NAME_PASSWORD);
-    let delete = arguments.is_present(ARGUMENT_NAME_DELETE);
-
-    let regexp_format = format!(r"{}{}", regex::escape(user_name), r":.*\n");
-    let regexp = RegexBuilder::new(regexp_format.as_str())
-        .case_insensitive(true)
-        .multi_line(true)
-        .build()
-        .unwrap();
-
-    let credentials = std::fs::read_to_string(file_name).unwrap();
-
-    let entry_exists = regexp.is_match(credentials.as_str());
-    let updated_credentials = if entry_exists {
-        let text = match delete {
-            true => "".to_string(),
-            false => build_entry(user_name, password).replace("$", "$$"), // for regexp
-        };
-
-        Some(
-            regexp
-                .replace(credentials.as_str(), text.as_str())
-                .into_owned(),
-        )
-    } else if !delete {
-        Some(credentials + build_entry(user_name, password).as_str())
+
+    if arguments.is_present(ARGUMENT_NAME_VERIFY) {
+        return verify(&file, user_name, password.unwrap());
+    }
+
+    if arguments.is_present(ARGUMENT_NAME_DELETE) {
+        if file.delete(user_name) {
+            save(&file, path)?;
+        } else {
+            eprintln!("User {} does not exist", user_name);
+        }
+        return Ok(true);
+    }
+
+    validate_user_name(user_name)?;
+    let algorithm = hash_algorithm(arguments)?;
+    file.set(user_name, &hash(password.unwrap(), algorithm)?);
+    save(&file, path)?;
+
+    Ok(true)
+}
+
+fn verify(file: &PasswdFile, user_name: &str, password: &str) -> Result<bool, String> {
+    let verified = match file.hash(user_name) {
+        Some(hash) => hash
+            .parse::<HashedPassword>()
+            .and_then(|hash| hash.verify(password.as_bytes()))
+            .map_err(|e| format!("invalid password hash of user {}: {:?}", user_name, e))?,
+        None => {
+            eprintln!("User {} does not exist", user_name);
+            false
+        }
+    };
+
+    println!(
+        "{}",
+        if verified {
+            "Password is correct"
+        } else {
+            "Password is incorrect"
+        }
+    );
+
+    Ok(verified)
+}
+
+/// Reads `user:password` lines or `user,password` CSV records, `-` is the standard input.
+fn read_batch(source: &str, csv: bool) -> Result<Vec<(String, String)>, String> {
+    let reader: Box<dyn Read> = if source == "-" {
+        Box::new(io::stdin())
     } else {
-        None
+        Box::new(File::open(source).map_err(|e| format!("cannot read {}: {}", source, e))?)
     };
 
-    if let Some(new_file_content) = updated_credentials {
-        std::fs::write(file_name, new_file_content).unwrap();
+    let entries = if csv {
+        read_csv(reader)?
+    } else {
+        read_lines(reader)?
+    };
+
+    for (user_name, _) in &entries {
+        validate_user_name(user_name)?;
+    }
+
+    Ok(entries)
+}
+
+fn read_lines(reader: impl Read) -> Result<Vec<(String, String)>, String> {
+    let mut entries = Vec::new();
+
+    for (index, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line.map_err(|e| format!("cannot read line {}: {}", index + 1, e))?;
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        if line.is_empty() {
+            continue;
+        }
+
+        // user names cannot contain a colon, passwords can
+        let (user_name, password) = line
+            .split_once(':')
+            .ok_or_else(|| format!("line {}: expected user:password", index + 1))?;
+        entries.push((user_name.to_string(), password.to_string()));
     }
+
+    Ok(entries)
+}
+
+fn read_csv(reader: impl Read) -> Result<Vec<(String, String)>, String> {
+    let mut entries = Vec::new();
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(reader);
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+
+        match (record.len(), record.get(0), record.get(1)) {
+            (2, Some(user_name), Some(password)) => {
+                entries.push((user_name.to_string(), password.to_string()))
+            }
+            _ => return Err(format!("line {}: expected user,password", line)),
+        }
+    }
+
+    Ok(entries)
+}
+
+fn validate_user_name(user_name: &str) -> Result<(), String> {
+    if user_name.is_empty() || user_name.contains(':') || user_name.chars().any(char::is_control) {
+        Err(format!(
+            "invalid user name {:?}, it must not be empty and must not contain ':' or control characters",
+            user_name
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn hash(password: &str, algorithm: HashAlgorithm) -> Result<String, String> {
+    hash_password(password.as_bytes(), algorithm)
+        .map_err(|e| format!("cannot hash password: {:?}", e))
+}
+
+fn save(file: &PasswdFile, path: &Path) -> Result<(), String> {
+    file.save(path)
+        .map_err(|e| format!("cannot write {}: {}", path.display(), e))
 }
 
 fn build_arguments(version: String) -> ArgMatches {
@@ -71,13 +225,20 @@ fn build_arguments(version: String) -> ArgMatches {
                 .required(false)
                 .takes_value(true),
         )
+        .arg(
+            Arg::new(ARGUMENT_NAME_CREATE)
+                .short('c')
+                .long(ARGUMENT_NAME_CREATE)
+                .about("Create the passwd file if it does not exist")
+                .takes_value(false),
+        )
         .arg(
             Arg::new(ARGUMENT_NAME_USER)
                 .short('u')
                 .long(ARGUMENT_NAME_USER)
                 .value_name("USER_NAME")
-                .about("User name to modify")
-                .required(true)
+                .about("User name to modify, the match is case-sensitive")
+                .required_unless_present_any([ARGUMENT_NAME_LIST, ARGUMENT_NAME_BATCH])
                 .takes_value(true),
         )
         .arg(
@@ -85,8 +246,12 @@ fn build_arguments(version: String) -> ArgMatches {
                 .short('p')
                 .long(ARGUMENT_NAME_PASSWORD)
                 .value_name("PASSWORD")
-                .about("Password to be set")
-                .required_unless_present(ARGUMENT_NAME_DELETE)
+                .about("Password to be set or verified")
+                .required_unless_present_any([
+                    ARGUMENT_NAME_DELETE,
+                    ARGUMENT_NAME_LIST,
+                    ARGUMENT_NAME_BATCH,
+                ])
                 .conflicts_with(ARGUMENT_NAME_DELETE)
                 .takes_value(true),
         )
@@ -98,19 +263,119 @@ fn build_arguments(version: String) -> ArgMatches {
                 .required(false)
                 .takes_value(false),
         )
+        .arg(
+            Arg::new(ARGUMENT_NAME_VERIFY)
+                .long(ARGUMENT_NAME_VERIFY)
+                .about("Check the password of the user, exits with 1 if it does not match")
+                .conflicts_with(ARGUMENT_NAME_DELETE)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new(ARGUMENT_NAME_LIST)
+                .short('l')
+                .long(ARGUMENT_NAME_LIST)
+                .about("List user names")
+                .conflicts_with_all(&[
+                    ARGUMENT_NAME_USER,
+                    ARGUMENT_NAME_PASSWORD,
+                    ARGUMENT_NAME_BATCH,
+                ])
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new(ARGUMENT_NAME_BATCH)
+                .short('b')
+                .long(ARGUMENT_NAME_BATCH)
+                .value_name("FILE")
+                .about("Add or update users from user:password lines, - reads the standard input")
+                .conflicts_with_all(&[ARGUMENT_NAME_USER, ARGUMENT_NAME_PASSWORD])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new(ARGUMENT_NAME_CSV)
+                .long(ARGUMENT_NAME_CSV)
+                .about("Batch input is CSV with user,password records")
+                .requires(ARGUMENT_NAME_BATCH)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new(ARGUMENT_NAME_ALGORITHM)
+                .short('a')
+                .long(ARGUMENT_NAME_ALGORITHM)
+                .value_name("ALGORITHM")
+                .about("Password hashing algorithm, mosquitto is PBKDF2-SHA512 in the mosquitto_passwd format")
+                .possible_values(["argon2", "bcrypt", "pbkdf2", "mosquitto"])
+                .default_value("argon2")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new(ARGUMENT_NAME_ITERATIONS)
+                .long(ARGUMENT_NAME_ITERATIONS)
+                .value_name("ITERATIONS")
+                .about("Iterations of argon2, rounds of pbkdf2 or mosquitto")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new(ARGUMENT_NAME_MEMORY)
+                .long(ARGUMENT_NAME_MEMORY)
+                .value_name("KIB")
+                .about("Memory used by argon2 in KiB")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new(ARGUMENT_NAME_PARALLELISM)
+                .long(ARGUMENT_NAME_PARALLELISM)
+                .value_name("LANES")
+                .about("Parallelism of argon2")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new(ARGUMENT_NAME_COST)
+                .long(ARGUMENT_NAME_COST)
+                .value_name("COST")
+                .about("Cost of bcrypt, 4 to 31")
+                .takes_value(true),
+        )
         .get_matches()
 }
 
-fn build_entry(user_name: &str, maybe_password: Option<&str>) -> String {
-    let password = maybe_password.unwrap();
+fn hash_algorithm(arguments: &ArgMatches) -> Result<HashAlgorithm, String> {
+    let number = |name: &str| {
+        arguments
+            .value_of(name)
+            .map(|value| {
+                value
+                    .parse::<u32>()
+                    .map_err(|_| format!("invalid value of --{}: {}", name, value))
+            })
+            .transpose()
+    };
 
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
+    let mut algorithm = match arguments.value_of(ARGUMENT_NAME_ALGORITHM).unwrap() {
+        "bcrypt" => HashAlgorithm::bcrypt(),
+        "pbkdf2" => HashAlgorithm::pbkdf2(),
+        "mosquitto" => HashAlgorithm::mosquitto(),
+        _ => HashAlgorithm::argon2(),
+    };
 
-    let encrypted_password = argon2
-        .hash_password(password.as_bytes(), &salt)
-        .unwrap()
-        .to_string();
+    match &mut algorithm {
+        HashAlgorithm::Argon2 {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => {
+            *memory_kib = number(ARGUMENT_NAME_MEMORY)?.unwrap_or(*memory_kib);
+            *iterations = number(ARGUMENT_NAME_ITERATIONS)?.unwrap_or(*iterations);
+            *parallelism = number(ARGUMENT_NAME_PARALLELISM)?.unwrap_or(*parallelism);
+        }
+        HashAlgorithm::Bcrypt { cost } => *cost = number(ARGUMENT_NAME_COST)?.unwrap_or(*cost),
+        HashAlgorithm::Pbkdf2 { rounds } => {
+            *rounds = number(ARGUMENT_NAME_ITERATIONS)?.unwrap_or(*rounds)
+        }
+        HashAlgorithm::Mosquitto { iterations } => {
+            *iterations = number(ARGUMENT_NAME_ITERATIONS)?.unwrap_or(*iterations)
+        }
+    }
 
-    format!("{}:{}\n", user_name, &encrypted_password)
+    Ok(algorithm)
 }
@@ -0,0 +1,249 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+enum Line {
+    Entry {
+        user_name: String,
+        hash: String,
+    },
+    /// Lines which are not entries are kept as they are.
+    Other(String),
+}
+
+/// Content of the RatelMQ password file with `user:hash` lines.
+#[derive(Debug, Default, PartialEq)]
+pub struct PasswdFile {
+    lines: Vec<Line>,
+}
+
+impl PasswdFile {
+    /// Reads the file, an empty one is returned when it does not exist and `create` is set.
+    pub fn load(path: &Path, create: bool) -> io::Result<PasswdFile> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(e) if e.kind() == ErrorKind::NotFound && create => Ok(PasswdFile::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn parse(content: &str) -> PasswdFile {
+        let lines = content
+            .lines()
+            .map(|line| match line.split_once(':') {
+                Some((user_name, hash)) => Line::Entry {
+                    user_name: user_name.to_string(),
+                    hash: hash.to_string(),
+                },
+                None => Line::Other(line.to_string()),
+            })
+            .collect();
+
+        PasswdFile { lines }
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Entry { user_name, .. } => Some(user_name.as_str()),
+            Line::Other(_) => None,
+        })
+    }
+
+    pub fn hash(&self, user_name: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| match line {
+            Line::Entry { user_name: u, hash } if u == user_name => Some(hash.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Replaces the hash of the user or adds a new entry at the end.
+    pub fn set(&mut self, user_name: &str, new_hash: &str) {
+        // only the first entry of the user is kept, the others would be ambiguous
+        let mut seen = false;
+        self.lines.retain(|line| match line {
+            Line::Entry { user_name: u, .. } if u == user_name => {
+                !std::mem::replace(&mut seen, true)
+            }
+            _ => true,
+        });
+
+        let existing = self.lines.iter_mut().find_map(|line| match line {
+            Line::Entry { user_name: u, hash } if u == user_name => Some(hash),
+            _ => None,
+        });
+
+        match existing {
+            Some(hash) => *hash = new_hash.to_string(),
+            None => self.lines.push(Line::Entry {
+                user_name: user_name.to_string(),
+                hash: new_hash.to_string(),
+            }),
+        }
+    }
+
+    /// Returns false if the user does not exist.
+    pub fn delete(&mut self, user_name: &str) -> bool {
+        let count = self.lines.len();
+
+        self.lines
+            .retain(|line| !matches!(line, Line::Entry { user_name: u, .. } if u == user_name));
+
+        self.lines.len() != count
+    }
+
+    pub fn content(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| match line {
+                Line::Entry { user_name, hash } => format!("{}:{}\n", user_name, hash),
+                Line::Other(line) => format!("{}\n", line),
+            })
+            .collect()
+    }
+
+    /// Writes a temporary file next to the target and renames it, so the broker never reads
+    /// a partially written file. Permissions of the existing file are preserved.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let temporary_path = temporary_path(path);
+
+        let result = Self::write(&temporary_path, path, self.content().as_bytes())
+            .and_then(|_| fs::rename(&temporary_path, path));
+        if result.is_err() {
+            let _ = fs::remove_file(&temporary_path);
+        }
+
+        result
+    }
+
+    fn write(temporary_path: &Path, path: &Path, content: &[u8]) -> io::Result<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            // readable only by the owner until the permissions are copied
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(temporary_path)?;
+        match fs::metadata(path) {
+            Ok(metadata) => file.set_permissions(metadata.permissions())?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        file.write_all(content)?;
+        file.sync_all()
+    }
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str = "alice:hash-a\nBob:hash-B\nbob:hash-b\n";
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ratelmq-passwd-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_exact_match() {
+        let mut file = PasswdFile::parse(CONTENT);
+
+        assert_eq!(file.hash("bob"), Some("hash-b"));
+        assert_eq!(file.hash("Bob"), Some("hash-B"));
+        assert_eq!(file.hash("BOB"), None);
+
+        file.set("bob", "new");
+        assert_eq!(file.content(), "alice:hash-a\nBob:hash-B\nbob:new\n");
+
+        assert!(file.delete("Bob"));
+        assert!(!file.delete("Bob"));
+        assert_eq!(file.content(), "alice:hash-a\nbob:new\n");
+    }
+
+    #[test]
+    fn test_set_adds_new_user_and_removes_duplicates() {
+        let mut file = PasswdFile::parse("alice:1\nbob:2\nalice:3\n");
+
+        file.set("alice", "new");
+        file.set("carol", "4");
+
+        assert_eq!(file.content(), "alice:new\nbob:2\ncarol:4\n");
+        assert_eq!(
+            file.users().collect::<Vec<_>>(),
+            vec!["alice", "bob", "carol"]
+        );
+    }
+
+    #[test]
+    fn test_hash_may_contain_colons() {
+        let file = PasswdFile::parse("alice:$7$101$a:b\n");
+
+        assert_eq!(file.hash("alice"), Some("$7$101$a:b"));
+        assert_eq!(file.content(), "alice:$7$101$a:b\n");
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let path = test_path("missing");
+
+        assert_eq!(
+            PasswdFile::load(&path, false).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            PasswdFile::load(&path, true).unwrap(),
+            PasswdFile::default()
+        );
+    }
+
+    #[test]
+    fn test_save() {
+        let path = test_path("save");
+        let _ = fs::remove_file(&path);
+
+        let mut file = PasswdFile::load(&path, true).unwrap();
+        file.set("alice", "hash");
+        file.save(&path).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "alice:hash\n");
+        assert!(!temporary_path(&path).exists());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_preserves_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = test_path("permissions");
+        fs::write(&path, CONTENT).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        let mut file = PasswdFile::load(&path, false).unwrap();
+        file.delete("alice");
+        file.save(&path).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "Bob:hash-B\nbob:hash-b\n"
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::blocking::Client;
+use reqwest::header::AUTHORIZATION;
+use reqwest::{Method, StatusCode};
+use serde_json::Value;
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum ApiError {
+    Request(reqwest::Error),
+    Read(std::io::Error),
+    /// Response with an error status and the message returned by the broker.
+    Status(StatusCode, String),
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(error: reqwest::Error) -> Self {
+        ApiError::Request(error)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Request(e) => write!(f, "request failed: {}", e),
+            ApiError::Read(e) => write!(f, "reading the response failed: {}", e),
+            ApiError::Status(status, message) => write!(f, "{}: {}", status, message),
+        }
+    }
+}
+
+/// Client of the REST API of the broker's management listener.
+pub struct ApiClient {
+    url: String,
+    token: String,
+    client: Client,
+    /// Without the timeout, for responses streamed until interrupted.
+    stream_client: Client,
+}
+
+impl ApiClient {
+    pub fn new(url: &str, token: &str) -> Result<ApiClient, ApiError> {
+        let client = Client::builder().timeout(TIMEOUT).build()?;
+        let stream_client = Client::builder()
+            .connect_timeout(TIMEOUT)
+            .timeout(None)
+            .build()?;
+
+        Ok(ApiClient {
+            url: url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            client,
+            stream_client,
+        })
+    }
+
+    pub fn get(&self, path: &str) -> Result<Value, ApiError> {
+        self.request(Method::GET, path, None)
+    }
+
+    pub fn post(&self, path: &str, body: Value) -> Result<Value, ApiError> {
+        self.request(Method::POST, path, Some(body))
+    }
+
+    pub fn delete(&self, path: &str) -> Result<Value, ApiError> {
+        self.request(Method::DELETE, path, None)
+    }
+
+    /// Calls `on_line` with every line of a streamed GET response until the broker ends it.
+    pub fn stream<F: FnMut(&str)>(&self, path: &str, mut on_line: F) -> Result<(), ApiError> {
+        let response = self
+            .stream_client
+            .get(format!("{}/api/v1/{}", self.url, path))
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+            .send()?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(status_error(status, response.text()?));
+        }
+
+        for line in BufReader::new(response).lines() {
+            on_line(&line.map_err(ApiError::Read)?);
+        }
+
+        Ok(())
+    }
+
+    /// Returns `Value::Null` for responses without body.
+    fn request(&self, method: Method, path: &str, body: Option<Value>) -> Result<Value, ApiError> {
+        let mut request = self
+            .client
+            .request(method, format!("{}/api/v1/{}", self.url, path))
+            .header(AUTHORIZATION, format!("Bearer {}", self.token));
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+
+        let response = request.send()?;
+        let status = response.status();
+        let text = response.text()?;
+
+        if !status.is_success() {
+            return Err(status_error(status, text));
+        }
+
+        match text.is_empty() {
+            true => Ok(Value::Null),
+            false => serde_json::from_str(&text).map_err(|e| {
+                ApiError::Status(status, format!("invalid response from the broker: {}", e))
+            }),
+        }
+    }
+}
+
+fn status_error(status: StatusCode, text: String) -> ApiError {
+    let message = serde_json::from_str::<Value>(&text)
+        .ok()
+        .and_then(|value| value["error"].as_str().map(String::from))
+        .unwrap_or(text);
+
+    ApiError::Status(status, message)
+}
+
+/// Encodes a client id or a topic to be used as a single path segment.
+pub fn segment(value: &str) -> String {
+    utf8_percent_encode(value, NON_ALPHANUMERIC).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segment() {
+        assert_eq!(segment("sensor-1"), "sensor%2D1");
+        assert_eq!(segment("a/+/#"), "a%2F%2B%2F%23");
+        assert_eq!(segment("zażółć"), "za%C5%BC%C3%B3%C5%82%C4%87");
+    }
+
+    #[test]
+    fn test_trailing_slash_removed() {
+        let client = ApiClient::new("http://127.0.0.1:9090/", "token").unwrap();
+
+        assert_eq!(client.url, "http://127.0.0.1:9090");
+    }
+}
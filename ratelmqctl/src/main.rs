@@ -0,0 +1,346 @@
+mod api_client;
+
+use clap::{App, AppSettings, Arg, ArgMatches};
+use serde_json::{json, Value};
+
+use api_client::{segment, ApiClient};
+use ratelmq::config::build_info::BUILD_INFO;
+
+const ARGUMENT_NAME_URL: &str = "url";
+const ARGUMENT_NAME_TOKEN: &str = "token";
+const ARGUMENT_NAME_JSON: &str = "json";
+const ARGUMENT_NAME_CLIENT_ID: &str = "client_id";
+const ARGUMENT_NAME_PERIOD: &str = "period";
+const ARGUMENT_NAME_TOPIC: &str = "topic";
+
+const COMMAND_STATUS: &str = "status";
+const COMMAND_CLIENTS: &str = "clients";
+const COMMAND_CLIENT: &str = "client";
+const COMMAND_DISCONNECT: &str = "disconnect";
+const COMMAND_SUBSCRIPTIONS: &str = "subscriptions";
+const COMMAND_RELOAD: &str = "reload";
+const COMMAND_DRAIN: &str = "drain";
+const COMMAND_TAP: &str = "tap";
+
+/// Preferred over `--token`, which is visible in the process list.
+const ENV_TOKEN: &str = "RATELMQ_API_TOKEN";
+const ENV_URL: &str = "RATELMQ_MANAGEMENT_URL";
+const DEFAULT_URL: &str = "http://127.0.0.1:9090";
+
+fn main() {
+    env_logger::init();
+
+    let version = format!("v{}({})", BUILD_INFO.version, &BUILD_INFO.commit_hash[..10]);
+
+    let arguments = build_arguments(version);
+
+    if let Err(e) = run(&arguments) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(arguments: &ArgMatches) -> Result<(), String> {
+    let url = match arguments.value_of(ARGUMENT_NAME_URL) {
+        Some(url) => url.to_string(),
+        None => std::env::var(ENV_URL).unwrap_or_else(|_| DEFAULT_URL.to_string()),
+    };
+    let token = match arguments.value_of(ARGUMENT_NAME_TOKEN) {
+        Some(token) => token.to_string(),
+        None => std::env::var(ENV_TOKEN)
+            .map_err(|_| format!("API token not set, use --token or {}", ENV_TOKEN))?,
+    };
+    let raw_json = arguments.is_present(ARGUMENT_NAME_JSON);
+
+    let client = ApiClient::new(&url, &token).map_err(|e| e.to_string())?;
+
+    let (command, command_arguments) = arguments.subcommand().unwrap();
+    let client_id = command_arguments
+        .value_of(ARGUMENT_NAME_CLIENT_ID)
+        .map(segment);
+
+    if command == COMMAND_TAP {
+        let query = match (client_id, command_arguments.value_of(ARGUMENT_NAME_TOPIC)) {
+            (Some(client_id), _) => format!("client_id={}", client_id),
+            (None, Some(topic)) => format!("topic={}", segment(topic)),
+            (None, None) => unreachable!(),
+        };
+        return client
+            .stream(&format!("tap?{}", query), |line| match raw_json {
+                true => println!("{}", line),
+                false => println!("{}", tap_record(line)),
+            })
+            .map_err(|e| e.to_string());
+    }
+
+    let response = match (command, client_id.as_deref()) {
+        (COMMAND_STATUS, _) => client.get("status"),
+        (COMMAND_CLIENTS, _) => client.get("clients"),
+        (COMMAND_CLIENT, Some(client_id)) => client.get(&format!("clients/{}", client_id)),
+        (COMMAND_DISCONNECT, Some(client_id)) => client.delete(&format!("clients/{}", client_id)),
+        (COMMAND_SUBSCRIPTIONS, Some(client_id)) => {
+            client.get(&format!("clients/{}/subscriptions", client_id))
+        }
+        (COMMAND_SUBSCRIPTIONS, None) => client.get("subscriptions"),
+        (COMMAND_RELOAD, _) => client.post("reload", json!({})),
+        (COMMAND_DRAIN, _) => {
+            let period_seconds: u64 = command_arguments
+                .value_of(ARGUMENT_NAME_PERIOD)
+                .unwrap()
+                .parse()
+                .map_err(|_| "the drain period must be a number of seconds".to_string())?;
+            client.post("drain", json!({ "period_seconds": period_seconds }))
+        }
+        _ => unreachable!("Unknown command {}", command),
+    }
+    .map_err(|e| e.to_string())?;
+
+    if raw_json {
+        if !response.is_null() {
+            println!("{}", serde_json::to_string_pretty(&response).unwrap());
+        }
+        return Ok(());
+    }
+
+    let output = match command {
+        COMMAND_STATUS | COMMAND_CLIENT => fields(&response),
+        COMMAND_CLIENTS => table(
+            &response,
+            &[
+                "client_id",
+                "ip",
+                "persistent",
+                "keep_alive_seconds",
+                "last_activity",
+                "packets_received",
+                "bytes_received",
+            ],
+        ),
+        COMMAND_SUBSCRIPTIONS if client_id.is_some() => lines(&response),
+        COMMAND_SUBSCRIPTIONS => table(&response, &["client_id", "topic"]),
+        COMMAND_DISCONNECT => "Client disconnected".to_string(),
+        COMMAND_RELOAD => "Reload requested, see the broker log for the result".to_string(),
+        COMMAND_DRAIN => "Draining, new clients are rejected".to_string(),
+        _ => unreachable!(),
+    };
+    if !output.is_empty() {
+        println!("{}", output);
+    }
+
+    Ok(())
+}
+
+/// Aligned name and value lines of a JSON object, arrays are joined with commas.
+fn fields(object: &Value) -> String {
+    let object = match object.as_object() {
+        Some(object) => object,
+        None => return String::new(),
+    };
+    let width = object.keys().map(|key| key.len()).max().unwrap_or(0);
+
+    object
+        .iter()
+        .map(|(key, value)| format!("{:width$}  {}", key, text(value), width = width))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Aligned columns of the given fields of a JSON array of objects.
+fn table(rows: &Value, columns: &[&str]) -> String {
+    let rows: Vec<Vec<String>> = rows
+        .as_array()
+        .map(|rows| {
+            rows.iter()
+                .map(|row| columns.iter().map(|column| text(&row[*column])).collect())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let header: Vec<String> = columns
+        .iter()
+        .map(|column| column.replace('_', " ").to_uppercase())
+        .collect();
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|i| {
+            rows.iter()
+                .chain(std::iter::once(&header))
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    std::iter::once(&header)
+        .chain(rows.iter())
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// One line per element of a JSON array.
+fn lines(values: &Value) -> String {
+    values
+        .as_array()
+        .map(|values| values.iter().map(text).collect::<Vec<_>>().join("\n"))
+        .unwrap_or_default()
+}
+
+/// A record of the tap as `timestamp client_id direction packet`.
+fn tap_record(line: &str) -> String {
+    let record: Value = match serde_json::from_str(line) {
+        Ok(record) => record,
+        Err(_) => return line.to_string(),
+    };
+
+    ["timestamp", "client_id", "direction", "packet"]
+        .iter()
+        .map(|field| text(&record[*field]))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        Value::Array(values) => values.iter().map(text).collect::<Vec<_>>().join(", "),
+        value => value.to_string(),
+    }
+}
+
+fn build_arguments(version: String) -> ArgMatches {
+    let client_id = || {
+        Arg::new(ARGUMENT_NAME_CLIENT_ID)
+            .value_name("CLIENT_ID")
+            .about("Client id, as sent by the client")
+    };
+
+    App::new("ratelmqctl")
+        .version(version.as_str())
+        .about("Tool for RatelMQ broker management through its management listener.")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::new(ARGUMENT_NAME_URL)
+                .long(ARGUMENT_NAME_URL)
+                .value_name("URL")
+                .about("Management listener of the broker [env: RATELMQ_MANAGEMENT_URL] [default: http://127.0.0.1:9090]")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new(ARGUMENT_NAME_TOKEN)
+                .long(ARGUMENT_NAME_TOKEN)
+                .value_name("TOKEN")
+                .about("API token of the broker, prefer the RATELMQ_API_TOKEN environment variable")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new(ARGUMENT_NAME_JSON)
+                .long(ARGUMENT_NAME_JSON)
+                .about("Print the JSON returned by the broker")
+                .global(true)
+                .takes_value(false),
+        )
+        .subcommand(App::new(COMMAND_STATUS).about("Show the broker version and statistics"))
+        .subcommand(App::new(COMMAND_CLIENTS).about("List connected clients"))
+        .subcommand(
+            App::new(COMMAND_CLIENT)
+                .about("Show a client with its subscriptions")
+                .arg(client_id().required(true)),
+        )
+        .subcommand(
+            App::new(COMMAND_DISCONNECT)
+                .about("Disconnect a client")
+                .arg(client_id().required(true)),
+        )
+        .subcommand(
+            App::new(COMMAND_SUBSCRIPTIONS)
+                .about("List subscriptions of all clients or of the given one")
+                .arg(client_id().required(false)),
+        )
+        .subcommand(
+            App::new(COMMAND_RELOAD)
+                .about("Reload password files and ACL, the same as SIGHUP"),
+        )
+        .subcommand(
+            App::new(COMMAND_DRAIN)
+                .about("Reject new clients and disconnect the connected ones, e.g. before a shutdown")
+                .arg(
+                    Arg::new(ARGUMENT_NAME_PERIOD)
+                        .long(ARGUMENT_NAME_PERIOD)
+                        .value_name("SECONDS")
+                        .about("Spread the disconnections over this period")
+                        .default_value("0")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new(COMMAND_TAP)
+                .about("Print packets of a client or a topic filter until interrupted")
+                .arg(client_id().required_unless_present(ARGUMENT_NAME_TOPIC))
+                .arg(
+                    Arg::new(ARGUMENT_NAME_TOPIC)
+                        .long(ARGUMENT_NAME_TOPIC)
+                        .value_name("TOPIC_FILTER")
+                        .about("Topic filter of PUBLISH packets, as sent by the clients")
+                        .conflicts_with(ARGUMENT_NAME_CLIENT_ID)
+                        .takes_value(true),
+                ),
+        )
+        .get_matches()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let rows = json!([
+            {"client_id": "sensor-1", "topic": "a/#", "ignored": 1},
+            {"client_id": "c2", "topic": "b"},
+        ]);
+
+        assert_eq!(
+            table(&rows, &["client_id", "topic"]),
+            "CLIENT ID  TOPIC\nsensor-1   a/#\nc2         b"
+        );
+        assert_eq!(table(&json!([]), &["topic"]), "TOPIC");
+    }
+
+    #[test]
+    fn test_fields() {
+        let object = json!({"client_id": "c1", "persistent": false, "subscriptions": ["a", "b"]});
+
+        assert_eq!(
+            fields(&object),
+            "client_id      c1\npersistent     false\nsubscriptions  a, b"
+        );
+    }
+
+    #[test]
+    fn test_tap_record() {
+        let line = r#"{"timestamp":"2021-01-01T00:00:00.000000Z","client_id":"c1","direction":"received","packet_type":"PINGREQ","packet":"PingReq"}"#;
+
+        assert_eq!(
+            tap_record(line),
+            "2021-01-01T00:00:00.000000Z c1 received PingReq"
+        );
+        assert_eq!(tap_record("not json"), "not json");
+    }
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(lines(&json!(["a/#", "b"])), "a/#\nb");
+        assert_eq!(lines(&json!([])), "");
+    }
+}
@@ -1,25 +1,47 @@
 use crate::broker::authentication::FileIdentityManager;
 use crate::broker::client_packet_handler::ClientPacketHandler;
 // use crate::broker::keepalive_checker::KeepAliveChecker;
+use crate::broker::messaging;
 use crate::broker::messaging::MessagingService;
+use crate::broker::metrics::BrokerMetrics;
+use crate::broker::reload;
 use crate::config::build_info::BUILD_INFO;
+use crate::logging;
+use crate::management::api::BrokerHandle;
+use crate::management::health::ListenerHealth;
+use crate::management::server::ManagementServer;
+use crate::mqtt::client_id_rules::ClientIdRules;
 use crate::mqtt::listener::MqttListener;
+use crate::mqtt::rate_limiter::RateLimiter;
+use crate::mqtt::tap::Taps;
 use crate::settings::Settings;
+use crate::telemetry;
 use futures::future::join_all;
 use log::{debug, info};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::signal;
 use tokio::sync::broadcast;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
 pub async fn run(config_filename: &str) {
+    let settings = Settings::new(config_filename).unwrap();
+    logging::init(&settings.log);
+    if let Some(tracing_settings) = &settings.tracing {
+        telemetry::init(tracing_settings).unwrap_or_else(|e| {
+            panic!(
+                "Unable to export spans to {}: {}",
+                &tracing_settings.otlp_endpoint, e
+            )
+        });
+    }
+
     info!(
         "Initializing RatelMQ v{} ({})...",
         BUILD_INFO.version,
         &BUILD_INFO.commit_hash[..10]
     );
-
     debug!("Using configuration file {}", config_filename);
-    let settings = Settings::new(config_filename).unwrap();
     debug!("Effective settings: {:?}", &settings);
 
     let (ctrl_c_tx, ctrl_c_rx) = broadcast::channel(5);
@@ -27,14 +49,33 @@ pub async fn run(config_filename: &str) {
     let (client_tx, client_rx) = mpsc::channel(32);
 
     // let messaging_service = Arc::new(Mutex::new(MessagingService::new()));
-    let messaging_service = MessagingService::new();
-    let (messaging_tx, mut messaging_rx) = mpsc::channel(32);
+    let metrics = Arc::new(BrokerMetrics::new());
+    let messaging_service = MessagingService::new(Arc::clone(&metrics));
+    let (messaging_tx, mut messaging_rx) = messaging::channel(32, Arc::clone(&metrics));
+
+    let (reload_tx, reload_rx) = mpsc::channel(1);
+    let (drain_tx, drain_rx) = watch::channel(None);
+    tokio::spawn(reload::reload_on_signal(
+        reload_tx.clone(),
+        ctrl_c_tx.subscribe(),
+    ));
+    if settings.reload.watch_files {
+        tokio::spawn(reload::reload_on_file_change(
+            reload::watched_files(&settings),
+            Duration::from_secs(settings.reload.watch_interval_seconds),
+            reload_tx.clone(),
+            ctrl_c_tx.subscribe(),
+        ));
+    }
 
     let manager = ClientPacketHandler::new(
         client_rx,
         ctrl_c_rx,
+        reload_rx,
+        drain_rx,
         &settings,
-        messaging_tx,
+        messaging_tx.clone(),
+        Arc::clone(&metrics),
         // Arc::clone(&messaging_service),
     );
     let manager_future = tokio::spawn(manager.run());
@@ -47,17 +88,55 @@ pub async fn run(config_filename: &str) {
 
     let mut listeners = Vec::new();
 
-    for bind_address in settings.mqtt.listeners_tcp {
+    // shared by all listeners
+    let connection_rate = settings
+        .mqtt
+        .max_connection_rate
+        .map(|rate| Arc::new(RateLimiter::new(rate)));
+    let client_id_rules = Arc::new(
+        ClientIdRules::new(&settings.mqtt.client_ids)
+            .expect("Invalid mqtt.client_ids.allowed_characters"),
+    );
+    let taps = Arc::new(Taps::default());
+    let listener_health = ListenerHealth::new(settings.mqtt.listeners.len());
+
+    for listener_settings in settings.mqtt.listeners {
         let listener = MqttListener::bind(
-            bind_address.as_str(),
+            listener_settings,
+            connection_rate.clone(),
+            Arc::clone(&client_id_rules),
+            Arc::clone(&metrics),
+            Arc::clone(&taps),
             client_tx.clone(),
             ctrl_c_tx.subscribe(),
         )
         .await
         .unwrap();
 
-        listeners.push(tokio::spawn(listener.start_accepting()));
+        // taken before spawning, so the listener counts as soon as it is bound
+        let accepting = listener_health.accepting();
+        listeners.push(tokio::spawn(async move {
+            listener.start_accepting().await;
+            drop(accepting);
+        }));
+    }
+
+    if let Some(management_settings) = &settings.management {
+        let broker = BrokerHandle {
+            metrics: Arc::clone(&metrics),
+            messaging_tx: messaging_tx.clone(),
+            client_tx: client_tx.clone(),
+            reload_tx,
+            drain_tx,
+            taps: Arc::clone(&taps),
+            listeners: listener_health,
+        };
+        let server =
+            ManagementServer::bind(management_settings, broker, ctrl_c_tx.subscribe()).unwrap();
+        listeners.push(tokio::spawn(server.run()));
     }
+    // the messaging service stops once all the senders are dropped
+    drop(messaging_tx);
 
     info!("Successfully initialized RatelMQ, ready to accept connections");
 
@@ -65,6 +144,8 @@ pub async fn run(config_filename: &str) {
 
     info!("Stopping RatelMQ...");
     ctrl_c_tx.send(()).unwrap();
+    // end the streaming tap responses so the management server can stop
+    taps.close_all();
 
     join_all(listeners).await;
 
@@ -72,5 +153,9 @@ pub async fn run(config_filename: &str) {
     // keep_alive_checker_future.await.unwrap();
     manager_future.await.unwrap();
 
+    if settings.tracing.is_some() {
+        telemetry::shutdown();
+    }
+
     info!("RatelMQ stopped");
 }
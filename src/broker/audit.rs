@@ -0,0 +1,341 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use chrono::{SecondsFormat, Utc};
+use log::{debug, error, warn};
+use prometheus::IntCounter;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+
+use crate::settings::AuditSettings;
+
+/// Who accessed the broker and its topics, topics include the mountpoints of the clients.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    Connect {
+        client_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        user_name: Option<String>,
+        remote_address: SocketAddr,
+        listener: String,
+        /// `accepted` or the reason of the rejection, e.g. `invalid_password`.
+        result: &'static str,
+    },
+    Disconnect {
+        client_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        user_name: Option<String>,
+        remote_address: SocketAddr,
+        reason: &'static str,
+    },
+    Subscribe {
+        client_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        user_name: Option<String>,
+        topic: String,
+        qos: u8,
+        granted: bool,
+    },
+    Unsubscribe {
+        client_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        user_name: Option<String>,
+        topic: String,
+    },
+    Denied {
+        client_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        user_name: Option<String>,
+        /// `publish` or `subscribe`.
+        action: &'static str,
+        topic: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct AuditRecord {
+    timestamp: String,
+    #[serde(flatten)]
+    event: AuditEvent,
+}
+
+/// Sends audit events to the writer thread, does nothing when the audit log is not configured.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    writer: Option<Writer>,
+}
+
+#[derive(Clone)]
+struct Writer {
+    tx: mpsc::Sender<AuditRecord>,
+    dropped: IntCounter,
+    /// Set while the queue is full, so the start and the end of dropping are logged once.
+    dropping: Arc<AtomicBool>,
+}
+
+impl AuditLog {
+    /// The writer stops once all the handles are dropped.
+    pub fn open(
+        settings: &AuditSettings,
+        dropped: IntCounter,
+    ) -> io::Result<(AuditLog, JoinHandle<()>)> {
+        let file = RotatingFile::open(
+            PathBuf::from(&settings.file),
+            settings.max_file_size_mb * 1024 * 1024,
+            settings.max_files,
+        )?;
+        let (tx, rx) = mpsc::channel(settings.queue_size);
+
+        // file writes block, they must not stall the runtime
+        let writer = tokio::task::spawn_blocking(move || write_records(rx, file));
+
+        let audit = AuditLog {
+            writer: Some(Writer {
+                tx,
+                dropped,
+                dropping: Arc::new(AtomicBool::new(false)),
+            }),
+        };
+        Ok((audit, writer))
+    }
+
+    /// Never waits, a slow disk must not stall the clients. The event is dropped and counted
+    /// when the writer falls behind.
+    pub fn record(&self, event: AuditEvent) {
+        if let Some(writer) = &self.writer {
+            let record = AuditRecord {
+                timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                event,
+            };
+            match writer.tx.try_send(record) {
+                Ok(()) => {
+                    if writer.dropping.load(Ordering::Relaxed)
+                        && writer.dropping.swap(false, Ordering::Relaxed)
+                    {
+                        warn!(
+                            "Audit log writer caught up, {} audit records were dropped since the start",
+                            writer.dropped.get()
+                        );
+                    }
+                }
+                Err(TrySendError::Full(_)) => {
+                    writer.dropped.inc();
+                    if !writer.dropping.swap(true, Ordering::Relaxed) {
+                        error!(
+                            "Audit log writer is behind, dropping audit records until it catches up, consider a larger audit.queue_size"
+                        );
+                    }
+                }
+                Err(TrySendError::Closed(_)) => {
+                    error!("Audit log writer stopped, the event is lost")
+                }
+            }
+        }
+    }
+}
+
+fn write_records(mut rx: mpsc::Receiver<AuditRecord>, mut file: RotatingFile) {
+    debug!("Started audit log writer");
+
+    while let Some(record) = rx.blocking_recv() {
+        let mut line = serde_json::to_vec(&record).unwrap();
+        line.push(b'\n');
+
+        if let Err(e) = file.write(&line) {
+            error!("Unable to write audit log {:?}: {}", &file.path, &e);
+        }
+    }
+
+    debug!("Stopped audit log writer");
+}
+
+/// Append only file renamed to `<path>.1` once it would grow over the maximum size, previously
+/// rotated files are shifted to `<path>.2`, `<path>.3`...
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            path,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(self.rotated(self.max_files));
+            for i in (1..self.max_files).rev() {
+                let from = self.rotated(i);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated(i + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ratelmq-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let path = temp_file("audit-rotation");
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+
+        for line in [
+            "1111\n", "2222\n", "3333\n", "4444\n", "5555\n", "6666\n", "7777\n",
+        ] {
+            file.write(line.as_bytes()).unwrap();
+        }
+
+        let read = |index: Option<usize>| {
+            let path = match index {
+                Some(index) => file.rotated(index),
+                None => path.clone(),
+            };
+            std::fs::read_to_string(path).unwrap()
+        };
+        assert_eq!(read(None), "7777\n");
+        assert_eq!(read(Some(1)), "5555\n6666\n");
+        assert_eq!(read(Some(2)), "3333\n4444\n");
+        assert!(!file.rotated(3).exists());
+
+        for index in 1..=2 {
+            std::fs::remove_file(file.rotated(index)).unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let path = temp_file("audit");
+        let settings = AuditSettings {
+            file: path.to_str().unwrap().to_string(),
+            max_file_size_mb: 1,
+            max_files: 1,
+            queue_size: 16,
+        };
+        let dropped = IntCounter::new("dropped", "dropped").unwrap();
+        let (audit, writer) = AuditLog::open(&settings, dropped.clone()).unwrap();
+
+        audit.record(AuditEvent::Connect {
+            client_id: "c1".to_string(),
+            user_name: Some("user".to_string()),
+            remote_address: "127.0.0.1:50000".parse().unwrap(),
+            listener: "0.0.0.0:1883".to_string(),
+            result: "accepted",
+        });
+        audit.record(AuditEvent::Denied {
+            client_id: "c1".to_string(),
+            user_name: None,
+            action: "publish",
+            topic: "a/b".to_string(),
+        });
+        drop(audit);
+        writer.await.unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let records: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert!(records[0]["timestamp"].is_string());
+        assert_eq!(records[0]["event"], "connect");
+        assert_eq!(records[0]["user_name"], "user");
+        assert_eq!(records[0]["remote_address"], "127.0.0.1:50000");
+        assert_eq!(records[0]["result"], "accepted");
+        assert_eq!(records[1]["event"], "denied");
+        assert_eq!(records[1]["action"], "publish");
+        assert!(records[1].get("user_name").is_none());
+        assert_eq!(dropped.get(), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_records_are_dropped_when_writer_is_behind() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let dropped = IntCounter::new("dropped", "dropped").unwrap();
+        let audit = AuditLog {
+            writer: Some(Writer {
+                tx,
+                dropped: dropped.clone(),
+                dropping: Arc::new(AtomicBool::new(false)),
+            }),
+        };
+
+        for topic in ["a", "b", "c"] {
+            audit.record(AuditEvent::Unsubscribe {
+                client_id: "c1".to_string(),
+                user_name: None,
+                topic: topic.to_string(),
+            });
+        }
+
+        assert_eq!(dropped.get(), 2);
+        let record = rx.try_recv().unwrap();
+        assert!(matches!(record.event, AuditEvent::Unsubscribe { topic, .. } if topic == "a"));
+
+        let writer = audit.writer.as_ref().unwrap();
+        assert!(writer.dropping.load(Ordering::Relaxed));
+        audit.record(AuditEvent::Unsubscribe {
+            client_id: "c1".to_string(),
+            user_name: None,
+            topic: "d".to_string(),
+        });
+        assert!(!writer.dropping.load(Ordering::Relaxed));
+        assert_eq!(dropped.get(), 2);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::broker::authentication::{
+    AuthenticationError, Credentials, Identity, IdentityProvider, SharedIdentityProvider,
+};
+use async_trait::async_trait;
+use log::trace;
+
+/// Asks the providers in order until one of them allows or denies the client.
+///
+/// A provider ignores the client by returning `AuthenticationError::UserNotFound`, e.g. when
+/// the user is not in its password file or the password is not a token, and the next provider
+/// is asked then. The client is rejected when all providers ignore it.
+pub struct ChainIdentityProvider {
+    providers: Vec<(String, SharedIdentityProvider)>,
+}
+
+impl ChainIdentityProvider {
+    pub fn new(providers: Vec<(String, SharedIdentityProvider)>) -> ChainIdentityProvider {
+        ChainIdentityProvider { providers }
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for ChainIdentityProvider {
+    async fn authenticate(
+        &self,
+        credentials: &Credentials<'_>,
+    ) -> Result<Identity, AuthenticationError> {
+        for (name, provider) in &self.providers {
+            match provider.authenticate(credentials).await {
+                Err(AuthenticationError::UserNotFound) => {
+                    trace!(
+                        "Identity provider {:?} ignored user {:?}",
+                        name,
+                        credentials.user_name
+                    );
+                }
+                result => return result,
+            }
+        }
+
+        Err(AuthenticationError::UserNotFound)
+    }
+
+    fn contains_user(&self, username: &str) -> bool {
+        self.providers
+            .iter()
+            .any(|(_, provider)| provider.contains_user(username))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Knows a single user, ignores all others.
+    struct SingleUserProvider {
+        user_name: &'static str,
+        password: &'static [u8],
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl IdentityProvider for SingleUserProvider {
+        async fn authenticate(
+            &self,
+            credentials: &Credentials<'_>,
+        ) -> Result<Identity, AuthenticationError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+
+            if credentials.user_name != self.user_name {
+                Err(AuthenticationError::UserNotFound)
+            } else if credentials.password != self.password {
+                Err(AuthenticationError::InvalidPassword)
+            } else {
+                Ok(Identity::default())
+            }
+        }
+
+        fn contains_user(&self, username: &str) -> bool {
+            username == self.user_name
+        }
+    }
+
+    fn provider(user_name: &'static str, password: &'static [u8]) -> Arc<SingleUserProvider> {
+        Arc::new(SingleUserProvider {
+            user_name,
+            password,
+            calls: AtomicUsize::new(0),
+        })
+    }
+
+    async fn authenticate(
+        chain: &ChainIdentityProvider,
+        user_name: &str,
+        password: &[u8],
+    ) -> Result<Identity, AuthenticationError> {
+        let client_id = "client-1".to_string();
+        let credentials = Credentials {
+            client_id: &client_id,
+            user_name,
+            password,
+            address: "127.0.0.1:1883".parse().unwrap(),
+        };
+
+        chain.authenticate(&credentials).await
+    }
+
+    #[tokio::test]
+    async fn test_first_provider_knowing_user_decides() {
+        let alice = provider("alice", b"secret");
+        let bob = provider("bob", b"secret");
+        let chain = ChainIdentityProvider::new(vec![
+            ("alice".to_string(), alice.clone() as SharedIdentityProvider),
+            ("bob".to_string(), bob.clone() as SharedIdentityProvider),
+        ]);
+
+        assert!(authenticate(&chain, "alice", b"secret").await.is_ok());
+        assert_eq!(bob.calls.load(Ordering::SeqCst), 0);
+
+        assert!(authenticate(&chain, "bob", b"secret").await.is_ok());
+        assert_eq!(alice.calls.load(Ordering::SeqCst), 2);
+        assert_eq!(bob.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_deny_stops_chain() {
+        let first = provider("alice", b"secret");
+        let second = provider("alice", b"other");
+        let chain = ChainIdentityProvider::new(vec![
+            ("first".to_string(), first as SharedIdentityProvider),
+            (
+                "second".to_string(),
+                second.clone() as SharedIdentityProvider,
+            ),
+        ]);
+
+        let result = authenticate(&chain, "alice", b"other").await;
+
+        assert!(matches!(result, Err(AuthenticationError::InvalidPassword)));
+        assert_eq!(second.calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_when_all_providers_ignore() {
+        let chain = ChainIdentityProvider::new(vec![(
+            "alice".to_string(),
+            provider("alice", b"secret") as SharedIdentityProvider,
+        )]);
+
+        let result = authenticate(&chain, "carol", b"secret").await;
+
+        assert!(matches!(result, Err(AuthenticationError::UserNotFound)));
+    }
+
+    #[test]
+    fn test_contains_user() {
+        let chain = ChainIdentityProvider::new(vec![
+            (
+                "alice".to_string(),
+                provider("alice", b"secret") as SharedIdentityProvider,
+            ),
+            (
+                "bob".to_string(),
+                provider("bob", b"secret") as SharedIdentityProvider,
+            ),
+        ]);
+
+        assert!(chain.contains_user("bob"));
+        assert!(!chain.contains_user("carol"));
+    }
+}
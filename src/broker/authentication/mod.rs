@@ -0,0 +1,148 @@
+pub mod chain;
+pub mod jwt;
+pub mod password;
+
+use crate::broker::authentication::AuthenticationError::{EncryptionError, InvalidPassword};
+use crate::broker::authentication::FileIdentityManagerError::InvalidEntry;
+use crate::broker::authorization::TopicPermissions;
+use crate::broker::webhook::WebhookError;
+use crate::mqtt::packets::ClientId;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::io::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use AuthenticationError::UserNotFound;
+
+use password::{HashedPassword, PasswordHashError};
+
+#[derive(Debug)]
+pub enum AuthenticationError {
+    // UserNotFound(UserNotFound),
+    // InvalidPassword(InvalidPassword),
+    UserNotFound,
+    InvalidPassword,
+    EncryptionError(PasswordHashError),
+    InvalidToken(jsonwebtoken::errors::Error),
+    /// The external identity service could not be reached.
+    Unavailable(WebhookError),
+}
+
+// #[derive(Debug, Clone)]
+// pub struct UserNotFound {
+//     // pub username: &'static str,
+// }
+//
+// #[derive(Debug, Clone)]
+// pub struct InvalidPassword;
+
+/// Authenticated client.
+#[derive(Debug, Default)]
+pub struct Identity {
+    /// The client is disconnected when its credentials expire.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Topics the client can access, used instead of the ACL when provided.
+    pub permissions: Option<TopicPermissions>,
+}
+
+/// Credentials presented by the client in the CONNECT packet.
+#[derive(Debug)]
+pub struct Credentials<'a> {
+    pub client_id: &'a ClientId,
+    pub user_name: &'a str,
+    pub password: &'a [u8],
+    pub address: SocketAddr,
+}
+
+#[async_trait]
+pub trait IdentityProvider {
+    /// Returns `AuthenticationError::UserNotFound` when the provider does not know the user,
+    /// the next provider in the chain is asked then.
+    async fn authenticate(
+        &self,
+        credentials: &Credentials<'_>,
+    ) -> Result<Identity, AuthenticationError>;
+
+    fn contains_user(&self, username: &str) -> bool;
+}
+
+pub type SharedIdentityProvider = Arc<dyn IdentityProvider + Send + Sync>;
+
+#[derive(Debug)]
+pub enum FileIdentityManagerError {
+    FileError(std::io::Error),
+    InvalidEntry,
+    InvalidPassword,
+}
+
+impl From<std::io::Error> for FileIdentityManagerError {
+    fn from(error: Error) -> Self {
+        FileIdentityManagerError::FileError(error)
+    }
+}
+
+pub struct FileIdentityManager {
+    passwords_by_username: HashMap<String, HashedPassword>,
+}
+
+impl FileIdentityManager {
+    pub fn new(filename: &str) -> Result<FileIdentityManager, FileIdentityManagerError> {
+        let credentials = std::fs::read_to_string(filename)?;
+
+        let mut passwords_by_username = HashMap::with_capacity(credentials.lines().count());
+
+        for line in credentials.lines() {
+            let credential = line.split_once(':').ok_or(InvalidEntry)?;
+            let username = credential.0;
+            let password = credential.1;
+
+            let password = password
+                .parse()
+                .map_err(|_| FileIdentityManagerError::InvalidPassword)?;
+
+            passwords_by_username.insert(username.to_owned(), password);
+        }
+
+        let manager = FileIdentityManager {
+            passwords_by_username,
+        };
+
+        Ok(manager)
+    }
+}
+
+fn verify_password(
+    password_hash: &HashedPassword,
+    password: &[u8],
+) -> Result<Identity, AuthenticationError> {
+    match password_hash.verify(password) {
+        Ok(true) => Ok(Identity::default()),
+        Ok(false) => Err(InvalidPassword),
+        Err(e) => Err(EncryptionError(e)),
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for FileIdentityManager {
+    async fn authenticate(
+        &self,
+        credentials: &Credentials<'_>,
+    ) -> Result<Identity, AuthenticationError> {
+        let password_hash = self
+            .passwords_by_username
+            .get(credentials.user_name)
+            .ok_or(UserNotFound)?
+            .clone();
+        let password = credentials.password.to_vec();
+
+        // hashing is CPU heavy by design, it must not block the async workers
+        tokio::task::spawn_blocking(move || verify_password(&password_hash, &password))
+            .await
+            .expect("Password verification panicked")
+    }
+
+    fn contains_user(&self, username: &str) -> bool {
+        self.passwords_by_username.contains_key(username)
+    }
+}
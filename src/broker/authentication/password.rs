@@ -0,0 +1,331 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::Hmac;
+use pbkdf2::Pbkdf2;
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha512};
+use std::str::FromStr;
+
+/// Length of salt generated for the mosquitto format, the same as mosquitto_passwd uses.
+const MOSQUITTO_SALT_LENGTH: usize = 12;
+const MOSQUITTO_HASH_LENGTH: usize = 64;
+
+#[derive(Debug)]
+pub enum PasswordHashError {
+    /// The hash is not in any of the supported formats.
+    UnsupportedFormat,
+    Phc(argon2::password_hash::Error),
+    Bcrypt(bcrypt::BcryptError),
+}
+
+impl From<argon2::password_hash::Error> for PasswordHashError {
+    fn from(error: argon2::password_hash::Error) -> Self {
+        PasswordHashError::Phc(error)
+    }
+}
+
+impl From<bcrypt::BcryptError> for PasswordHashError {
+    fn from(error: bcrypt::BcryptError) -> Self {
+        PasswordHashError::Bcrypt(error)
+    }
+}
+
+/// Password hash from the password file in one of the formats:
+///
+/// * PHC string of Argon2 (`$argon2id$...`) or PBKDF2 (`$pbkdf2-sha512$...`)
+/// * bcrypt (`$2b$...`, `$2a$...`, `$2y$...`)
+/// * mosquitto SHA-512 (`$6$salt$hash`) and PBKDF2-SHA512 (`$7$iterations$salt$hash`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum HashedPassword {
+    Phc(String),
+    Bcrypt(String),
+    MosquittoSha512 {
+        salt: Vec<u8>,
+        hash: Vec<u8>,
+    },
+    MosquittoPbkdf2 {
+        iterations: u32,
+        salt: Vec<u8>,
+        hash: Vec<u8>,
+    },
+}
+
+impl FromStr for HashedPassword {
+    type Err = PasswordHashError;
+
+    fn from_str(hash: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = hash.split('$').collect();
+
+        match fields.as_slice() {
+            ["", "6", salt, hash] => Ok(HashedPassword::MosquittoSha512 {
+                salt: decode_base64(salt)?,
+                hash: decode_base64(hash)?,
+            }),
+            ["", "7", iterations, salt, hash] => Ok(HashedPassword::MosquittoPbkdf2 {
+                iterations: iterations
+                    .parse()
+                    .ok()
+                    .filter(|iterations| *iterations > 0)
+                    .ok_or(PasswordHashError::UnsupportedFormat)?,
+                salt: decode_base64(salt)?,
+                hash: decode_base64(hash)?,
+            }),
+            ["", "2a" | "2b" | "2y", _, _] if hash.len() == 60 => {
+                Ok(HashedPassword::Bcrypt(hash.to_string()))
+            }
+            ["", algorithm, ..]
+                if algorithm.starts_with("argon2") || algorithm.starts_with("pbkdf2") =>
+            {
+                if PasswordHash::new(hash)?.hash.is_none() {
+                    return Err(PasswordHashError::UnsupportedFormat);
+                }
+                Ok(HashedPassword::Phc(hash.to_string()))
+            }
+            _ => Err(PasswordHashError::UnsupportedFormat),
+        }
+    }
+}
+
+impl HashedPassword {
+    /// Checks the password, the hashing is slow by design.
+    pub fn verify(&self, password: &[u8]) -> Result<bool, PasswordHashError> {
+        match self {
+            HashedPassword::Phc(hash) => {
+                let hash = PasswordHash::new(hash)?;
+
+                match hash.verify_password(&[&Argon2::default(), &Pbkdf2], password) {
+                    Ok(()) => Ok(true),
+                    Err(argon2::password_hash::Error::Password) => Ok(false),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            HashedPassword::Bcrypt(hash) => Ok(bcrypt::verify(password, hash)?),
+            HashedPassword::MosquittoSha512 { salt, hash } => {
+                let mut digest = Sha512::new();
+                digest.update(password);
+                digest.update(salt);
+
+                Ok(constant_time_eq(&digest.finalize(), hash))
+            }
+            HashedPassword::MosquittoPbkdf2 {
+                iterations,
+                salt,
+                hash,
+            } => {
+                let mut derived = vec![0u8; hash.len()];
+                pbkdf2::pbkdf2::<Hmac<Sha512>>(password, salt, *iterations, &mut derived);
+
+                Ok(constant_time_eq(&derived, hash))
+            }
+        }
+    }
+}
+
+/// Algorithm and its parameters used to hash new passwords.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HashAlgorithm {
+    Argon2 {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+    Bcrypt {
+        cost: u32,
+    },
+    Pbkdf2 {
+        rounds: u32,
+    },
+    Mosquitto {
+        iterations: u32,
+    },
+}
+
+impl HashAlgorithm {
+    pub fn argon2() -> Self {
+        HashAlgorithm::Argon2 {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+
+    pub fn bcrypt() -> Self {
+        HashAlgorithm::Bcrypt {
+            cost: bcrypt::DEFAULT_COST,
+        }
+    }
+
+    pub fn pbkdf2() -> Self {
+        HashAlgorithm::Pbkdf2 {
+            rounds: pbkdf2::Params::default().rounds,
+        }
+    }
+
+    /// The same number of iterations as mosquitto_passwd uses.
+    pub fn mosquitto() -> Self {
+        HashAlgorithm::Mosquitto { iterations: 101 }
+    }
+}
+
+/// Hashes the password with a random salt.
+pub fn hash_password(
+    password: &[u8],
+    algorithm: HashAlgorithm,
+) -> Result<String, PasswordHashError> {
+    match algorithm {
+        HashAlgorithm::Argon2 {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => {
+            let params = Params::new(memory_kib, iterations, parallelism, None)
+                .map_err(argon2::password_hash::Error::from)?;
+            let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+            let salt = SaltString::generate(&mut OsRng);
+
+            Ok(argon2.hash_password(password, &salt)?.to_string())
+        }
+        HashAlgorithm::Bcrypt { cost } => Ok(bcrypt::hash(password, cost)?),
+        HashAlgorithm::Pbkdf2 { rounds } => {
+            let salt = SaltString::generate(&mut OsRng);
+            let params = pbkdf2::Params {
+                rounds,
+                output_length: 64,
+            };
+            let hash = Pbkdf2.hash_password_customized(
+                password,
+                Some(pbkdf2::Algorithm::Pbkdf2Sha512.ident()),
+                None,
+                params,
+                &salt,
+            )?;
+
+            Ok(hash.to_string())
+        }
+        HashAlgorithm::Mosquitto { iterations } => {
+            if iterations == 0 {
+                return Err(PasswordHashError::UnsupportedFormat);
+            }
+
+            let mut salt = [0u8; MOSQUITTO_SALT_LENGTH];
+            OsRng.fill_bytes(&mut salt);
+
+            let mut hash = [0u8; MOSQUITTO_HASH_LENGTH];
+            pbkdf2::pbkdf2::<Hmac<Sha512>>(password, &salt, iterations, &mut hash);
+
+            Ok(format!(
+                "$7${}${}${}",
+                iterations,
+                STANDARD.encode(salt),
+                STANDARD.encode(hash)
+            ))
+        }
+    }
+}
+
+fn decode_base64(value: &str) -> Result<Vec<u8>, PasswordHashError> {
+    STANDARD
+        .decode(value)
+        .ok()
+        .filter(|bytes| !bytes.is_empty())
+        .ok_or(PasswordHashError::UnsupportedFormat)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (x, y)| difference | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verify(hash: &str, password: &str) -> bool {
+        hash.parse::<HashedPassword>()
+            .unwrap()
+            .verify(password.as_bytes())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_argon2() {
+        let hash = hash_password(
+            b"secret",
+            HashAlgorithm::Argon2 {
+                memory_kib: 1024,
+                iterations: 1,
+                parallelism: 1,
+            },
+        )
+        .unwrap();
+
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(verify(&hash, "secret"));
+        assert!(!verify(&hash, "wrong"));
+    }
+
+    #[test]
+    fn test_bcrypt() {
+        let hash = hash_password(b"secret", HashAlgorithm::Bcrypt { cost: 4 }).unwrap();
+
+        assert!(hash.starts_with("$2b$04$"));
+        assert!(verify(&hash, "secret"));
+        assert!(!verify(&hash, "wrong"));
+    }
+
+    #[test]
+    fn test_pbkdf2() {
+        let hash = hash_password(b"secret", HashAlgorithm::Pbkdf2 { rounds: 1000 }).unwrap();
+
+        assert!(hash.starts_with("$pbkdf2-sha512$i=1000,l=64$"));
+        assert!(verify(&hash, "secret"));
+        assert!(!verify(&hash, "wrong"));
+    }
+
+    #[test]
+    fn test_mosquitto_pbkdf2() {
+        let hash = hash_password(b"secret", HashAlgorithm::mosquitto()).unwrap();
+
+        assert!(hash.starts_with("$7$101$"));
+        assert!(verify(&hash, "secret"));
+        assert!(!verify(&hash, "wrong"));
+    }
+
+    #[test]
+    fn test_mosquitto_passwd_hashes() {
+        // the formats of mosquitto_passwd 1.6 ($6$) and 2.0 ($7$), password "secret",
+        // salt "saltsaltsalt"
+        let sha512 = "$6$c2FsdHNhbHRzYWx0$3aiBbchfIZo+1WYrkPzJjQxZ79F/NmwLQ+wF073kTd+6ANhaic8G/jWF/X4sqSFUKpLNdm1FZjBck6HQ8knM3A==";
+        let pbkdf2 = "$7$101$c2FsdHNhbHRzYWx0$Hd71hnNoKcARd6Fkl1rUE+opfs3V78ZJB2AgXXC1kaKQiWfi5S5qX7D1mY5b+bIzvyidWMnS8VA+YAsAygq9EA==";
+
+        assert!(verify(sha512, "secret"));
+        assert!(!verify(sha512, "wrong"));
+        assert!(verify(pbkdf2, "secret"));
+        assert!(!verify(pbkdf2, "wrong"));
+    }
+
+    #[test]
+    fn test_unsupported_formats() {
+        for hash in [
+            "plaintext",
+            "$1$salt$hash",
+            "$6$salt",
+            "$6$$aGFzaA==",
+            "$7$0$c2FsdA==$aGFzaA==",
+            "$7$x$c2FsdA==$aGFzaA==",
+            "$2b$10$tooshort",
+            "$argon2id$invalid",
+        ] {
+            assert!(
+                hash.parse::<HashedPassword>().is_err(),
+                "{} must be rejected",
+                hash
+            );
+        }
+    }
+}
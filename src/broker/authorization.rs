@@ -0,0 +1,415 @@
+use crate::broker::authorization::FileAuthorizerError::InvalidEntry;
+use crate::broker::mountpoint::substitute;
+use crate::mqtt::packets::ClientId;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::io::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Access to the topic requested by the client. For `Access::Read` the topic is
+/// a subscription filter, for `Access::Write` a topic name of a published message.
+#[derive(Debug)]
+pub struct AuthorizationRequest<'a> {
+    pub client_id: &'a ClientId,
+    pub user_name: Option<&'a str>,
+    pub address: SocketAddr,
+    pub topic: &'a str,
+    pub access: Access,
+}
+
+#[async_trait]
+pub trait Authorizer {
+    async fn authorize(&self, request: &AuthorizationRequest<'_>) -> bool;
+
+    /// Whether `authorize` waits for something outside of the broker, e.g. an external service.
+    /// Such authorizations do not run in the client packet handler task.
+    fn is_remote(&self) -> bool {
+        false
+    }
+}
+
+pub type SharedAuthorizer = Arc<dyn Authorizer + Send + Sync>;
+
+/// Topics granted to the client by its identity provider, e.g. in a JWT claim.
+#[derive(Debug, PartialEq, Clone, Default, Deserialize)]
+pub struct TopicPermissions {
+    #[serde(default)]
+    pub read: Vec<String>,
+    #[serde(default)]
+    pub write: Vec<String>,
+}
+
+impl TopicPermissions {
+    pub fn allows(&self, topic: &str, access: Access) -> bool {
+        let filters = match access {
+            Access::Read => &self.read,
+            Access::Write => &self.write,
+        };
+
+        filters
+            .iter()
+            .any(|filter| rule_allows(filter, topic, access))
+    }
+}
+
+/// Used when no ACL is configured, every client can access every topic.
+pub struct AllowAllAuthorizer;
+
+#[async_trait]
+impl Authorizer for AllowAllAuthorizer {
+    async fn authorize(&self, _: &AuthorizationRequest<'_>) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+pub enum FileAuthorizerError {
+    FileError(std::io::Error),
+    InvalidEntry(usize),
+}
+
+impl From<std::io::Error> for FileAuthorizerError {
+    fn from(error: Error) -> Self {
+        FileAuthorizerError::FileError(error)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Rule {
+    read: bool,
+    write: bool,
+    topic: String,
+}
+
+impl Rule {
+    fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        }
+    }
+}
+
+/// ACL file in the mosquitto format:
+///
+/// ```text
+/// # rules for anonymous clients
+/// topic read public/#
+///
+/// # rules for the given user
+/// user alice
+/// topic readwrite alice/#
+///
+/// # rules for all clients, %u is replaced with the user name and %c with the client id
+/// pattern write devices/%c/#
+/// ```
+///
+/// Access can be `read`, `write` or `readwrite` (the default when omitted).
+/// Everything which is not explicitly allowed is denied.
+pub struct FileAuthorizer {
+    anonymous_rules: Vec<Rule>,
+    user_rules: Vec<(String, Rule)>,
+    pattern_rules: Vec<Rule>,
+}
+
+impl FileAuthorizer {
+    pub fn new(filename: &str) -> Result<FileAuthorizer, FileAuthorizerError> {
+        let content = std::fs::read_to_string(filename)?;
+
+        Self::parse(&content)
+    }
+
+    fn parse(content: &str) -> Result<FileAuthorizer, FileAuthorizerError> {
+        let mut authorizer = FileAuthorizer {
+            anonymous_rules: Vec::new(),
+            user_rules: Vec::new(),
+            pattern_rules: Vec::new(),
+        };
+
+        let mut user: Option<String> = None;
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let line_number = index + 1;
+            let (keyword, arguments) = line
+                .split_once(char::is_whitespace)
+                .ok_or(InvalidEntry(line_number))?;
+            let arguments = arguments.trim();
+
+            match keyword {
+                "user" => user = Some(arguments.to_string()),
+                "topic" => {
+                    let rule = Self::parse_rule(arguments).ok_or(InvalidEntry(line_number))?;
+                    match &user {
+                        Some(user) => authorizer.user_rules.push((user.clone(), rule)),
+                        None => authorizer.anonymous_rules.push(rule),
+                    }
+                }
+                "pattern" => {
+                    let rule = Self::parse_rule(arguments).ok_or(InvalidEntry(line_number))?;
+                    authorizer.pattern_rules.push(rule);
+                }
+                _ => return Err(InvalidEntry(line_number)),
+            }
+        }
+
+        Ok(authorizer)
+    }
+
+    fn parse_rule(arguments: &str) -> Option<Rule> {
+        if matches!(arguments, "read" | "write" | "readwrite") {
+            return None;
+        }
+
+        let (read, write, topic) = match arguments.split_once(char::is_whitespace) {
+            Some(("read", topic)) => (true, false, topic),
+            Some(("write", topic)) => (false, true, topic),
+            Some(("readwrite", topic)) => (true, true, topic),
+            _ => (true, true, arguments),
+        };
+
+        let topic = topic.trim();
+        if topic.is_empty() {
+            return None;
+        }
+
+        Some(Rule {
+            read,
+            write,
+            topic: topic.to_string(),
+        })
+    }
+
+    fn check(
+        &self,
+        client_id: &ClientId,
+        user_name: Option<&str>,
+        topic: &str,
+        access: Access,
+    ) -> bool {
+        let direct_rules: Vec<&Rule> = match user_name {
+            Some(user_name) => self
+                .user_rules
+                .iter()
+                .filter(|(user, _)| user == user_name)
+                .map(|(_, rule)| rule)
+                .collect(),
+            None => self.anonymous_rules.iter().collect(),
+        };
+
+        let direct_match = direct_rules
+            .into_iter()
+            .any(|rule| rule.allows(access) && rule_allows(&rule.topic, topic, access));
+
+        direct_match || self.pattern_rules.iter().any(|rule| {
+            let rule_topic = substitute(&rule.topic, client_id, user_name);
+
+            rule.allows(access)
+                && matches!(rule_topic, Some(rule_topic) if rule_allows(&rule_topic, topic, access))
+        })
+    }
+}
+
+#[async_trait]
+impl Authorizer for FileAuthorizer {
+    async fn authorize(&self, request: &AuthorizationRequest<'_>) -> bool {
+        self.check(
+            request.client_id,
+            request.user_name,
+            request.topic,
+            request.access,
+        )
+    }
+}
+
+fn rule_allows(rule: &str, topic: &str, access: Access) -> bool {
+    match access {
+        Access::Read => filter_covers(rule, topic),
+        Access::Write => filter_covers(rule, topic) && !topic.contains(['+', '#']),
+    }
+}
+
+/// Checks if every topic matched by the `filter` is also matched by the `rule`.
+fn filter_covers(rule: &str, filter: &str) -> bool {
+    let mut rule_levels = rule.split('/');
+    let mut filter_levels = filter.split('/');
+
+    loop {
+        match (rule_levels.next(), filter_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some(_), Some("#")) => return false,
+            (Some("+"), Some(_)) => {}
+            (Some(_), Some("+")) => return false,
+            (Some(rule_level), Some(filter_level)) if rule_level == filter_level => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACL: &str = r#"
+        # anonymous clients
+        topic read public/#
+
+        user alice
+        topic readwrite alice/#
+        topic write sensors/+/temperature
+
+        user bob
+        topic bob/inbox
+
+        pattern read devices/%c/commands
+        pattern write users/%u/#
+    "#;
+
+    fn is_allowed(user_name: Option<&str>, topic: &str, access: Access) -> bool {
+        let authorizer = FileAuthorizer::parse(ACL).unwrap();
+        authorizer.check(&"device-1".to_string(), user_name, topic, access)
+    }
+
+    #[test]
+    fn test_anonymous_rules() {
+        assert!(is_allowed(None, "public/news", Access::Read));
+        assert!(is_allowed(None, "public/#", Access::Read));
+        assert!(!is_allowed(None, "public/news", Access::Write));
+        assert!(!is_allowed(None, "alice/a", Access::Read));
+        assert!(!is_allowed(Some("alice"), "public/news", Access::Read));
+    }
+
+    #[test]
+    fn test_user_rules() {
+        assert!(is_allowed(Some("alice"), "alice/a/b", Access::Read));
+        assert!(is_allowed(Some("alice"), "alice/a/b", Access::Write));
+        assert!(is_allowed(
+            Some("alice"),
+            "sensors/1/temperature",
+            Access::Write
+        ));
+        assert!(!is_allowed(
+            Some("alice"),
+            "sensors/1/temperature",
+            Access::Read
+        ));
+        assert!(!is_allowed(
+            Some("alice"),
+            "sensors/1/humidity",
+            Access::Write
+        ));
+        assert!(!is_allowed(Some("bob"), "alice/a", Access::Read));
+    }
+
+    #[test]
+    fn test_default_access_is_readwrite() {
+        assert!(is_allowed(Some("bob"), "bob/inbox", Access::Read));
+        assert!(is_allowed(Some("bob"), "bob/inbox", Access::Write));
+    }
+
+    #[test]
+    fn test_pattern_rules() {
+        assert!(is_allowed(
+            Some("bob"),
+            "devices/device-1/commands",
+            Access::Read
+        ));
+        assert!(is_allowed(None, "devices/device-1/commands", Access::Read));
+        assert!(!is_allowed(None, "devices/device-2/commands", Access::Read));
+        assert!(is_allowed(Some("bob"), "users/bob/status", Access::Write));
+        assert!(!is_allowed(
+            Some("bob"),
+            "users/alice/status",
+            Access::Write
+        ));
+        assert!(!is_allowed(None, "users/%u/status", Access::Write));
+    }
+
+    #[test]
+    fn test_pattern_substitution_rejects_wildcards() {
+        let authorizer = FileAuthorizer::parse("pattern read users/%u/#").unwrap();
+
+        assert!(!authorizer.check(&"c1".to_string(), Some("+"), "users/x/a", Access::Read));
+        assert!(!authorizer.check(&"c1".to_string(), Some("#"), "users/#", Access::Read));
+    }
+
+    #[test]
+    fn test_pattern_substitution_is_not_repeated() {
+        let authorizer = FileAuthorizer::parse("pattern write users/%u/#").unwrap();
+
+        assert!(!authorizer.check(
+            &"alice".to_string(),
+            Some("%c"),
+            "users/alice/x",
+            Access::Write
+        ));
+    }
+
+    #[test]
+    fn test_subscription_must_be_covered_by_rule() {
+        assert!(is_allowed(Some("alice"), "alice/+/b", Access::Read));
+        assert!(!is_allowed(Some("alice"), "#", Access::Read));
+        assert!(!is_allowed(Some("alice"), "+/a", Access::Read));
+        assert!(!is_allowed(None, "devices/+/commands", Access::Read));
+    }
+
+    #[test]
+    fn test_publish_to_wildcard_topic_is_denied() {
+        assert!(!is_allowed(Some("alice"), "alice/#", Access::Write));
+    }
+
+    #[test]
+    fn test_invalid_entries() {
+        assert!(matches!(
+            FileAuthorizer::parse("user alice\ntopic"),
+            Err(InvalidEntry(2))
+        ));
+        assert!(matches!(
+            FileAuthorizer::parse("topic read"),
+            Err(InvalidEntry(1))
+        ));
+        assert!(matches!(
+            FileAuthorizer::parse("deny a/b"),
+            Err(InvalidEntry(1))
+        ));
+    }
+
+    #[test]
+    fn test_topic_permissions() {
+        let permissions = TopicPermissions {
+            read: vec!["a/#".to_string()],
+            write: vec!["b/+".to_string()],
+        };
+
+        assert!(permissions.allows("a/b", Access::Read));
+        assert!(!permissions.allows("a/b", Access::Write));
+        assert!(permissions.allows("b/c", Access::Write));
+        assert!(!permissions.allows("b/#", Access::Write));
+        assert!(!TopicPermissions::default().allows("a", Access::Read));
+    }
+
+    #[test]
+    fn test_filter_covers() {
+        assert!(filter_covers("a/b", "a/b"));
+        assert!(!filter_covers("a/b", "a/b/c"));
+        assert!(!filter_covers("a/b/c", "a/b"));
+        assert!(filter_covers("a/+", "a/b"));
+        assert!(filter_covers("a/+", "a/+"));
+        assert!(!filter_covers("a/+", "a/#"));
+        assert!(filter_covers("a/#", "a"));
+        assert!(filter_covers("a/#", "a/#"));
+        assert!(filter_covers("#", "a/b/c"));
+    }
+}
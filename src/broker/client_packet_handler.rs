@@ -1,56 +1,266 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use chrono::Utc;
+use bytes::BytesMut;
+
+use chrono::{DateTime, Utc};
 use log::{debug, error, info, trace};
 use tokio::select;
 use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, Interval};
+use tracing::Instrument;
 use uuid::Uuid;
 
-use crate::broker::authentication::{FileIdentityManager, IdentityProvider};
-use crate::broker::messaging::{MessagingOperation, MessagingService, MessagingTx};
-use crate::broker::session::Session;
+use crate::broker::audit::{AuditEvent, AuditLog};
+use crate::broker::authentication::chain::ChainIdentityProvider;
+use crate::broker::authentication::jwt::JwtIdentityProvider;
+use crate::broker::authentication::{
+    AuthenticationError, Credentials, FileIdentityManager, Identity, SharedIdentityProvider,
+};
+use crate::broker::authorization::{
+    Access, AllowAllAuthorizer, AuthorizationRequest, FileAuthorizer, SharedAuthorizer,
+    TopicPermissions,
+};
+use crate::broker::login_guard::LoginGuard;
+use crate::broker::messaging::{self, MessagingOperation, MessagingService, MessagingTx};
+use crate::broker::metrics::BrokerMetrics;
+use crate::broker::mountpoint::Mountpoint;
+use crate::broker::reload::ReloadError;
+use crate::broker::session::{ClientStatistics, Session};
+use crate::broker::sys_topics;
+use crate::broker::webhook::{WebhookAuthorizer, WebhookIdentityProvider};
 use crate::mqtt::events::{ClientEvent, ServerEvent};
 use crate::mqtt::packets::connack::ConnAckReturnCode;
-use crate::mqtt::packets::suback::SubAckPacket;
+use crate::mqtt::packets::suback::{SubAckPacket, SubAckReturnCode};
 use crate::mqtt::packets::subscribe::SubscribePacket;
 use crate::mqtt::packets::unsuback::UnSubAckPacket;
 use crate::mqtt::packets::unsubscribe::UnsubscribePacket;
 use crate::mqtt::packets::ControlPacket::{ConnAck, PingResp, Publish, SubAck, UnsubAck};
 use crate::mqtt::packets::*;
-use crate::settings::Settings;
+use crate::mqtt::subscription::Subscription;
+use crate::settings::{
+    AuthenticationSettings, AuthorizationSettings, IdentityProviderSettings, ListenerSettings,
+    Settings,
+};
+
+struct ClientConnection {
+    address: SocketAddr,
+    user_name: Option<String>,
+    identity_provider: Option<String>,
+    permissions: Option<TopicPermissions>,
+    mountpoint: Mountpoint,
+    sender: Sender<ServerEvent>,
+    statistics: Arc<ClientStatistics>,
+    expires_at: Option<DateTime<Utc>>,
+    expiry_timer: Option<JoinHandle<()>>,
+    /// Why the broker closes the connection, for the audit log.
+    disconnect_reason: Option<&'static str>,
+    /// Set while a packet waits for its authorization, the packets received in the meantime are
+    /// handled in order once it is authorized.
+    authorizing: Option<Vec<ControlPacket>>,
+}
+
+/// Client waiting for the result of its authentication. Packets it sends in the meantime
+/// are handled once the connection is accepted.
+struct PendingConnection {
+    sender: Sender<ServerEvent>,
+    statistics: Arc<ClientStatistics>,
+    packets: Vec<ControlPacket>,
+}
+
+/// Connection the broker closes from outside of the handler task, e.g. once the credentials of
+/// the client expire.
+struct CloseRequest {
+    client_id: ClientId,
+    /// Tells the connection apart from a later one with the same client id.
+    sender: Sender<ServerEvent>,
+    reason: &'static str,
+}
+
+/// Result of the authentication running outside of the handler task.
+struct Authentication {
+    packet: ConnectPacket,
+    address: SocketAddr,
+    listener: Arc<ListenerSettings>,
+    sender: Sender<ServerEvent>,
+    result: Result<Identity, AuthenticationError>,
+}
+
+/// Result of the authorization of a packet running outside of the handler task.
+struct Authorization {
+    client_id: ClientId,
+    sender: Sender<ServerEvent>,
+    packet: ControlPacket,
+    /// One result per topic of the packet.
+    allowed: Vec<bool>,
+}
+
+impl Drop for ClientConnection {
+    fn drop(&mut self) {
+        if let Some(expiry_timer) = &self.expiry_timer {
+            expiry_timer.abort();
+        }
+    }
+}
 
 pub struct ClientPacketHandler {
     rx: mpsc::Receiver<ClientEvent>,
     ctrl_c_rx: broadcast::Receiver<()>,
+    reload_rx: mpsc::Receiver<()>,
+    /// Period to disconnect the clients over once the broker is draining.
+    drain_rx: watch::Receiver<Option<Duration>>,
     // sessions: SessionService<InMemorySessionRepository>,
     // messaging: MessagingServiceSync,
     messaging_tx: MessagingTx,
-    identity_provider: Box<dyn IdentityProvider + Send + Sync>,
+    authentication_settings: AuthenticationSettings,
+    authorization_settings: AuthorizationSettings,
+    identity_provider: SharedIdentityProvider,
+    identity_providers: HashMap<String, SharedIdentityProvider>,
+    authorizer: SharedAuthorizer,
+    authentication_tx: Sender<Authentication>,
+    authentication_rx: Receiver<Authentication>,
+    authorization_tx: Sender<Authorization>,
+    authorization_rx: Receiver<Authorization>,
+    close_tx: Sender<CloseRequest>,
+    close_rx: Receiver<CloseRequest>,
+    pending_connections: HashMap<ClientId, PendingConnection>,
+    login_guard: LoginGuard,
+    connections: HashMap<ClientId, ClientConnection>,
+    metrics: Arc<BrokerMetrics>,
+    audit: AuditLog,
+    audit_writer: Option<JoinHandle<()>>,
+    sys_interval: Option<Interval>,
 }
 
 impl ClientPacketHandler {
     pub fn new(
         rx: Receiver<ClientEvent>,
         ctrl_c_rx: broadcast::Receiver<()>,
+        reload_rx: Receiver<()>,
+        drain_rx: watch::Receiver<Option<Duration>>,
         settings: &Settings,
         // messaging: MessagingServiceSync,
         messaging_tx: MessagingTx,
+        metrics: Arc<BrokerMetrics>,
     ) -> ClientPacketHandler {
-        let identity_provider = Box::new(
-            FileIdentityManager::new(settings.authentication.password_file.as_str()).unwrap(),
-        );
+        let (identity_provider, identity_providers) =
+            Self::load_identity_providers(&settings.authentication).unwrap();
+
+        let authorizer = Self::load_authorizer(&settings.authorization).unwrap();
+        let (authentication_tx, authentication_rx) = mpsc::channel(32);
+        let (authorization_tx, authorization_rx) = mpsc::channel(32);
+        let (close_tx, close_rx) = mpsc::channel(32);
+        let (audit, audit_writer) = match &settings.audit {
+            Some(audit_settings) => {
+                let (audit, writer) =
+                    AuditLog::open(audit_settings, metrics.audit_records_dropped.clone())
+                        .unwrap_or_else(|e| {
+                            panic!("Unable to open audit log {:?}: {}", &audit_settings.file, e)
+                        });
+                (audit, Some(writer))
+            }
+            None => (AuditLog::default(), None),
+        };
+        let sys_interval = match settings.mqtt.sys_interval_seconds {
+            0 => None,
+            seconds => Some(interval(Duration::from_secs(seconds))),
+        };
 
         ClientPacketHandler {
             rx,
             ctrl_c_rx,
+            reload_rx,
+            drain_rx,
             // sessions: SessionService::default(),
             // messaging,
             messaging_tx,
+            authentication_settings: settings.authentication.clone(),
+            authorization_settings: settings.authorization.clone(),
             identity_provider,
+            identity_providers,
+            authorizer,
+            authentication_tx,
+            authentication_rx,
+            authorization_tx,
+            authorization_rx,
+            close_tx,
+            close_rx,
+            pending_connections: HashMap::new(),
+            login_guard: LoginGuard::new(settings.authentication.failed_logins.clone()),
+            connections: HashMap::new(),
+            metrics,
+            audit,
+            audit_writer,
+            sys_interval,
         }
     }
 
+    fn load_identity_providers(
+        settings: &AuthenticationSettings,
+    ) -> Result<
+        (
+            SharedIdentityProvider,
+            HashMap<String, SharedIdentityProvider>,
+        ),
+        ReloadError,
+    > {
+        let identity_provider =
+            Arc::new(FileIdentityManager::new(settings.password_file.as_str())?);
+
+        let mut identity_providers: HashMap<String, SharedIdentityProvider> = HashMap::new();
+        for (name, provider_settings) in &settings.identity_providers {
+            let provider: SharedIdentityProvider = match provider_settings {
+                IdentityProviderSettings::File { password_file } => {
+                    Arc::new(FileIdentityManager::new(password_file.as_str())?)
+                }
+                IdentityProviderSettings::Jwt(jwt) => Arc::new(JwtIdentityProvider::new(jwt)?),
+                IdentityProviderSettings::Webhook(webhook) => {
+                    Arc::new(WebhookIdentityProvider::new(webhook)?)
+                }
+                // chains refer to the other providers, they are created once all of them exist
+                IdentityProviderSettings::Chain { .. } => continue,
+            };
+            identity_providers.insert(name.clone(), provider);
+        }
+
+        // chains of chains are rejected, the members are looked up among the other providers only
+        let mut chains = Vec::new();
+        for (name, provider_settings) in &settings.identity_providers {
+            if let IdentityProviderSettings::Chain { providers } = provider_settings {
+                let providers = providers
+                    .iter()
+                    .map(|provider| match identity_providers.get(provider) {
+                        Some(identity_provider) => {
+                            Ok((provider.clone(), identity_provider.clone()))
+                        }
+                        None => Err(ReloadError::InvalidChain(name.clone())),
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                let chain: SharedIdentityProvider = Arc::new(ChainIdentityProvider::new(providers));
+                chains.push((name.clone(), chain));
+            }
+        }
+        identity_providers.extend(chains);
+
+        Ok((identity_provider, identity_providers))
+    }
+
+    fn load_authorizer(settings: &AuthorizationSettings) -> Result<SharedAuthorizer, ReloadError> {
+        let authorizer: SharedAuthorizer = match (&settings.acl_file, &settings.webhook) {
+            (Some(acl_file), None) => Arc::new(FileAuthorizer::new(acl_file.as_str())?),
+            (None, Some(webhook)) => Arc::new(WebhookAuthorizer::new(webhook)?),
+            (None, None) => Arc::new(AllowAllAuthorizer),
+            (Some(_), Some(_)) => return Err(ReloadError::ConflictingAuthorizers),
+        };
+
+        Ok(authorizer)
+    }
+
     pub async fn run(mut self) {
         loop {
             select! {
@@ -64,23 +274,65 @@ impl ClientPacketHandler {
 
                         match event {
 
-                            ClientEvent::Connected(c, address, tx) => self.on_connect(tx, c, address).await,
-                            ClientEvent::ControlPacket(client_id, packet, tx) => {
-                                self.on_packet(client_id, packet, tx).await;
+                            ClientEvent::Connected(c, address, listener, tx, statistics) => {
+                                self.on_connect(tx, statistics, c, address, listener).await;
+                            }
+                            ClientEvent::ControlPacket(client_id, packet, tx, span) => {
+                                self.on_packet(client_id, packet, tx).instrument(span).await;
                             }
                             ClientEvent::Disconnected(_client_id) => {}
-                            ClientEvent::ConnectionLost(client_id) => {
-                                self.on_connection_lost(client_id).await;
+                            ClientEvent::ConnectionLost(client_id, tx) => {
+                                self.on_connection_lost(client_id, tx).await;
+                            }
+                            ClientEvent::HealthCheck(resp) => {
+                                let _ = resp.send(());
+                            }
+                            ClientEvent::Kick(client_id, resp) => {
+                                let _ = resp.send(self.close(&client_id, "kicked").await);
                             }
                         }
                     }
                  }
+                 Some(authentication) = self.authentication_rx.recv() => {
+                    self.on_authentication(authentication).await;
+                 }
+                 Some(authorization) = self.authorization_rx.recv() => {
+                    self.on_authorization(authorization).await;
+                 }
+                 Some(request) = self.close_rx.recv() => {
+                    self.on_close_request(request).await;
+                 }
+                 Some(_) = self.reload_rx.recv() => {
+                    self.on_reload().await;
+                 }
+                 Ok(()) = self.drain_rx.changed() => {
+                    self.on_drain();
+                 }
+                 _ = Self::tick(&mut self.sys_interval) => {
+                    self.on_sys_tick().await;
+                 }
             }
         }
 
+        // the writer stops once the audit log is dropped with the handler
+        if let Some(audit_writer) = self.audit_writer.take() {
+            drop(self);
+            audit_writer.await.unwrap();
+        }
+
         debug!("Stopped Manager");
     }
 
+    /// Waits for the next tick, forever when the interval is disabled.
+    async fn tick(interval: &mut Option<Interval>) {
+        match interval {
+            Some(interval) => {
+                interval.tick().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
     async fn on_packet(
         &mut self,
         client_id: String,
@@ -89,96 +341,515 @@ impl ClientPacketHandler {
     ) {
         trace!("Got packet {:?}", packet);
 
+        if let Some(pending) = self.pending_connections.get_mut(&client_id) {
+            pending.packets.push(packet);
+            return;
+        }
+
+        // the broker might have closed the connection while the packet was queued
+        let is_connected = matches!(self.connections.get(&client_id), Some(connection) if connection.sender.same_channel(&tx));
+        if !is_connected {
+            debug!(
+                client_id = client_id.as_str(), packet_type = packet.name();
+                "Ignoring packet of client {:?}, its connection is closed",
+                &client_id
+            );
+            return;
+        }
+
+        let connection = self.connections.get_mut(&client_id).unwrap();
+        if let Some(packets) = &mut connection.authorizing {
+            packets.push(packet);
+            return;
+        }
+        if let Some((topics, access)) = self.remote_authorization(&client_id, &packet) {
+            self.authorize_remotely(client_id, packet, tx, topics, access);
+            return;
+        }
+
         match packet {
             // ControlPacket::Connect(c) => {
             //     self.on_connect(action.response, c, &mut sessions).await
             // }
             // ControlPacket::ConnAck(_) => {}
-            ControlPacket::Publish(p) => self.on_publish(tx, p, client_id).await,
+            ControlPacket::Publish(p) => self.on_publish(tx, p, client_id, None).await,
             // ControlPacket::PubAck(_) => {}
             // ControlPacket::PubRec(_) => {}
             // ControlPacket::PubRel(_) => {}
             // ControlPacket::PubComp(_) => {}
-            ControlPacket::Subscribe(p) => self.on_subscribe(tx, p, &client_id).await,
+            ControlPacket::Subscribe(p) => self.on_subscribe(tx, p, &client_id, None).await,
             // ControlPacket::SubAck(_) => {}
             ControlPacket::Unsubscribe(p) => self.on_unsubscribe(tx, p, &client_id).await,
             // ControlPacket::UnsubAck(_) => {}
             ControlPacket::PingReq => self.on_ping_req(tx, &client_id).await,
             // ControlPacket::PingResp() => {}
-            ControlPacket::Disconnect(_) => self.on_disconnect(client_id).await,
-            _ => error!("Packet {} not supported", &packet),
+            ControlPacket::Disconnect(_) => self.on_disconnect(client_id, tx).await,
+            _ => {
+                error!(client_id = client_id.as_str(), packet_type = packet.name(); "Packet {} not supported", &packet)
+            }
         };
     }
 
     async fn on_connect(
         &mut self,
         sender: Sender<ServerEvent>,
+        statistics: Arc<ClientStatistics>,
         packet: ConnectPacket,
         address: SocketAddr,
+        listener: Arc<ListenerSettings>,
     ) {
-        let client_id = packet.client_id;
-        debug!("New client {:?} connected", &client_id);
+        let client_id = packet.client_id.clone();
+        let user_name = packet.user_name.as_deref();
+        debug!(
+            client_id = client_id.as_str(), user_name = user_name, remote_address:% = address, listener = listener.address.as_str(), packet_type = "CONNECT";
+            "New client {:?} connected from {} to listener {}",
+            &client_id, &address, &listener.address
+        );
 
-        if let Some(user_name) = packet.user_name {
-            let password = packet.password.unwrap();
-            if let Err(e) = self.identity_provider.authenticate(&user_name, &password) {
-                info!("Client {} authentication error: {:?}", &user_name, &e);
+        if self.drain_rx.borrow().is_some() {
+            info!(
+                client_id = client_id.as_str(), user_name = user_name, remote_address:% = address, listener = listener.address.as_str(), packet_type = "CONNECT";
+                "Client {:?} rejected, the broker is draining",
+                &client_id
+            );
+            self.audit_connect(&client_id, user_name, address, &listener, "draining");
+            Self::reject(&sender, ConnAckReturnCode::ServerUnavailable).await;
+            return;
+        }
 
-                let conn_ack = ConnAckPacket::new(false, ConnAckReturnCode::NotAuthorized);
-                sender
-                    .send(ServerEvent::ControlPacket(ConnAck(conn_ack)))
-                    .await
-                    .unwrap();
-                sender.send(ServerEvent::Disconnect).await.unwrap();
+        if !listener.protocol_versions.contains(&packet.version) {
+            info!(
+                client_id = client_id.as_str(), user_name = user_name, remote_address:% = address, listener = listener.address.as_str(), packet_type = "CONNECT";
+                "Client {:?} uses protocol version {:?} not allowed on listener {}",
+                &client_id, &packet.version, &listener.address
+            );
+            self.audit_connect(
+                &client_id,
+                user_name,
+                address,
+                &listener,
+                "unacceptable_protocol_version",
+            );
+            Self::reject(&sender, ConnAckReturnCode::UnacceptableProtocolVersion).await;
+            return;
+        }
+
+        if let Some(user_name) = &packet.user_name {
+            let password = match &packet.password {
+                Some(password) => password.clone(),
+                None => {
+                    info!(
+                        client_id = client_id.as_str(), user_name = user_name.as_str(), remote_address:% = address, listener = listener.address.as_str(), packet_type = "CONNECT";
+                        "Client {:?} rejected, user {:?} did not provide password",
+                        &client_id, &user_name
+                    );
+                    self.metrics
+                        .authentication_failures
+                        .with_label_values(&["missing_password"])
+                        .inc();
+                    self.audit_connect(
+                        &client_id,
+                        Some(user_name),
+                        address,
+                        &listener,
+                        "missing_password",
+                    );
+
+                    Self::reject(&sender, ConnAckReturnCode::BadUserNameOrPassword).await;
+                    return;
+                }
+            };
+            if let Some(ban) = self
+                .login_guard
+                .banned(address.ip(), user_name, Instant::now())
+            {
+                info!(
+                    client_id = client_id.as_str(), user_name = user_name.as_str(), remote_address:% = address, listener = listener.address.as_str(), packet_type = "CONNECT";
+                    "Client {:?} rejected, too many failed logins from {} or as user {:?}, banned for {:?}", &client_id, address.ip(), &user_name, &ban
+                );
+                self.metrics
+                    .authentication_failures
+                    .with_label_values(&["banned"])
+                    .inc();
+                self.audit_connect(&client_id, Some(user_name), address, &listener, "banned");
+
+                Self::reject(&sender, ConnAckReturnCode::NotAuthorized).await;
                 return;
             }
+            self.login_guard
+                .on_attempt(address.ip(), user_name, Instant::now());
+
+            let identity_provider = match &listener.identity_provider {
+                Some(name) => self.identity_providers[name].clone(),
+                None => self.identity_provider.clone(),
+            };
+
+            let pending = PendingConnection {
+                sender: sender.clone(),
+                statistics,
+                packets: Vec::new(),
+            };
+            self.pending_connections.insert(client_id.clone(), pending);
+
+            // hashing or a request to an external service must not stall the other clients
+            let user_name = user_name.clone();
+            let authentication_tx = self.authentication_tx.clone();
+            tokio::spawn(async move {
+                let credentials = Credentials {
+                    client_id: &client_id,
+                    user_name: &user_name,
+                    password: &password,
+                    address,
+                };
+                let result = identity_provider.authenticate(&credentials).await;
+
+                let authentication = Authentication {
+                    packet,
+                    address,
+                    listener,
+                    sender,
+                    result,
+                };
+                if authentication_tx.send(authentication).await.is_err() {
+                    debug!(
+                        "Authentication of client {:?} finished after shutdown",
+                        &client_id
+                    );
+                }
+            });
+        } else if listener.allow_anonymous != Some(true) {
+            info!(
+                client_id = client_id.as_str(), remote_address:% = address, listener = listener.address.as_str(), packet_type = "CONNECT";
+                "Client {:?} rejected, anonymous access is not allowed on listener {}",
+                &client_id, &listener.address
+            );
+            self.metrics
+                .authentication_failures
+                .with_label_values(&["anonymous"])
+                .inc();
+            self.audit_connect(&client_id, None, address, &listener, "anonymous");
+
+            Self::reject(&sender, ConnAckReturnCode::NotAuthorized).await;
+        } else {
+            self.accept(
+                sender,
+                statistics,
+                packet,
+                address,
+                listener,
+                Identity::default(),
+            )
+            .await;
+        }
+    }
+
+    async fn on_authentication(&mut self, authentication: Authentication) {
+        let Authentication {
+            packet,
+            address,
+            listener,
+            sender,
+            result,
+        } = authentication;
+        let client_id = packet.client_id.clone();
+        let user_name = packet.user_name.as_deref().unwrap_or_default();
+
+        // the attempt counts as failed until now, also when the client is gone
+        match &result {
+            Ok(_) => self.login_guard.on_success(address.ip(), user_name),
+            // an unavailable service is not the client's fault
+            Err(AuthenticationError::Unavailable(_)) => {
+                self.login_guard.on_unavailable(address.ip(), user_name)
+            }
+            Err(_) => {}
+        }
+
+        // the client might have disconnected or connected again with the same client id in the meantime
+        let is_pending = matches!(self.pending_connections.get(&client_id), Some(pending) if pending.sender.same_channel(&sender));
+        if !is_pending {
+            debug!(
+                "Client {:?} is gone, ignoring its authentication",
+                &client_id
+            );
+            return;
+        }
+        let pending = self.pending_connections.remove(&client_id).unwrap();
+
+        let identity = match result {
+            Ok(identity) => identity,
+            Err(e) => {
+                info!(
+                    client_id = client_id.as_str(), user_name = user_name, remote_address:% = address, listener = listener.address.as_str(), packet_type = "CONNECT";
+                    "Client {:?} authentication error: {:?}", &client_id, &e
+                );
+
+                let (return_code, reason) = match e {
+                    AuthenticationError::UserNotFound => {
+                        (ConnAckReturnCode::BadUserNameOrPassword, "user_not_found")
+                    }
+                    AuthenticationError::InvalidPassword => {
+                        (ConnAckReturnCode::BadUserNameOrPassword, "invalid_password")
+                    }
+                    AuthenticationError::InvalidToken(_) => {
+                        (ConnAckReturnCode::BadUserNameOrPassword, "invalid_token")
+                    }
+                    AuthenticationError::EncryptionError(_) => {
+                        (ConnAckReturnCode::NotAuthorized, "error")
+                    }
+                    AuthenticationError::Unavailable(_) => {
+                        (ConnAckReturnCode::ServerUnavailable, "unavailable")
+                    }
+                };
+                self.metrics
+                    .authentication_failures
+                    .with_label_values(&[reason])
+                    .inc();
+                self.audit_connect(&client_id, Some(user_name), address, &listener, reason);
+                Self::reject(&sender, return_code).await;
+                return;
+            }
+        };
+
+        if self
+            .accept(
+                sender.clone(),
+                pending.statistics,
+                packet,
+                address,
+                listener,
+                identity,
+            )
+            .await
+        {
+            for packet in pending.packets {
+                self.on_packet(client_id.clone(), packet, sender.clone())
+                    .await;
+            }
+        }
+    }
+
+    /// Registers the authenticated client, returns false when it was rejected.
+    async fn accept(
+        &mut self,
+        sender: Sender<ServerEvent>,
+        statistics: Arc<ClientStatistics>,
+        packet: ConnectPacket,
+        address: SocketAddr,
+        listener: Arc<ListenerSettings>,
+        identity: Identity,
+    ) -> bool {
+        let client_id = packet.client_id;
+
+        let mountpoint = match &listener.mountpoint {
+            Some(pattern) => {
+                match Mountpoint::new(pattern, &client_id, packet.user_name.as_deref()) {
+                    Some(mountpoint) => mountpoint,
+                    None => {
+                        info!(
+                            client_id = client_id.as_str(), user_name = packet.user_name.as_deref(), remote_address:% = address, listener = listener.address.as_str(), packet_type = "CONNECT";
+                            "Client {:?} rejected, cannot apply mountpoint {:?}",
+                            &client_id, pattern
+                        );
+                        self.audit_connect(
+                            &client_id,
+                            packet.user_name.as_deref(),
+                            address,
+                            &listener,
+                            "mountpoint",
+                        );
+
+                        Self::reject(&sender, ConnAckReturnCode::NotAuthorized).await;
+                        return false;
+                    }
+                }
+            }
+            None => Mountpoint::default(),
+        };
+        let expiry_timer = identity
+            .expires_at
+            .map(|expires_at| Self::close_at(expires_at, &client_id, &sender, &self.close_tx));
+        let connection = ClientConnection {
+            address,
+            user_name: packet.user_name.clone(),
+            identity_provider: listener.identity_provider.clone(),
+            permissions: identity.permissions,
+            mountpoint: mountpoint.clone(),
+            sender: sender.clone(),
+            statistics: Arc::clone(&statistics),
+            expires_at: identity.expires_at,
+            expiry_timer,
+            disconnect_reason: None,
+            authorizing: None,
         };
+        // the previous connection of the client is closed - MQTT-3.1.4-2
+        if let Some(previous) = self.connections.insert(client_id.clone(), connection) {
+            self.take_over(&client_id, previous);
+        }
+        self.update_connected_clients();
+        self.audit_connect(
+            &client_id,
+            packet.user_name.as_deref(),
+            address,
+            &listener,
+            "accepted",
+        );
 
         let session_present = {
+            // a session of a previous connection is replaced, the subscriptions are kept unless
+            // the client starts a clean session - MQTT-3.1.2-6
+            let session = Session::new(
+                client_id,
+                address.ip(),
+                !packet.clean_session,
+                sender.clone(),
+                packet.keep_alive_seconds,
+                mountpoint,
+                statistics,
+            );
             let (tx, rx) = oneshot::channel();
-            let op = MessagingOperation::SessionGet { client_id: client_id.clone(), resp: tx };
-
+            let op = MessagingOperation::SessionReplace {
+                session,
+                clean_session: packet.clean_session,
+                resp: tx,
+            };
             self.messaging_tx.send(op).await.unwrap();
-            let maybe_session = rx.await.unwrap();
+            let replaced = rx.await.unwrap();
 
-            if let Some(session) = maybe_session {
-                // session.set_last_activity(Utc::now());
-                true
-            } else {
-                let session = Session::new(
-                    client_id,
-                    address.ip(),
-                    !packet.clean_session,
-                    sender.clone(),
-                    packet.keep_alive_seconds,
-                    Utc::now(),
-                );
-                let (insert_tx, insert_rx) = oneshot::channel();
-                let op = MessagingOperation::SessionInsert { session, resp: insert_tx };
-                self.messaging_tx.send(op).await.unwrap();
-                let insert_result = insert_rx.await.unwrap();
-                false
-            }
+            // MQTT-3.2.2-1
+            replaced && !packet.clean_session
         };
 
         let conn_ack = ConnAckPacket::new(session_present, ConnAckReturnCode::Accepted);
 
-        sender
-            .send(ServerEvent::ControlPacket(ConnAck(conn_ack)))
-            .await
-            .unwrap();
+        Self::send(&sender, ServerEvent::ControlPacket(ConnAck(conn_ack))).await;
 
         // debug!(
         //     "Active sessions count: {:?}",
         //     self.messaging.session_count()
         // );
+
+        true
+    }
+
+    async fn reject(sender: &Sender<ServerEvent>, return_code: ConnAckReturnCode) {
+        let conn_ack = ConnAckPacket::new(false, return_code);
+        Self::send(sender, ServerEvent::ControlPacket(ConnAck(conn_ack))).await;
+        Self::send(sender, ServerEvent::Disconnect).await;
+    }
+
+    /// Reads the password files and the ACL again. The new configuration is used only if
+    /// all of them are valid, otherwise the current one is kept.
+    async fn on_reload(&mut self) {
+        info!("Reloading identity providers and ACL...");
+
+        let identity_providers = Self::load_identity_providers(&self.authentication_settings);
+        let authorizer = Self::load_authorizer(&self.authorization_settings);
+
+        match (identity_providers, authorizer) {
+            (Ok((identity_provider, identity_providers)), Ok(authorizer)) => {
+                self.identity_provider = identity_provider;
+                self.identity_providers = identity_providers;
+                self.authorizer = authorizer;
+                info!("Reloaded identity providers and ACL");
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Reload failed, keeping the current configuration: {:?}", &e);
+                return;
+            }
+        }
+
+        if self.authentication_settings.disconnect_removed_users {
+            self.disconnect_removed_users().await;
+        }
+    }
+
+    /// Disconnects the clients evenly over the drain period, so they do not reconnect to the other
+    /// brokers all at once. New clients are rejected from now on.
+    fn on_drain(&mut self) {
+        let period = match *self.drain_rx.borrow() {
+            Some(period) => period,
+            None => return,
+        };
+
+        // the handler closes each connection once its request is received, new ones are refused
+        let requests: Vec<CloseRequest> = self
+            .connections
+            .iter()
+            .map(|(client_id, connection)| CloseRequest {
+                client_id: client_id.clone(),
+                sender: connection.sender.clone(),
+                reason: "draining",
+            })
+            .collect();
+        info!(
+            "Draining, disconnecting {} clients over {:?}",
+            requests.len(),
+            &period
+        );
+
+        let pause = period / requests.len().max(1) as u32;
+        let close_tx = self.close_tx.clone();
+        tokio::spawn(async move {
+            for request in requests {
+                if close_tx.send(request).await.is_err() {
+                    return;
+                }
+                sleep(pause).await;
+            }
+            info!("Drained, all clients disconnected");
+        });
     }
 
-    async fn on_disconnect(&mut self, client_id: ClientId) {
-        debug!("Client {:?} disconnected", &client_id);
+    async fn disconnect_removed_users(&mut self) {
+        let mut removed = Vec::new();
+        for (client_id, connection) in &self.connections {
+            if let Some(user_name) = &connection.user_name {
+                let identity_provider = match &connection.identity_provider {
+                    Some(name) => &self.identity_providers[name],
+                    None => &self.identity_provider,
+                };
+
+                if !identity_provider.contains_user(user_name) {
+                    info!(
+                        client_id = client_id.as_str(), user_name = user_name.as_str(), remote_address:% = connection.address;
+                        "Disconnecting client {:?}, user {:?} has been removed",
+                        client_id, user_name
+                    );
+                    removed.push(client_id.clone());
+                }
+            }
+        }
+
+        for client_id in removed {
+            self.close(&client_id, "user_removed").await;
+        }
+    }
+
+    async fn on_disconnect(&mut self, client_id: ClientId, sender: Sender<ServerEvent>) {
+        if !self.is_current(&client_id, &sender) {
+            return;
+        }
+
+        if let Some(connection) = self.connections.remove(&client_id) {
+            debug!(
+                client_id = client_id.as_str(), user_name = connection.user_name.as_deref(), remote_address:% = connection.address, packet_type = "DISCONNECT";
+                "Client {:?} disconnected",
+                &client_id
+            );
+            self.audit.record(AuditEvent::Disconnect {
+                client_id: client_id.clone(),
+                user_name: connection.user_name.clone(),
+                remote_address: connection.address,
+                reason: "disconnect",
+            });
+        }
+        self.update_connected_clients();
 
         let (tx, rx) = oneshot::channel();
-        let op = MessagingOperation::ConnectionDisconnected { client_id, resp: tx };
+        let op = MessagingOperation::ConnectionDisconnected {
+            client_id,
+            resp: tx,
+        };
 
         self.messaging_tx.send(op).await.unwrap();
         let maybe_session = rx.await.unwrap();
@@ -189,11 +860,52 @@ impl ClientPacketHandler {
         // );
     }
 
-    async fn on_connection_lost(&mut self, client_id: ClientId) {
-        info!("Client {:?} disconnected unexpectedly", &client_id);
+    async fn on_connection_lost(&mut self, client_id: ClientId, sender: Sender<ServerEvent>) {
+        if !self.is_current(&client_id, &sender) {
+            debug!(
+                client_id = client_id.as_str();
+                "Ignoring lost connection of client {:?}, it has connected again",
+                &client_id
+            );
+            return;
+        }
+
+        let is_pending = matches!(self.pending_connections.get(&client_id), Some(pending) if pending.sender.same_channel(&sender));
+        if is_pending {
+            self.pending_connections.remove(&client_id);
+        }
+        match self.connections.remove(&client_id) {
+            Some(connection) => {
+                let reason = match connection.disconnect_reason {
+                    Some(reason) => reason,
+                    None if matches!(connection.expires_at, Some(expires_at) if expires_at <= Utc::now()) => {
+                        "credentials_expired"
+                    }
+                    None => "connection_lost",
+                };
+                info!(
+                    client_id = client_id.as_str(), user_name = connection.user_name.as_deref(), remote_address:% = connection.address;
+                    "Client {:?} disconnected unexpectedly: {}",
+                    &client_id, reason
+                );
+                self.audit.record(AuditEvent::Disconnect {
+                    client_id: client_id.clone(),
+                    user_name: connection.user_name.clone(),
+                    remote_address: connection.address,
+                    reason,
+                });
+            }
+            None => {
+                info!(client_id = client_id.as_str(); "Client {:?} disconnected unexpectedly", &client_id)
+            }
+        }
+        self.update_connected_clients();
 
         let (tx, rx) = oneshot::channel();
-        let op = MessagingOperation::ConnectionLost { client_id, resp: tx };
+        let op = MessagingOperation::ConnectionLost {
+            client_id,
+            resp: tx,
+        };
 
         self.messaging_tx.send(op).await.unwrap();
         let maybe_session = rx.await.unwrap();
@@ -204,56 +916,161 @@ impl ClientPacketHandler {
         // );
     }
 
+    /// `authorized` is the result of a remote authorization, the authorizer is asked otherwise.
+    #[tracing::instrument(skip_all, fields(topic = publish.message.topic.as_str()))]
     async fn on_publish(
         &self,
         _sender: Sender<ServerEvent>,
         publish: PublishPacket,
         client_id: ClientId,
+        authorized: Option<bool>,
     ) {
+        self.metrics.messages_received.inc();
+        self.metrics
+            .publish_bytes_received
+            .inc_by(publish.message.payload.len() as u64);
+
+        // the $SYS tree is published by the broker only
+        if sys_topics::is_sys_topic(&publish.message.topic) {
+            info!(
+                client_id = client_id.as_str(), user_name = self.user_name(&client_id), packet_type = "PUBLISH";
+                "Client {:?} is not allowed to publish on reserved topic {:?}",
+                &client_id, &publish.message.topic
+            );
+            self.audit_denied(&client_id, "publish", &publish.message.topic);
+            self.count_dropped(&client_id);
+            return;
+        }
+
+        let authorized = match authorized {
+            Some(authorized) => authorized,
+            None => {
+                self.is_authorized(&client_id, &publish.message.topic, Access::Write)
+                    .await
+            }
+        };
+        if !authorized {
+            info!(
+                client_id = client_id.as_str(), user_name = self.user_name(&client_id), packet_type = "PUBLISH";
+                "Client {:?} is not authorized to publish on topic {:?}",
+                &client_id, &publish.message.topic
+            );
+            self.audit_denied(&client_id, "publish", &publish.message.topic);
+            self.count_dropped(&client_id);
+            return;
+        }
+
+        let mut publish = publish;
+        publish.message.topic = self.mount(&client_id, &publish.message.topic);
         debug!(
+            client_id = client_id.as_str(), user_name = self.user_name(&client_id), packet_type = "PUBLISH";
             "Client {:?} published message on topic {:?}",
             &client_id, &publish.message.topic
         );
 
-        let (tx, rx) = oneshot::channel();
-        let op = MessagingOperation::SendersToPublish { topic: publish.message.topic.clone(), resp: tx };
-
-        self.messaging_tx.send(op).await.unwrap();
-        let senders_to_publish = rx.await.unwrap();
+        messaging::deliver(&self.messaging_tx, &self.metrics, publish).await;
+    }
 
-        for sender in senders_to_publish {
-            let event = ServerEvent::ControlPacket(Publish(publish.clone()));
-            sender.send(event).await.unwrap();
+    /// Retained, so new subscribers get the values without waiting for the next tick.
+    async fn on_sys_tick(&self) {
+        for (topic, payload) in sys_topics::sys_messages(&self.metrics) {
+            let publish = PublishPacket::new(
+                topic.to_string(),
+                BytesMut::from(payload.as_bytes()),
+                QoS::AtMostOnce,
+                true,
+                None,
+                false,
+            );
+            messaging::deliver_internal(&self.messaging_tx, publish).await;
         }
     }
 
+    fn update_connected_clients(&self) {
+        self.metrics
+            .clients_connected
+            .set(self.connections.len() as i64);
+    }
+
+    /// `authorized` holds the results of a remote authorization, one per subscription.
     async fn on_subscribe(
         &mut self,
         sender: Sender<ServerEvent>,
         subscribe: SubscribePacket,
         client_id: &ClientId,
+        authorized: Option<Vec<bool>>,
     ) {
-        debug!("Client {:?} subscribed to topics {:?}", client_id, &subscribe.subscriptions);
+        debug!(
+            client_id = client_id.as_str(), user_name = self.user_name(client_id), packet_type = "SUBSCRIBE";
+            "Client {:?} subscribed to topics {:?}",
+            client_id, &subscribe.subscriptions
+        );
 
         let mut return_codes = Vec::new();
+        let mut retained = Vec::new();
 
-        for subscription in subscribe.subscriptions {
+        for (i, subscription) in subscribe.subscriptions.into_iter().enumerate() {
             // each subscription request must be handled as a separate subscribe packet
 
+            let is_authorized = match &authorized {
+                Some(authorized) => authorized[i],
+                None => {
+                    self.is_authorized(client_id, subscription.topic(), Access::Read)
+                        .await
+                }
+            };
+            if !is_authorized {
+                info!(
+                    client_id = client_id.as_str(), user_name = self.user_name(client_id), packet_type = "SUBSCRIBE";
+                    "Client {:?} is not authorized to subscribe to {:?}",
+                    client_id,
+                    subscription.topic()
+                );
+                self.audit_denied(client_id, "subscribe", subscription.topic());
+                return_codes.push(SubAckReturnCode::Failure);
+                continue;
+            }
+
+            let topic = self.mount(client_id, subscription.topic());
+            let qos = subscription.qos();
+            let subscription = Subscription::new(topic.clone(), qos);
+
             let (tx, rx) = oneshot::channel();
-            let op = MessagingOperation::Subscribe { client_id: client_id.clone(), subscription, resp: tx };
+            let op = MessagingOperation::Subscribe {
+                client_id: client_id.clone(),
+                subscription,
+                resp: tx,
+            };
 
             self.messaging_tx.send(op).await.unwrap();
             let return_code = rx.await.unwrap();
 
+            self.audit.record(AuditEvent::Subscribe {
+                client_id: client_id.clone(),
+                user_name: self.user_name(client_id).map(str::to_string),
+                topic: topic.clone(),
+                qos: qos as u8,
+                granted: return_code != SubAckReturnCode::Failure,
+            });
+
+            if return_code != SubAckReturnCode::Failure {
+                let (tx, rx) = oneshot::channel();
+                let op = MessagingOperation::RetainedMatching {
+                    topic_filter: topic,
+                    resp: tx,
+                };
+
+                self.messaging_tx.send(op).await.unwrap();
+                retained.extend(rx.await.unwrap());
+            }
+
             return_codes.push(return_code);
         }
 
         let sub_ack = SubAckPacket::new(subscribe.packet_id, return_codes);
-        sender
-            .send(ServerEvent::ControlPacket(SubAck(sub_ack)))
-            .await
-            .unwrap();
+        Self::send(&sender, ServerEvent::ControlPacket(SubAck(sub_ack))).await;
+
+        self.send_retained(&sender, client_id, retained).await;
     }
 
     async fn on_unsubscribe(
@@ -262,19 +1079,345 @@ impl ClientPacketHandler {
         unsubscribe: UnsubscribePacket,
         client_id: &ClientId,
     ) {
-        debug!("Client {:?} unsubscribed from topics {:?}", client_id, &unsubscribe.topics);
+        debug!(
+            client_id = client_id.as_str(), user_name = self.user_name(client_id), packet_type = "UNSUBSCRIBE";
+            "Client {:?} unsubscribed from topics {:?}",
+            client_id, &unsubscribe.topics
+        );
+
+        let topics: Vec<String> = unsubscribe
+            .topics
+            .iter()
+            .map(|topic| self.mount(client_id, topic))
+            .collect();
+        for topic in &topics {
+            self.audit.record(AuditEvent::Unsubscribe {
+                client_id: client_id.clone(),
+                user_name: self.user_name(client_id).map(str::to_string),
+                topic: topic.clone(),
+            });
+        }
 
         let (tx, rx) = oneshot::channel();
-        let op = MessagingOperation::Unsubscribe { client_id: client_id.clone(), topics: unsubscribe.topics, resp: tx };
+        let op = MessagingOperation::Unsubscribe {
+            client_id: client_id.clone(),
+            topics,
+            resp: tx,
+        };
 
         self.messaging_tx.send(op).await.unwrap();
         let _ = rx.await.unwrap();
 
         let unsub_ack = UnSubAckPacket::new(unsubscribe.packet_id);
-        sender
-            .send(ServerEvent::ControlPacket(UnsubAck(unsub_ack)))
-            .await
-            .unwrap();
+        Self::send(&sender, ServerEvent::ControlPacket(UnsubAck(unsub_ack))).await;
+    }
+
+    /// Sends the retained messages matching new subscriptions, their topics are mounted.
+    async fn send_retained(
+        &self,
+        sender: &Sender<ServerEvent>,
+        client_id: &ClientId,
+        retained: Vec<PublishPacket>,
+    ) {
+        let mountpoint = match self.connections.get(client_id) {
+            Some(connection) => &connection.mountpoint,
+            None => return,
+        };
+
+        for mut publish in retained {
+            let topic = match mountpoint.unmount(&publish.message.topic) {
+                Some(topic) => topic.to_string(),
+                None => continue,
+            };
+            publish.message.topic = topic;
+            let payload_size = publish.message.payload.len() as u64;
+
+            if Self::send(sender, ServerEvent::ControlPacket(Publish(publish))).await {
+                self.metrics.messages_sent.inc();
+                self.metrics.publish_bytes_sent.inc_by(payload_size);
+            }
+        }
+    }
+
+    /// Sends the event to the connection, which might be already gone when the client
+    /// disconnected in the meantime. Returns false in that case.
+    async fn send(sender: &Sender<ServerEvent>, event: ServerEvent) -> bool {
+        match sender.send(event).await {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("Unable to send event to closed connection: {:?}", &e.0);
+                false
+            }
+        }
+    }
+
+    /// Checks the access to the topic as seen by the client, i.e. before mounting.
+    async fn is_authorized(&self, client_id: &ClientId, topic: &str, access: Access) -> bool {
+        let connection = match self.connections.get(client_id) {
+            Some(connection) => connection,
+            None => return false,
+        };
+
+        if let Some(permissions) = &connection.permissions {
+            return permissions.allows(topic, access);
+        }
+
+        let request = AuthorizationRequest {
+            client_id,
+            user_name: connection.user_name.as_deref(),
+            address: connection.address,
+            topic,
+            access,
+        };
+        self.authorizer.authorize(&request).await
+    }
+
+    /// Topics of the packet to be checked by a remote authorizer, none when the packet can be
+    /// handled right away.
+    fn remote_authorization(
+        &self,
+        client_id: &ClientId,
+        packet: &ControlPacket,
+    ) -> Option<(Vec<String>, Access)> {
+        let connection = self.connections.get(client_id)?;
+        if !self.authorizer.is_remote() || connection.permissions.is_some() {
+            return None;
+        }
+
+        match packet {
+            // publishing on the $SYS tree is denied without asking
+            ControlPacket::Publish(p) if !sys_topics::is_sys_topic(&p.message.topic) => {
+                Some((vec![p.message.topic.clone()], Access::Write))
+            }
+            ControlPacket::Subscribe(p) => {
+                let topics = p.subscriptions.iter().map(|s| s.topic().to_string());
+                Some((topics.collect(), Access::Read))
+            }
+            _ => None,
+        }
+    }
+
+    /// Holds the packets of the client until the packet is authorized.
+    fn authorize_remotely(
+        &mut self,
+        client_id: ClientId,
+        packet: ControlPacket,
+        sender: Sender<ServerEvent>,
+        topics: Vec<String>,
+        access: Access,
+    ) {
+        let connection = self.connections.get_mut(&client_id).unwrap();
+        connection.authorizing = Some(Vec::new());
+        let user_name = connection.user_name.clone();
+        let address = connection.address;
+
+        // a request to an external service must not stall the other clients
+        let authorizer = Arc::clone(&self.authorizer);
+        let authorization_tx = self.authorization_tx.clone();
+        tokio::spawn(async move {
+            let mut allowed = Vec::new();
+            for topic in &topics {
+                let request = AuthorizationRequest {
+                    client_id: &client_id,
+                    user_name: user_name.as_deref(),
+                    address,
+                    topic,
+                    access,
+                };
+                allowed.push(authorizer.authorize(&request).await);
+            }
+
+            let authorization = Authorization {
+                client_id,
+                sender,
+                packet,
+                allowed,
+            };
+            if let Err(e) = authorization_tx.send(authorization).await {
+                debug!(
+                    "Authorization of client {:?} finished after shutdown",
+                    &e.0.client_id
+                );
+            }
+        });
+    }
+
+    async fn on_authorization(&mut self, authorization: Authorization) {
+        let Authorization {
+            client_id,
+            sender,
+            packet,
+            allowed,
+        } = authorization;
+
+        // the connection might have been closed in the meantime
+        let packets = match self.connections.get_mut(&client_id) {
+            Some(connection) if connection.sender.same_channel(&sender) => {
+                connection.authorizing.take().unwrap_or_default()
+            }
+            _ => {
+                debug!(
+                    "Client {:?} is gone, ignoring its authorization",
+                    &client_id
+                );
+                return;
+            }
+        };
+
+        match packet {
+            ControlPacket::Publish(p) => {
+                self.on_publish(sender.clone(), p, client_id.clone(), Some(allowed[0]))
+                    .await
+            }
+            ControlPacket::Subscribe(p) => {
+                self.on_subscribe(sender.clone(), p, &client_id, Some(allowed))
+                    .await
+            }
+            _ => {}
+        }
+
+        for packet in packets {
+            self.on_packet(client_id.clone(), packet, sender.clone())
+                .await;
+        }
+    }
+
+    /// Closes the connection when the credentials of the client expire.
+    fn close_at(
+        expires_at: DateTime<Utc>,
+        client_id: &ClientId,
+        sender: &Sender<ServerEvent>,
+        close_tx: &Sender<CloseRequest>,
+    ) -> JoinHandle<()> {
+        let request = CloseRequest {
+            client_id: client_id.clone(),
+            sender: sender.clone(),
+            reason: "credentials_expired",
+        };
+        let close_tx = close_tx.clone();
+        let expires_in = (expires_at - Utc::now()).to_std().unwrap_or_default();
+
+        tokio::spawn(async move {
+            sleep(expires_in).await;
+
+            info!(
+                client_id = request.client_id.as_str();
+                "Disconnecting client {:?}, its credentials expired",
+                &request.client_id
+            );
+            let _ = close_tx.send(request).await;
+        })
+    }
+
+    async fn on_close_request(&mut self, request: CloseRequest) {
+        let is_connected = matches!(self.connections.get(&request.client_id), Some(connection) if connection.sender.same_channel(&request.sender));
+        if is_connected {
+            self.close(&request.client_id, request.reason).await;
+        }
+    }
+
+    /// Closes the connection on the broker's side, returns false when the client is not connected.
+    /// The connection is removed right away, so the packets the client sent in the meantime are
+    /// ignored. Ending the write loop of the connection ends its read loop, closing the socket.
+    async fn close(&mut self, client_id: &ClientId, reason: &'static str) -> bool {
+        let sender = match self.connections.get_mut(client_id) {
+            Some(connection) => {
+                connection.disconnect_reason = Some(reason);
+                connection.sender.clone()
+            }
+            None => return false,
+        };
+
+        // a busy connection must not stall the other clients
+        let disconnect_sender = sender.clone();
+        tokio::spawn(async move {
+            Self::send(&disconnect_sender, ServerEvent::Disconnect).await;
+        });
+        self.on_connection_lost(client_id.clone(), sender).await;
+
+        true
+    }
+
+    /// Closes the previous connection of a client which connected again. Its session is passed
+    /// on to the new connection, the late events of the previous connection are ignored.
+    fn take_over(&self, client_id: &ClientId, previous: ClientConnection) {
+        info!(
+            client_id = client_id.as_str(), user_name = previous.user_name.as_deref(), remote_address:% = previous.address;
+            "Client {:?} connected again, closing its previous connection",
+            client_id
+        );
+        self.audit.record(AuditEvent::Disconnect {
+            client_id: client_id.clone(),
+            user_name: previous.user_name.clone(),
+            remote_address: previous.address,
+            reason: "taken_over",
+        });
+
+        let sender = previous.sender.clone();
+        tokio::spawn(async move {
+            Self::send(&sender, ServerEvent::Disconnect).await;
+        });
+    }
+
+    fn audit_connect(
+        &self,
+        client_id: &str,
+        user_name: Option<&str>,
+        address: SocketAddr,
+        listener: &ListenerSettings,
+        result: &'static str,
+    ) {
+        self.audit.record(AuditEvent::Connect {
+            client_id: client_id.to_string(),
+            user_name: user_name.map(str::to_string),
+            remote_address: address,
+            listener: listener.address.clone(),
+            result,
+        });
+    }
+
+    /// The topic is mounted, as the client would access it.
+    fn audit_denied(&self, client_id: &ClientId, action: &'static str, topic: &str) {
+        self.audit.record(AuditEvent::Denied {
+            client_id: client_id.clone(),
+            user_name: self.user_name(client_id).map(str::to_string),
+            action,
+            topic: self.mount(client_id, topic),
+        });
+    }
+
+    /// Whether the events of the connection are about the current connection of the client,
+    /// the events of a connection replaced by a newer one are late.
+    fn is_current(&self, client_id: &ClientId, sender: &Sender<ServerEvent>) -> bool {
+        match (
+            self.connections.get(client_id),
+            self.pending_connections.get(client_id),
+        ) {
+            (Some(connection), _) => connection.sender.same_channel(sender),
+            (None, Some(pending)) => pending.sender.same_channel(sender),
+            (None, None) => true,
+        }
+    }
+
+    /// User name of a connected client, for the log fields.
+    fn user_name(&self, client_id: &ClientId) -> Option<&str> {
+        self.connections
+            .get(client_id)
+            .and_then(|connection| connection.user_name.as_deref())
+    }
+
+    fn count_dropped(&self, client_id: &ClientId) {
+        self.metrics.messages_dropped.inc();
+        if let Some(connection) = self.connections.get(client_id) {
+            connection.statistics.add_dropped();
+        }
+    }
+
+    fn mount(&self, client_id: &ClientId, topic: &str) -> String {
+        match self.connections.get(client_id) {
+            Some(connection) => connection.mountpoint.mount(topic),
+            None => topic.to_string(),
+        }
     }
 
     async fn on_ping_req(&mut self, sender: Sender<ServerEvent>, client_id: &ClientId) {
@@ -296,23 +1439,224 @@ impl ClientPacketHandler {
 
         let session_present = {
             let (tx, rx) = oneshot::channel();
-            let op = MessagingOperation::SessionExists { client_id: client_id.clone(), resp: tx };
+            let op = MessagingOperation::SessionExists {
+                client_id: client_id.clone(),
+                resp: tx,
+            };
 
             self.messaging_tx.send(op).await.unwrap();
             rx.await.unwrap()
         };
 
         if session_present {
-            sender
-                .send(ServerEvent::ControlPacket(PingResp))
-                .await
-                .unwrap();
+            Self::send(&sender, ServerEvent::ControlPacket(PingResp)).await;
         } else {
             error!(
+                client_id = client_id.as_str(), packet_type = "PINGREQ";
                 "Received PING from not existing session with client id {}",
                 client_id
             );
-            sender.send(ServerEvent::Disconnect).await.unwrap();
+            Self::send(&sender, ServerEvent::Disconnect).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broker::messaging::MessagingService;
+    use crate::mqtt::packets::subscribe::SubscribePacket;
+
+    struct Broker {
+        client_tx: Sender<ClientEvent>,
+        messaging_tx: MessagingTx,
+        listener: Arc<ListenerSettings>,
+        _ctrl_c_tx: broadcast::Sender<()>,
+        _reload_tx: Sender<()>,
+        _drain_tx: watch::Sender<Option<Duration>>,
+    }
+
+    impl Broker {
+        fn start() -> Broker {
+            let settings = Settings::from_toml(
+                r#"
+                [mqtt]
+                sys_interval_seconds = 0
+
+                [[mqtt.listeners]]
+                address = "127.0.0.1:1883"
+
+                [authentication]
+                password_file = "config/passwd"
+                "#,
+            );
+            let metrics = Arc::new(BrokerMetrics::new());
+            let (messaging_tx, messaging_rx) = messaging::channel(32, Arc::clone(&metrics));
+            tokio::spawn(MessagingService::new(Arc::clone(&metrics)).run(messaging_rx));
+
+            let (client_tx, client_rx) = mpsc::channel(32);
+            let (ctrl_c_tx, ctrl_c_rx) = broadcast::channel(1);
+            let (reload_tx, reload_rx) = mpsc::channel(1);
+            let (drain_tx, drain_rx) = watch::channel(None);
+            let handler = ClientPacketHandler::new(
+                client_rx,
+                ctrl_c_rx,
+                reload_rx,
+                drain_rx,
+                &settings,
+                messaging_tx.clone(),
+                metrics,
+            );
+            tokio::spawn(handler.run());
+
+            Broker {
+                client_tx,
+                messaging_tx,
+                listener: Arc::new(settings.mqtt.listeners[0].clone()),
+                _ctrl_c_tx: ctrl_c_tx,
+                _reload_tx: reload_tx,
+                _drain_tx: drain_tx,
+            }
+        }
+
+        /// Returns the connection and the session present flag of its CONNACK.
+        async fn connect(
+            &self,
+            clean_session: bool,
+        ) -> (Sender<ServerEvent>, Receiver<ServerEvent>, bool) {
+            let (tx, mut rx) = mpsc::channel(32);
+            let packet = ConnectPacket::new(
+                ProtocolVersion::Mqtt3,
+                "c1".to_string(),
+                60,
+                clean_session,
+                None,
+                None,
+                None,
+            );
+            let event = ClientEvent::Connected(
+                packet,
+                "127.0.0.1:50000".parse().unwrap(),
+                Arc::clone(&self.listener),
+                tx.clone(),
+                Arc::new(ClientStatistics::new()),
+            );
+            self.client_tx.send(event).await.unwrap();
+
+            match rx.recv().await {
+                Some(ServerEvent::ControlPacket(ConnAck(conn_ack))) => {
+                    (tx, rx, conn_ack.session_present)
+                }
+                other => panic!("Expected CONNACK, got {:?}", other),
+            }
+        }
+
+        async fn subscribe(&self, tx: &Sender<ServerEvent>, rx: &mut Receiver<ServerEvent>) {
+            let subscription = Subscription::new("a/b".to_string(), QoS::AtMostOnce);
+            let packet = ControlPacket::Subscribe(SubscribePacket::new(1, vec![subscription]));
+            let event = ClientEvent::ControlPacket(
+                "c1".to_string(),
+                packet,
+                tx.clone(),
+                tracing::Span::none(),
+            );
+            self.client_tx.send(event).await.unwrap();
+
+            assert!(matches!(
+                rx.recv().await,
+                Some(ServerEvent::ControlPacket(SubAck(_)))
+            ));
+        }
+
+        async fn subscriptions(&self) -> Option<Vec<String>> {
+            // the handler is done with the events sent before once it answers
+            let (tx, rx) = oneshot::channel();
+            self.client_tx
+                .send(ClientEvent::HealthCheck(tx))
+                .await
+                .unwrap();
+            rx.await.unwrap();
+
+            let (tx, rx) = oneshot::channel();
+            let op = MessagingOperation::ClientSubscriptions {
+                client_id: "c1".to_string(),
+                resp: tx,
+            };
+            self.messaging_tx.send(op).await.unwrap();
+            rx.await.unwrap()
         }
     }
+
+    #[tokio::test]
+    async fn test_late_connection_lost_of_previous_connection_is_ignored() {
+        let broker = Broker::start();
+        let (old_tx, mut old_rx, _) = broker.connect(false).await;
+        broker.subscribe(&old_tx, &mut old_rx).await;
+
+        let (_tx, _rx, _) = broker.connect(false).await;
+        assert!(
+            matches!(old_rx.recv().await, Some(ServerEvent::Disconnect)),
+            "previous connection is closed - MQTT-3.1.4-2"
+        );
+
+        let event = ClientEvent::ConnectionLost("c1".to_string(), old_tx);
+        broker.client_tx.send(event).await.unwrap();
+
+        assert_eq!(broker.subscriptions().await, Some(vec!["a/b".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_persistent_session_is_kept_on_reconnect() {
+        let broker = Broker::start();
+        let (tx, mut rx, session_present) = broker.connect(false).await;
+        assert!(!session_present);
+        broker.subscribe(&tx, &mut rx).await;
+
+        let (_tx, _rx, session_present) = broker.connect(false).await;
+
+        assert!(session_present);
+        assert_eq!(broker.subscriptions().await, Some(vec!["a/b".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_clean_session_discards_subscriptions_on_reconnect() {
+        let broker = Broker::start();
+        let (tx, mut rx, _) = broker.connect(false).await;
+        broker.subscribe(&tx, &mut rx).await;
+
+        let (_tx, _rx, session_present) = broker.connect(true).await;
+
+        // MQTT-3.1.2-6, MQTT-3.2.2-1
+        assert!(!session_present);
+        assert_eq!(broker.subscriptions().await, Some(vec![]));
+    }
+
+    #[test]
+    fn test_chain_of_chains_is_rejected() {
+        let settings = Settings::from_toml(
+            r#"
+            [[mqtt.listeners]]
+            address = "127.0.0.1:1883"
+
+            [authentication]
+            password_file = "config/passwd"
+
+            [authentication.identity_providers.local]
+            type = "file"
+            password_file = "config/passwd"
+
+            [authentication.identity_providers.inner]
+            type = "chain"
+            providers = ["local"]
+
+            [authentication.identity_providers.outer]
+            type = "chain"
+            providers = ["inner", "local"]
+            "#,
+        );
+
+        let result = ClientPacketHandler::load_identity_providers(&settings.authentication);
+
+        assert!(matches!(result, Err(ReloadError::InvalidChain(name)) if name == "outer"));
+    }
 }
@@ -0,0 +1,312 @@
+use crate::settings::FailedLoginSettings;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Forgotten failures are purged when a map grows beyond this size.
+const PURGE_SIZE: usize = 10_000;
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last_failure: Instant,
+    banned_until: Option<Instant>,
+}
+
+/// Tracks failed logins per IP and per user name. After `max_attempts` failures the IP or
+/// the user name is banned, every further failure doubles the ban up to `max_ban_seconds`.
+///
+/// A login counts as failed from its start until it succeeds, so parallel logins waiting for
+/// their authentication are counted too.
+pub struct LoginGuard {
+    settings: FailedLoginSettings,
+    ips: HashMap<IpAddr, Failures>,
+    user_names: HashMap<String, Failures>,
+}
+
+impl LoginGuard {
+    pub fn new(settings: FailedLoginSettings) -> LoginGuard {
+        LoginGuard {
+            settings,
+            ips: HashMap::new(),
+            user_names: HashMap::new(),
+        }
+    }
+
+    /// Returns the remaining ban of the IP or the user name, whichever is longer.
+    pub fn banned(&self, ip: IpAddr, user_name: &str, now: Instant) -> Option<Duration> {
+        let ip_ban = self.ips.get(&ip).and_then(|f| f.remaining_ban(now));
+        let user_name_ban = self
+            .user_names
+            .get(user_name)
+            .and_then(|f| f.remaining_ban(now));
+
+        ip_ban.max(user_name_ban)
+    }
+
+    pub fn on_attempt(&mut self, ip: IpAddr, user_name: &str, now: Instant) {
+        if self.settings.by_ip {
+            Self::record(&mut self.ips, &self.settings, ip, now);
+        }
+        if self.settings.by_user_name {
+            Self::record(
+                &mut self.user_names,
+                &self.settings,
+                user_name.to_string(),
+                now,
+            );
+        }
+    }
+
+    /// The failures of the IP are kept, otherwise logging in with an own account would reset them.
+    pub fn on_success(&mut self, ip: IpAddr, user_name: &str) {
+        Self::forgive(&mut self.ips, &self.settings, &ip);
+        self.user_names.remove(user_name);
+    }
+
+    /// The login could not be checked, e.g. the identity provider is unavailable, which is not
+    /// the client's fault.
+    pub fn on_unavailable(&mut self, ip: IpAddr, user_name: &str) {
+        Self::forgive(&mut self.ips, &self.settings, &ip);
+        Self::forgive(&mut self.user_names, &self.settings, user_name);
+    }
+
+    /// Takes back the failure counted when the login started.
+    fn forgive<K, Q>(failures: &mut HashMap<K, Failures>, settings: &FailedLoginSettings, key: &Q)
+    where
+        K: Eq + Hash + Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if let Some(entry) = failures.get_mut(key) {
+            entry.count = entry.count.saturating_sub(1);
+            if entry.count <= settings.max_attempts {
+                entry.banned_until = None;
+            }
+        }
+    }
+
+    fn record<K: Eq + Hash>(
+        failures: &mut HashMap<K, Failures>,
+        settings: &FailedLoginSettings,
+        key: K,
+        now: Instant,
+    ) {
+        let reset = Duration::from_secs(settings.reset_seconds);
+
+        if failures.len() >= PURGE_SIZE {
+            failures.retain(|_, f| !f.is_forgotten(now, reset));
+        }
+
+        let entry = failures.entry(key).or_insert(Failures {
+            count: 0,
+            last_failure: now,
+            banned_until: None,
+        });
+        if entry.is_forgotten(now, reset) {
+            entry.count = 0;
+            entry.banned_until = None;
+        }
+
+        entry.count = entry.count.saturating_add(1);
+        entry.last_failure = now;
+
+        if entry.count > settings.max_attempts {
+            let doublings = entry.count - settings.max_attempts - 1;
+            let ban = settings
+                .ban_seconds
+                .checked_shl(doublings)
+                .filter(|ban| *ban >> doublings == settings.ban_seconds)
+                .unwrap_or(u64::MAX)
+                .min(settings.max_ban_seconds);
+
+            // keeps the current ban if the configured one does not fit into Instant
+            entry.banned_until = now
+                .checked_add(Duration::from_secs(ban))
+                .or(entry.banned_until);
+        }
+    }
+}
+
+impl Failures {
+    fn remaining_ban(&self, now: Instant) -> Option<Duration> {
+        self.banned_until
+            .filter(|banned_until| *banned_until > now)
+            .map(|banned_until| banned_until - now)
+    }
+
+    fn is_forgotten(&self, now: Instant, reset: Duration) -> bool {
+        self.remaining_ban(now).is_none()
+            && now.saturating_duration_since(self.last_failure) >= reset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> FailedLoginSettings {
+        FailedLoginSettings {
+            by_ip: true,
+            by_user_name: true,
+            max_attempts: 2,
+            ban_seconds: 10,
+            max_ban_seconds: 30,
+            reset_seconds: 100,
+        }
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ban_after_max_attempts() {
+        let mut guard = LoginGuard::new(settings());
+        let now = Instant::now();
+
+        guard.on_attempt(ip("10.0.0.1"), "alice", now);
+        guard.on_attempt(ip("10.0.0.1"), "alice", now);
+        assert_eq!(guard.banned(ip("10.0.0.1"), "alice", now), None);
+
+        guard.on_attempt(ip("10.0.0.1"), "alice", now);
+        assert_eq!(
+            guard.banned(ip("10.0.0.1"), "alice", now),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            guard.banned(ip("10.0.0.2"), "alice", now),
+            Some(Duration::from_secs(10)),
+            "user name is banned from other IPs too"
+        );
+        assert_eq!(
+            guard.banned(ip("10.0.0.1"), "bob", now),
+            Some(Duration::from_secs(10)),
+            "IP is banned for other users too"
+        );
+        assert_eq!(guard.banned(ip("10.0.0.2"), "bob", now), None);
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(guard.banned(ip("10.0.0.1"), "alice", later), None);
+    }
+
+    #[test]
+    fn test_ban_doubles_up_to_max() {
+        let mut guard = LoginGuard::new(settings());
+        let now = Instant::now();
+
+        let bans: Vec<_> = (0..6)
+            .map(|_| {
+                guard.on_attempt(ip("10.0.0.1"), "alice", now);
+                guard.banned(ip("10.0.0.1"), "alice", now)
+            })
+            .collect();
+
+        let seconds = |s| Some(Duration::from_secs(s));
+        assert_eq!(
+            bans,
+            vec![
+                None,
+                None,
+                seconds(10),
+                seconds(20),
+                seconds(30),
+                seconds(30)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_huge_number_of_failures_does_not_overflow() {
+        let mut guard = LoginGuard::new(settings());
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            guard.on_attempt(ip("10.0.0.1"), "alice", now);
+        }
+
+        assert_eq!(
+            guard.banned(ip("10.0.0.1"), "alice", now),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn test_failures_are_forgotten() {
+        let mut guard = LoginGuard::new(settings());
+        let now = Instant::now();
+
+        guard.on_attempt(ip("10.0.0.1"), "alice", now);
+        guard.on_attempt(ip("10.0.0.1"), "alice", now);
+
+        let later = now + Duration::from_secs(100);
+        guard.on_attempt(ip("10.0.0.1"), "alice", later);
+        assert_eq!(guard.banned(ip("10.0.0.1"), "alice", later), None);
+    }
+
+    #[test]
+    fn test_success_resets_user_name_failures() {
+        let mut guard = LoginGuard::new(settings());
+        let now = Instant::now();
+
+        guard.on_attempt(ip("10.0.0.1"), "alice", now);
+        guard.on_attempt(ip("10.0.0.1"), "alice", now);
+        guard.on_attempt(ip("10.0.0.1"), "alice", now);
+        guard.on_success(ip("10.0.0.1"), "alice");
+        assert_eq!(guard.banned(ip("10.0.0.2"), "alice", now), None);
+
+        guard.on_attempt(ip("10.0.0.1"), "mallory", now);
+        assert_eq!(
+            guard.banned(ip("10.0.0.1"), "bob", now),
+            Some(Duration::from_secs(10)),
+            "failures of the IP are kept"
+        );
+        assert_eq!(guard.banned(ip("10.0.0.2"), "alice", now), None);
+    }
+
+    #[test]
+    fn test_parallel_attempts_are_counted() {
+        let mut guard = LoginGuard::new(settings());
+        let now = Instant::now();
+
+        // none of the results is known yet
+        for _ in 0..3 {
+            assert_eq!(guard.banned(ip("10.0.0.1"), "alice", now), None);
+            guard.on_attempt(ip("10.0.0.1"), "alice", now);
+        }
+        assert!(guard.banned(ip("10.0.0.1"), "alice", now).is_some());
+
+        guard.on_success(ip("10.0.0.1"), "alice");
+        assert_eq!(guard.banned(ip("10.0.0.1"), "alice", now), None);
+    }
+
+    #[test]
+    fn test_unavailable_is_not_counted() {
+        let mut guard = LoginGuard::new(settings());
+        let now = Instant::now();
+
+        for _ in 0..5 {
+            guard.on_attempt(ip("10.0.0.1"), "alice", now);
+            guard.on_unavailable(ip("10.0.0.1"), "alice");
+        }
+
+        assert_eq!(guard.banned(ip("10.0.0.1"), "alice", now), None);
+    }
+
+    #[test]
+    fn test_tracking_can_be_disabled() {
+        let mut guard = LoginGuard::new(FailedLoginSettings {
+            by_user_name: false,
+            ..settings()
+        });
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            guard.on_attempt(ip("10.0.0.1"), "alice", now);
+        }
+
+        assert!(guard.banned(ip("10.0.0.1"), "bob", now).is_some());
+        assert_eq!(guard.banned(ip("10.0.0.2"), "alice", now), None);
+    }
+}
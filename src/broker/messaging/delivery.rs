@@ -0,0 +1,240 @@
+use std::time::Instant;
+
+use tokio::sync::oneshot;
+use tracing::{info_span, Instrument, Span};
+
+use crate::broker::messaging::{MessagingOperation, MessagingTx};
+use crate::broker::metrics::BrokerMetrics;
+use crate::mqtt::events::ServerEvent;
+use crate::mqtt::packets::PublishPacket;
+
+/// Stores the message when it is retained and sends it to the subscribers of its topic,
+/// which is already mounted. Returns the number of subscribers the message was sent to.
+pub async fn deliver(
+    messaging_tx: &MessagingTx,
+    metrics: &BrokerMetrics,
+    publish: PublishPacket,
+) -> usize {
+    fan_out(messaging_tx, Some(metrics), publish).await
+}
+
+/// Same as `deliver` for the messages of the broker itself, e.g. the `$SYS` topics,
+/// which are not counted in the publish metrics.
+pub async fn deliver_internal(messaging_tx: &MessagingTx, publish: PublishPacket) -> usize {
+    fan_out(messaging_tx, None, publish).await
+}
+
+#[tracing::instrument(skip_all, fields(topic = publish.message.topic.as_str(), subscribers))]
+async fn fan_out(
+    messaging_tx: &MessagingTx,
+    metrics: Option<&BrokerMetrics>,
+    publish: PublishPacket,
+) -> usize {
+    if publish.message.retain {
+        let (tx, rx) = oneshot::channel();
+        let op = MessagingOperation::RetainedSet {
+            publish: publish.clone(),
+            resp: tx,
+        };
+
+        messaging_tx.send(op).await.unwrap();
+        rx.await.unwrap();
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let op = MessagingOperation::SendersToPublish {
+        topic: publish.message.topic.clone(),
+        span: Span::current(),
+        resp: tx,
+    };
+
+    messaging_tx.send(op).await.unwrap();
+    let senders_to_publish = rx.await.unwrap();
+    Span::current().record("subscribers", senders_to_publish.len());
+
+    let mut fanout = 0;
+    for (client_id, subscriber_topic, sender, statistics) in senders_to_publish {
+        let mut packet = publish.clone();
+        packet.message.topic = subscriber_topic;
+        // messages sent because of an existing subscription are not retained - MQTT-3.3.1-9
+        packet.message.retain = false;
+        let payload_size = packet.message.payload.len() as u64;
+
+        // a slow subscriber shows as a long wait for space in its channel
+        let span = info_span!("send_publish", client_id = client_id.as_str());
+        let event = ServerEvent::Publish(packet, span.clone());
+        let started_at = Instant::now();
+        // the connection might be already gone when the client disconnected in the meantime
+        let sent = sender.send(event).instrument(span).await.is_ok();
+        if sent {
+            fanout += 1;
+        }
+
+        if let Some(metrics) = metrics {
+            metrics
+                .channel_send_seconds
+                .with_label_values(&["client"])
+                .observe(started_at.elapsed().as_secs_f64());
+
+            if sent {
+                metrics.messages_sent.inc();
+                metrics.publish_bytes_sent.inc_by(payload_size);
+            } else {
+                metrics.messages_dropped.inc();
+                statistics.add_dropped();
+            }
+        }
+    }
+    if let Some(metrics) = metrics {
+        metrics.publish_fanout.observe(fanout as f64);
+    }
+
+    fanout
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broker::messaging::{channel, MessagingService};
+    use crate::broker::mountpoint::Mountpoint;
+    use crate::broker::session::{ClientStatistics, Session};
+    use crate::mqtt::packets::QoS;
+    use crate::mqtt::subscription::Subscription;
+    use bytes::BytesMut;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    async fn connect(
+        messaging_tx: &MessagingTx,
+        clean_session: bool,
+    ) -> (mpsc::Receiver<ServerEvent>, Arc<ClientStatistics>) {
+        let (sender, receiver) = mpsc::channel(32);
+        let statistics = Arc::new(ClientStatistics::new());
+        let session = Session::new(
+            "c1".to_string(),
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            false,
+            sender,
+            60,
+            Mountpoint::default(),
+            Arc::clone(&statistics),
+        );
+        let (tx, rx) = oneshot::channel();
+        let op = MessagingOperation::SessionReplace {
+            session,
+            clean_session,
+            resp: tx,
+        };
+        messaging_tx.send(op).await.unwrap();
+        rx.await.unwrap();
+
+        (receiver, statistics)
+    }
+
+    async fn subscribe(
+        messaging_tx: &MessagingTx,
+        topic: &str,
+    ) -> (mpsc::Receiver<ServerEvent>, Arc<ClientStatistics>) {
+        let connection = connect(messaging_tx, true).await;
+
+        let (tx, rx) = oneshot::channel();
+        let op = MessagingOperation::Subscribe {
+            client_id: "c1".to_string(),
+            subscription: Subscription::new(topic.to_string(), QoS::AtMostOnce),
+            resp: tx,
+        };
+        messaging_tx.send(op).await.unwrap();
+        rx.await.unwrap();
+
+        connection
+    }
+
+    fn publish(topic: &str, retain: bool) -> PublishPacket {
+        PublishPacket::new(
+            topic.to_string(),
+            BytesMut::from("1"),
+            QoS::AtMostOnce,
+            retain,
+            None,
+            false,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_internal_messages_are_not_counted() {
+        let metrics = Arc::new(BrokerMetrics::new());
+        let (messaging_tx, messaging_rx) = channel(32, Arc::clone(&metrics));
+        tokio::spawn(MessagingService::new(Arc::clone(&metrics)).run(messaging_rx));
+        let (mut receiver, _) = subscribe(&messaging_tx, "$SYS/#").await;
+
+        let fanout = deliver_internal(&messaging_tx, publish("$SYS/broker/uptime", true)).await;
+
+        assert_eq!(fanout, 1);
+        assert!(matches!(
+            receiver.recv().await,
+            Some(ServerEvent::Publish(..))
+        ));
+        assert_eq!(metrics.messages_sent.get(), 0);
+        assert_eq!(metrics.publish_bytes_sent.get(), 0);
+        assert_eq!(metrics.retained_messages.get(), 1);
+
+        deliver(
+            &messaging_tx,
+            &metrics,
+            publish("$SYS/broker/uptime", false),
+        )
+        .await;
+        assert_eq!(metrics.messages_sent.get(), 1);
+        assert_eq!(metrics.publish_bytes_sent.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_undelivered_messages_are_counted() {
+        let metrics = Arc::new(BrokerMetrics::new());
+        let (messaging_tx, messaging_rx) = channel(32, Arc::clone(&metrics));
+        tokio::spawn(MessagingService::new(Arc::clone(&metrics)).run(messaging_rx));
+        let (receiver, statistics) = subscribe(&messaging_tx, "sensors/#").await;
+        drop(receiver);
+
+        let fanout = deliver(&messaging_tx, &metrics, publish("sensors/1", false)).await;
+
+        assert_eq!(fanout, 0);
+        assert_eq!(metrics.messages_dropped.get(), 1);
+        assert_eq!(statistics.messages_dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_sent_to_the_new_connection() {
+        let metrics = Arc::new(BrokerMetrics::new());
+        let (messaging_tx, messaging_rx) = channel(32, Arc::clone(&metrics));
+        tokio::spawn(MessagingService::new(Arc::clone(&metrics)).run(messaging_rx));
+        let (old_receiver, old_statistics) = subscribe(&messaging_tx, "sensors/#").await;
+        drop(old_receiver);
+        let (mut receiver, statistics) = connect(&messaging_tx, false).await;
+
+        let fanout = deliver(&messaging_tx, &metrics, publish("sensors/1", false)).await;
+
+        assert_eq!(fanout, 1);
+        assert!(matches!(
+            receiver.recv().await,
+            Some(ServerEvent::Publish(..))
+        ));
+        assert_eq!(old_statistics.messages_dropped(), 0);
+        assert_eq!(statistics.messages_dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_clean_session_removes_subscriptions() {
+        let metrics = Arc::new(BrokerMetrics::new());
+        let (messaging_tx, messaging_rx) = channel(32, Arc::clone(&metrics));
+        tokio::spawn(MessagingService::new(Arc::clone(&metrics)).run(messaging_rx));
+        subscribe(&messaging_tx, "sensors/#").await;
+        let (_receiver, _) = connect(&messaging_tx, true).await;
+
+        let fanout = deliver(&messaging_tx, &metrics, publish("sensors/1", false)).await;
+
+        assert_eq!(fanout, 0);
+        assert_eq!(metrics.subscriptions.get(), 0);
+    }
+}
@@ -1,23 +1,110 @@
 use log::{debug, info, trace, warn};
 
+use crate::broker::messaging::retained_repository::RetainedRepository;
 use crate::broker::messaging::subscriptions_repository::SubscriptionsRepository;
+use crate::broker::metrics::BrokerMetrics;
 use crate::broker::session::session_repository::SessionRepository;
-use crate::broker::session::{InMemorySessionRepository, Session};
+use crate::broker::session::{ClientStatistics, InMemorySessionRepository, Session};
 use crate::mqtt::events::ServerEvent;
 use crate::mqtt::message::Message;
 use crate::mqtt::packets::suback::SubAckReturnCode;
 use crate::mqtt::packets::ControlPacket::Publish;
-use crate::mqtt::packets::{ClientId, PublishPacket};
+use crate::mqtt::packets::{ClientId, ProtocolVersion, PublishPacket};
 use crate::mqtt::subscription::Subscription;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use std::collections::hash_map::Iter;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc::error::SendError;
 use tokio::sync::{mpsc, oneshot};
+use tracing::Span;
 
-pub type MessagingTx = mpsc::Sender<MessagingOperation>;
-pub type MessagingRx = mpsc::Receiver<MessagingOperation>;
+/// Operation with the time it was queued at.
+type QueuedOperation = (Instant, MessagingOperation);
+
+pub type MessagingRx = mpsc::Receiver<QueuedOperation>;
+
+/// Sending side of the messaging service queue, measures the time spent waiting for a free slot.
+#[derive(Clone)]
+pub struct MessagingTx {
+    tx: mpsc::Sender<QueuedOperation>,
+    metrics: Arc<BrokerMetrics>,
+}
+
+impl MessagingTx {
+    pub async fn send(&self, op: MessagingOperation) -> Result<(), SendError<MessagingOperation>> {
+        let started_at = Instant::now();
+        let permit = match self.tx.reserve().await {
+            Ok(permit) => permit,
+            Err(_) => return Err(SendError(op)),
+        };
+        self.metrics
+            .channel_send_seconds
+            .with_label_values(&["messaging"])
+            .observe(started_at.elapsed().as_secs_f64());
+
+        permit.send((Instant::now(), op));
+        Ok(())
+    }
+}
+
+pub fn channel(buffer: usize, metrics: Arc<BrokerMetrics>) -> (MessagingTx, MessagingRx) {
+    let (tx, rx) = mpsc::channel(buffer);
+
+    (MessagingTx { tx, metrics }, rx)
+}
 
 type Responder<T> = oneshot::Sender<T>;
 
+/// Subscriber of a topic: the client, the topic inside of its mountpoint, its connection
+/// and its statistics.
+pub type Subscriber = (
+    ClientId,
+    String,
+    mpsc::Sender<ServerEvent>,
+    Arc<ClientStatistics>,
+);
+
+/// Snapshot of a session returned to the management API.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+    pub client_id: ClientId,
+    pub ip: IpAddr,
+    pub persistent: bool,
+    pub keep_alive_seconds: u16,
+    pub protocol_version: Option<ProtocolVersion>,
+    pub connected_at: DateTime<Utc>,
+    pub last_activity: DateTime<Utc>,
+    pub packets_received: u64,
+    pub packets_sent: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub messages_dropped: u64,
+    pub queued_packets: usize,
+}
+
+impl From<&Session> for SessionInfo {
+    fn from(session: &Session) -> Self {
+        let statistics = session.statistics();
+        SessionInfo {
+            client_id: session.client_id().clone(),
+            ip: session.ip(),
+            persistent: session.is_persistent(),
+            keep_alive_seconds: session.keep_alive_seconds,
+            protocol_version: statistics.protocol_version().cloned(),
+            connected_at: statistics.connected_at(),
+            last_activity: statistics.last_activity(),
+            packets_received: statistics.packets_received(),
+            packets_sent: statistics.packets_sent(),
+            bytes_received: statistics.bytes_received(),
+            bytes_sent: statistics.bytes_sent(),
+            messages_dropped: statistics.messages_dropped(),
+            queued_packets: session.queued_packets(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum MessagingOperation {
     SessionExists {
@@ -32,12 +119,26 @@ pub enum MessagingOperation {
         client_id: ClientId,
         resp: Responder<Option<Session>>,
     },
+    /// Replaces the session of a previous connection of the client, answered with whether there
+    /// was one. Its subscriptions are removed when the client starts a clean session.
+    SessionReplace {
+        session: Session,
+        clean_session: bool,
+        resp: Responder<bool>,
+    },
     SessionGetExpiredKeepAlive {
         resp: Responder<Vec<Session>>,
     },
     SessionCount {
         resp: Responder<usize>,
     },
+    SessionList {
+        resp: Responder<Vec<SessionInfo>>,
+    },
+    SessionDetails {
+        client_id: ClientId,
+        resp: Responder<Option<SessionInfo>>,
+    },
 
     ConnectionLost {
         client_id: ClientId,
@@ -63,28 +164,64 @@ pub enum MessagingOperation {
         publish: PublishPacket,
         resp: Responder<()>,
     },
+    /// Subscribers of the topic with the topic as seen from inside of their mountpoints.
     SendersToPublish {
         topic: String,
-        resp: Responder<Vec<mpsc::Sender<ServerEvent>>>,
+        /// Parent of the lookup span.
+        span: Span,
+        resp: Responder<Vec<Subscriber>>,
+    },
+    /// Topic filters with the subscribed clients.
+    SubscriptionList {
+        resp: Responder<Vec<(String, ClientId)>>,
+    },
+    /// Topic filters of the client or `None` when there is no such session.
+    ClientSubscriptions {
+        client_id: ClientId,
+        resp: Responder<Option<Vec<String>>>,
+    },
+
+    RetainedSet {
+        publish: PublishPacket,
+        resp: Responder<()>,
+    },
+    RetainedMatching {
+        topic_filter: String,
+        resp: Responder<Vec<PublishPacket>>,
+    },
+    RetainedList {
+        resp: Responder<Vec<PublishPacket>>,
+    },
+    RetainedDelete {
+        topic: String,
+        resp: Responder<bool>,
     },
 }
 
 pub struct MessagingService {
     sessions: InMemorySessionRepository,
     subscriptions: SubscriptionsRepository,
+    retained: RetainedRepository,
+    metrics: Arc<BrokerMetrics>,
 }
 
 impl MessagingService {
-    pub fn new() -> Self {
+    pub fn new(metrics: Arc<BrokerMetrics>) -> Self {
         MessagingService {
             sessions: InMemorySessionRepository::default(),
             subscriptions: SubscriptionsRepository::new(),
+            retained: RetainedRepository::default(),
+            metrics,
         }
     }
 
-    pub async fn run(mut self, mut rx: mpsc::Receiver<MessagingOperation>) {
+    pub async fn run(mut self, mut rx: MessagingRx) {
         debug!("Started Messaging Manager");
-        while let Some(op) = rx.recv().await {
+        while let Some((queued_at, op)) = rx.recv().await {
+            self.metrics
+                .messaging_queue_seconds
+                .observe(queued_at.elapsed().as_secs_f64());
+
             match op {
                 MessagingOperation::SessionExists { client_id, resp } => {
                     let result = self.session_exists(&client_id);
@@ -98,6 +235,14 @@ impl MessagingService {
                     // let result = self.session_get(&client_id);
                     let _ = resp.send(None);
                 }
+                MessagingOperation::SessionReplace {
+                    session,
+                    clean_session,
+                    resp,
+                } => {
+                    let result = self.session_replace(session, clean_session);
+                    let _ = resp.send(result);
+                }
                 MessagingOperation::SessionGetExpiredKeepAlive { resp } => {
                     // let result = self.session_get_keep_alive_expired();
                     let _ = resp.send(Vec::new());
@@ -106,6 +251,18 @@ impl MessagingService {
                     let result = self.session_count();
                     let _ = resp.send(result);
                 }
+                MessagingOperation::SessionList { resp } => {
+                    let result = self
+                        .sessions
+                        .iter()
+                        .map(|(_, session)| session.into())
+                        .collect();
+                    let _ = resp.send(result);
+                }
+                MessagingOperation::SessionDetails { client_id, resp } => {
+                    let result = self.sessions.get(&client_id).map(SessionInfo::from);
+                    let _ = resp.send(result);
+                }
                 MessagingOperation::ConnectionLost { client_id, resp } => {
                     let result = self.connection_lost(&client_id);
                     let _ = resp.send(result);
@@ -114,28 +271,83 @@ impl MessagingService {
                     let result = self.disconnect(&client_id);
                     let _ = resp.send(result);
                 }
-                MessagingOperation::Subscribe { client_id, subscription, resp } => {
+                MessagingOperation::Subscribe {
+                    client_id,
+                    subscription,
+                    resp,
+                } => {
                     let result = self.subscribe(&client_id, &subscription);
                     let _ = resp.send(result);
                 }
-                MessagingOperation::Unsubscribe { client_id, topics, resp } => {
+                MessagingOperation::Unsubscribe {
+                    client_id,
+                    topics,
+                    resp,
+                } => {
                     self.unsubscribe(&client_id, &topics);
                     let _ = resp.send(());
                 }
-                MessagingOperation::Publish { message, publish, resp } => {
+                MessagingOperation::Publish {
+                    message,
+                    publish,
+                    resp,
+                } => {
                     self.publish(&message, &publish);
                     let _ = resp.send(());
                 }
 
-                MessagingOperation::SendersToPublish { topic, resp } => {
-                    let result = self.senders_to_publish(&topic);
+                MessagingOperation::SendersToPublish { topic, span, resp } => {
+                    let result = span.in_scope(|| self.senders_to_publish(&topic));
+                    let _ = resp.send(result);
+                }
+                MessagingOperation::SubscriptionList { resp } => {
+                    let _ = resp.send(self.subscriptions.subscriptions());
+                }
+                MessagingOperation::ClientSubscriptions { client_id, resp } => {
+                    let result = match self.sessions.exists(&client_id) {
+                        true => Some(self.subscriptions.client_subscriptions(&client_id)),
+                        false => None,
+                    };
+                    let _ = resp.send(result);
+                }
+
+                MessagingOperation::RetainedSet { publish, resp } => {
+                    self.retained.set(&publish);
+                    self.update_retained_messages();
+                    let _ = resp.send(());
+                }
+                MessagingOperation::RetainedMatching { topic_filter, resp } => {
+                    let result = self.retained.matching(&topic_filter);
+                    let _ = resp.send(result);
+                }
+                MessagingOperation::RetainedList { resp } => {
+                    let _ = resp.send(self.retained.list());
+                }
+                MessagingOperation::RetainedDelete { topic, resp } => {
+                    let result = self.retained.delete(&topic);
+                    self.update_retained_messages();
                     let _ = resp.send(result);
                 }
             }
+
+            self.metrics.sessions.set(self.sessions.count() as i64);
+            self.metrics
+                .subscriptions
+                .set(self.subscriptions.count() as i64);
+            self.metrics
+                .subscription_trie_nodes
+                .set(self.subscriptions.node_count() as i64);
         }
         debug!("Stopped Messaging Manager");
     }
 
+    /// Updated before responding, so the count is current once the change is acknowledged.
+    fn update_retained_messages(&self) {
+        self.metrics
+            .retained_messages
+            .set(self.retained.count() as i64);
+    }
+
     pub fn session_exists(&self, client_id: &ClientId) -> bool {
         self.sessions.exists(client_id)
     }
@@ -145,6 +357,16 @@ impl MessagingService {
         self.sessions.insert(session)
     }
 
+    pub fn session_replace(&mut self, session: Session, clean_session: bool) -> bool {
+        let existed = self.sessions.exists(session.client_id());
+        if clean_session {
+            self.subscriptions.disconnected(session.client_id());
+        }
+        self.session_insert(session);
+
+        existed
+    }
+
     pub fn session_get(&self, client_id: &ClientId) -> Option<&Session> {
         self.sessions.get(client_id)
     }
@@ -214,14 +436,22 @@ impl MessagingService {
         }
     }
 
-    pub fn senders_to_publish(&self, topic: &String) -> Vec<mpsc::Sender<ServerEvent>> {
+    #[tracing::instrument(skip(self))]
+    pub fn senders_to_publish(&self, topic: &String) -> Vec<Subscriber> {
         let mut senders = Vec::new();
 
         if let Some(client_ids) = self.subscriptions.subscribed_clients(topic) {
             for c in &client_ids {
                 match self.sessions.get(c) {
                     Some(session) => {
-                        senders.push(session.sender().clone());
+                        if let Some(subscriber_topic) = session.mountpoint().unmount(topic) {
+                            senders.push((
+                                c.clone(),
+                                subscriber_topic.to_string(),
+                                session.sender().clone(),
+                                Arc::clone(session.statistics()),
+                            ));
+                        }
                     }
                     None => {
                         warn!(
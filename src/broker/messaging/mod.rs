@@ -1,7 +1,12 @@
+mod delivery;
 mod messaging_service;
+mod retained_repository;
 mod subscriptions_repository;
 
-pub use self::messaging_service::MessagingService;
+pub use self::delivery::{deliver, deliver_internal};
+pub use self::messaging_service::channel;
 pub use self::messaging_service::MessagingOperation;
-pub use self::messaging_service::MessagingTx;
 pub use self::messaging_service::MessagingRx;
+pub use self::messaging_service::MessagingService;
+pub use self::messaging_service::MessagingTx;
+pub use self::messaging_service::SessionInfo;
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+
+use crate::mqtt::packets::PublishPacket;
+use crate::mqtt::subscription::filter_matches;
+
+/// The last retained message of each topic, kept in memory only.
+#[derive(Default)]
+pub struct RetainedRepository {
+    messages: BTreeMap<String, PublishPacket>,
+}
+
+impl RetainedRepository {
+    /// Stores the message, a message with an empty payload removes the retained one - MQTT-3.3.1-10
+    pub fn set(&mut self, publish: &PublishPacket) {
+        let topic = publish.message.topic.clone();
+
+        if publish.message.payload.is_empty() {
+            self.messages.remove(&topic);
+        } else {
+            self.messages.insert(topic, publish.clone());
+        }
+    }
+
+    /// Returns false if there was no retained message on the topic.
+    pub fn delete(&mut self, topic: &str) -> bool {
+        self.messages.remove(topic).is_some()
+    }
+
+    /// Messages ordered by topic.
+    pub fn list(&self) -> Vec<PublishPacket> {
+        self.messages.values().cloned().collect()
+    }
+
+    pub fn matching(&self, topic_filter: &str) -> Vec<PublishPacket> {
+        self.messages
+            .iter()
+            .filter(|(topic, _)| filter_matches(topic_filter, topic))
+            .map(|(_, publish)| publish.clone())
+            .collect()
+    }
+
+    pub fn count(&self) -> usize {
+        self.messages.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::packets::QoS;
+    use bytes::BytesMut;
+
+    fn publish(topic: &str, payload: &str) -> PublishPacket {
+        PublishPacket::new(
+            topic.to_string(),
+            BytesMut::from(payload.as_bytes()),
+            QoS::AtMostOnce,
+            true,
+            None,
+            false,
+        )
+    }
+
+    fn topics(messages: Vec<PublishPacket>) -> Vec<String> {
+        messages.into_iter().map(|p| p.message.topic).collect()
+    }
+
+    #[test]
+    fn test_set_replaces_and_empty_payload_removes() {
+        let mut repo = RetainedRepository::default();
+
+        repo.set(&publish("a/b", "1"));
+        repo.set(&publish("a/b", "2"));
+        repo.set(&publish("a/c", "3"));
+        assert_eq!(repo.count(), 2);
+        assert_eq!(repo.list()[0].message.payload, BytesMut::from("2"));
+
+        repo.set(&publish("a/c", ""));
+        assert_eq!(topics(repo.list()), vec!["a/b"]);
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut repo = RetainedRepository::default();
+        repo.set(&publish("a/b", "1"));
+
+        assert!(repo.delete("a/b"));
+        assert!(!repo.delete("a/b"));
+        assert_eq!(repo.count(), 0);
+    }
+
+    #[test]
+    fn test_matching() {
+        let mut repo = RetainedRepository::default();
+        for topic in ["a", "a/b", "a/b/c", "a/x/c", "$SYS/broker/uptime"] {
+            repo.set(&publish(topic, "1"));
+        }
+
+        assert_eq!(topics(repo.matching("a/b")), vec!["a/b"]);
+        assert_eq!(topics(repo.matching("a/+/c")), vec!["a/b/c", "a/x/c"]);
+        assert_eq!(
+            topics(repo.matching("a/#")),
+            vec!["a", "a/b", "a/b/c", "a/x/c"]
+        );
+        assert_eq!(
+            topics(repo.matching("#")),
+            vec!["a", "a/b", "a/b/c", "a/x/c"]
+        );
+        assert_eq!(topics(repo.matching("+/broker/#")), Vec::<String>::new());
+        assert_eq!(topics(repo.matching("$SYS/#")), vec!["$SYS/broker/uptime"]);
+    }
+}
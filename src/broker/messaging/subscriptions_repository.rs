@@ -7,15 +7,28 @@ use crate::mqtt::subscription::Subscription;
 
 pub struct SubscriptionsRepository {
     root: SubscriptionNode,
+    count: usize,
+    node_count: usize,
 }
 
 impl SubscriptionsRepository {
     pub fn new() -> SubscriptionsRepository {
         SubscriptionsRepository {
             root: SubscriptionNode::new(),
+            count: 0,
+            node_count: 0,
         }
     }
 
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Number of nodes of the topic tree, nodes are never removed.
+    pub fn node_count(&self) -> usize {
+        self.node_count
+    }
+
     pub fn subscribe(
         &mut self,
         client_id: &ClientId,
@@ -29,12 +42,13 @@ impl SubscriptionsRepository {
 
         let segments: Vec<&str> = subscription.topic().split("/").collect();
         for segment in segments {
-            node = node
-                .children
-                .entry(segment.to_string())
-                .or_insert(SubscriptionNode::new());
+            node = node.children.entry(segment.to_string()).or_insert_with(|| {
+                self.node_count += 1;
+                SubscriptionNode::new()
+            });
         }
         node.clients.push(client_id.clone());
+        self.count += 1;
         // println!("Nodes: {:?}", &self.root);
 
         SubAckReturnCode::SuccessQoS0
@@ -45,35 +59,32 @@ impl SubscriptionsRepository {
             // if let Some(client_ids) = self.subscriptions.get_mut(topic) {
             // client_ids.remove(client_id);
             // }
-            let mut node = &mut self.root;
-
-            // subscription.topic().split("/").for_each(|x| {
-            //     node = node.children.entry(x.to_string()).or_insert(SubscriptionNode::new());
-            // });
-
-            let segments: Vec<&str> = topic.split("/").collect();
-            for segment in segments {
-                node = node
-                    .children
-                    .entry(segment.to_string())
-                    .or_insert(SubscriptionNode::new());
-            }
+            let mut node = Some(&mut self.root);
 
-            node.clients
-                .iter()
-                .position(|c| c == client_id)
-                .map(|p| node.clients.remove(p));
+            // unknown topics must not grow the tree
+            for segment in topic.split('/') {
+                node = node.and_then(|node| node.children.get_mut(segment));
+            }
+            let node = match node {
+                Some(node) => node,
+                None => continue,
+            };
+
+            if let Some(p) = node.clients.iter().position(|c| c == client_id) {
+                node.clients.remove(p);
+                self.count -= 1;
+            }
             // node.clients.(client_id.clone());
             // println!("Nodes: {:?}", &self.root);
         }
     }
 
     pub fn disconnected(&mut self, client_id: &ClientId) {
-        Self::remove_client(&mut self.root, client_id);
+        self.count -= Self::remove_client(&mut self.root, client_id);
     }
 
     pub fn connections_lost(&mut self, client_id: &ClientId) {
-        Self::remove_client(&mut self.root, client_id);
+        self.count -= Self::remove_client(&mut self.root, client_id);
     }
 
     pub fn subscribed_clients(&self, topic: &String) -> Option<Vec<ClientId>> {
@@ -81,7 +92,10 @@ impl SubscriptionsRepository {
 
         let mut nodes = vec![&self.root];
         let segments: Vec<&str> = topic.split("/").collect();
-        for segment in segments {
+        for (level, segment) in segments.into_iter().enumerate() {
+            // wildcards at the first level do not match topics starting with $ - MQTT-4.7.2-1
+            let wildcards_match = level > 0 || !segment.starts_with('$');
+
             // nodes.iter().or itera
             let mut descendant_nodes = Vec::new();
             for node in nodes {
@@ -89,6 +103,10 @@ impl SubscriptionsRepository {
                     descendant_nodes.push(direct);
                 };
 
+                if !wildcards_match {
+                    continue;
+                }
+
                 if let Some(plus) = node.children.get("+") {
                     descendant_nodes.push(plus);
                 };
@@ -111,15 +129,52 @@ impl SubscriptionsRepository {
         // None
     }
 
-    fn remove_client(node: &mut SubscriptionNode, client_id: &ClientId) {
-        node.clients
-            .iter()
-            .position(|c| c == client_id)
-            .map(|p| node.clients.remove(p));
+    /// Topic filters with the subscribed clients, ordered by topic and client id.
+    pub fn subscriptions(&self) -> Vec<(String, ClientId)> {
+        let mut subscriptions = Vec::new();
+        for (segment, node) in &self.root.children {
+            Self::collect_subscriptions(node, segment.clone(), &mut subscriptions);
+        }
 
-        node.children
-            .iter_mut()
-            .for_each(|c| Self::remove_client(c.1, client_id));
+        subscriptions.sort();
+        subscriptions
+    }
+
+    /// Topic filters the client is subscribed to, ordered by topic.
+    pub fn client_subscriptions(&self, client_id: &ClientId) -> Vec<String> {
+        self.subscriptions()
+            .into_iter()
+            .filter(|(_, c)| c == client_id)
+            .map(|(topic, _)| topic)
+            .collect()
+    }
+
+    fn collect_subscriptions(
+        node: &SubscriptionNode,
+        topic: String,
+        subscriptions: &mut Vec<(String, ClientId)>,
+    ) {
+        for client_id in &node.clients {
+            subscriptions.push((topic.clone(), client_id.clone()));
+        }
+
+        for (segment, child) in &node.children {
+            Self::collect_subscriptions(child, format!("{}/{}", topic, segment), subscriptions);
+        }
+    }
+
+    /// Returns the number of removed subscriptions.
+    fn remove_client(node: &mut SubscriptionNode, client_id: &ClientId) -> usize {
+        let count = node.clients.len();
+        node.clients.retain(|c| c != client_id);
+
+        let removed = count - node.clients.len();
+        removed
+            + node
+                .children
+                .values_mut()
+                .map(|c| Self::remove_client(c, client_id))
+                .sum::<usize>()
     }
 }
 
@@ -318,6 +373,74 @@ mod tests {
         assert(repo.subscribed_clients(&"a/b/c".to_string()), Some(vec![]));
     }
 
+    #[test]
+    fn test_subscribed_clients_wildcards_not_matching_dollar_topics() {
+        let mut repo = SubscriptionsRepository::new();
+
+        subscribe(&mut repo, "#", "c1");
+        subscribe(&mut repo, "+/broker/uptime", "c2");
+        subscribe(&mut repo, "$SYS/#", "c3");
+        subscribe(&mut repo, "$SYS/+/uptime", "c4");
+        subscribe(&mut repo, "$SYS/broker/uptime", "c5");
+
+        assert(
+            repo.subscribed_clients(&"$SYS/broker/uptime".to_string()),
+            Some(vec!["c3".to_string(), "c4".to_string(), "c5".to_string()]),
+        );
+        assert(
+            repo.subscribed_clients(&"a/$b".to_string()),
+            Some(vec!["c1".to_string()]),
+        );
+    }
+
+    #[test]
+    fn test_client_subscriptions() {
+        let mut repo = SubscriptionsRepository::new();
+
+        subscribe(&mut repo, "a/b", "c1");
+        subscribe(&mut repo, "a/#", "c1");
+        subscribe(&mut repo, "a/b/c", "c2");
+        subscribe(&mut repo, "+", "c1");
+
+        assert_eq!(
+            repo.client_subscriptions(&"c1".to_string()),
+            vec!["+", "a/#", "a/b"]
+        );
+        assert_eq!(repo.client_subscriptions(&"c2".to_string()), vec!["a/b/c"]);
+        assert!(repo.client_subscriptions(&"c3".to_string()).is_empty());
+
+        assert_eq!(
+            repo.subscriptions(),
+            vec![
+                ("+".to_string(), "c1".to_string()),
+                ("a/#".to_string(), "c1".to_string()),
+                ("a/b".to_string(), "c1".to_string()),
+                ("a/b/c".to_string(), "c2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_count() {
+        let mut repo = SubscriptionsRepository::new();
+
+        subscribe(&mut repo, "a/b", "c1");
+        subscribe(&mut repo, "a/#", "c1");
+        subscribe(&mut repo, "a/b", "c2");
+        assert_eq!(repo.count(), 3);
+        assert_eq!(repo.node_count(), 3);
+
+        repo.unsubscribe(
+            &ClientId::from("c2"),
+            &vec!["a/b".to_string(), "x/y".to_string()],
+        );
+        assert_eq!(repo.count(), 2);
+        assert_eq!(repo.node_count(), 3);
+
+        repo.connections_lost(&ClientId::from("c1"));
+        assert_eq!(repo.count(), 0);
+    }
+
     fn assert(actual: Option<Vec<ClientId>>, expected: Option<Vec<ClientId>>) {
         let a = actual.map(|mut v| {
             v.sort();
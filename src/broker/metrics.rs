@@ -0,0 +1,375 @@
+use crate::broker::messaging::SessionInfo;
+use crate::config::build_info::BUILD_INFO;
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::time::{Duration, Instant};
+
+const NAMESPACE: &str = "ratelmq";
+
+/// Broker-wide metrics. Each of them is updated by the task owning the related state and read
+/// by the others, i.e. the `$SYS` topics publisher and the Prometheus endpoint.
+pub struct BrokerMetrics {
+    started_at: Instant,
+    registry: Registry,
+    uptime_seconds: IntGauge,
+    /// Accepted TCP connections.
+    pub connections: IntCounter,
+    /// TCP connections closed right away, labeled by reason.
+    pub connections_rejected: IntCounterVec,
+    /// Clients with an accepted MQTT connection.
+    pub clients_connected: IntGauge,
+    /// Sessions of both connected and disconnected clients.
+    pub sessions: IntGauge,
+    pub subscriptions: IntGauge,
+    pub subscription_trie_nodes: IntGauge,
+    /// Set by the messaging service whenever the retained messages change.
+    pub retained_messages: IntGauge,
+    pub packets_received: IntCounterVec,
+    pub packets_sent: IntCounterVec,
+    /// Bytes read from and written to the connections, including the MQTT framing.
+    pub bytes_received: IntCounter,
+    pub bytes_sent: IntCounter,
+    pub messages_received: IntCounter,
+    pub messages_sent: IntCounter,
+    /// PUBLISH packets which were not authorized or not delivered because the subscriber was gone.
+    pub messages_dropped: IntCounter,
+    /// Payload bytes of the received and sent PUBLISH packets.
+    pub publish_bytes_received: IntCounter,
+    pub publish_bytes_sent: IntCounter,
+    /// Number of subscribers a message was delivered to.
+    pub publish_fanout: Histogram,
+    pub authentication_failures: IntCounterVec,
+    /// Audit records lost because the audit log writer fell behind.
+    pub audit_records_dropped: IntCounter,
+    /// Time spent waiting for a free slot in a full channel, labeled by the channel.
+    pub channel_send_seconds: HistogramVec,
+    /// Time an operation waits in the queue of the messaging service.
+    pub messaging_queue_seconds: Histogram,
+}
+
+impl BrokerMetrics {
+    pub fn new() -> BrokerMetrics {
+        let registry = Registry::new_custom(Some(NAMESPACE.to_string()), None)
+            .expect("Invalid metrics namespace");
+
+        let build_info = IntGaugeVec::new(
+            Opts::new("build_info", "Version and commit of the running broker"),
+            &["version", "commit"],
+        )
+        .unwrap();
+        build_info
+            .with_label_values(&[BUILD_INFO.version, BUILD_INFO.commit_hash])
+            .set(1);
+        register(&registry, build_info);
+
+        let latency_buckets = vec![0.000_01, 0.000_1, 0.001, 0.01, 0.1, 1.0];
+
+        BrokerMetrics {
+            started_at: Instant::now(),
+            uptime_seconds: register(
+                &registry,
+                IntGauge::new("uptime_seconds", "Seconds since the broker started").unwrap(),
+            ),
+            connections: register(
+                &registry,
+                IntCounter::new("connections_total", "Accepted TCP connections").unwrap(),
+            ),
+            connections_rejected: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("connections_rejected_total", "Rejected TCP connections"),
+                    &["reason"],
+                )
+                .unwrap(),
+            ),
+            clients_connected: register(
+                &registry,
+                IntGauge::new("clients_connected", "Clients with accepted connection").unwrap(),
+            ),
+            sessions: register(
+                &registry,
+                IntGauge::new("sessions", "Sessions of connected and disconnected clients")
+                    .unwrap(),
+            ),
+            subscriptions: register(
+                &registry,
+                IntGauge::new("subscriptions", "Subscriptions of all clients").unwrap(),
+            ),
+            subscription_trie_nodes: register(
+                &registry,
+                IntGauge::new(
+                    "subscription_trie_nodes",
+                    "Nodes of the subscription topic tree",
+                )
+                .unwrap(),
+            ),
+            retained_messages: register(
+                &registry,
+                IntGauge::new("retained_messages", "Stored retained messages").unwrap(),
+            ),
+            packets_received: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("packets_received_total", "Received MQTT packets"),
+                    &["type"],
+                )
+                .unwrap(),
+            ),
+            packets_sent: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("packets_sent_total", "Sent MQTT packets"),
+                    &["type"],
+                )
+                .unwrap(),
+            ),
+            bytes_received: register(
+                &registry,
+                IntCounter::new("bytes_received_total", "Bytes read from connections").unwrap(),
+            ),
+            bytes_sent: register(
+                &registry,
+                IntCounter::new("bytes_sent_total", "Bytes written to connections").unwrap(),
+            ),
+            messages_received: register(
+                &registry,
+                IntCounter::new(
+                    "publish_messages_received_total",
+                    "PUBLISH packets received from clients",
+                )
+                .unwrap(),
+            ),
+            messages_sent: register(
+                &registry,
+                IntCounter::new(
+                    "publish_messages_sent_total",
+                    "PUBLISH packets sent to subscribers",
+                )
+                .unwrap(),
+            ),
+            messages_dropped: register(
+                &registry,
+                IntCounter::new(
+                    "publish_messages_dropped_total",
+                    "PUBLISH packets not authorized or not delivered",
+                )
+                .unwrap(),
+            ),
+            publish_bytes_received: register(
+                &registry,
+                IntCounter::new(
+                    "publish_bytes_received_total",
+                    "Payload bytes of received PUBLISH packets",
+                )
+                .unwrap(),
+            ),
+            publish_bytes_sent: register(
+                &registry,
+                IntCounter::new(
+                    "publish_bytes_sent_total",
+                    "Payload bytes of sent PUBLISH packets",
+                )
+                .unwrap(),
+            ),
+            publish_fanout: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new(
+                        "publish_fanout",
+                        "Number of subscribers a message was delivered to",
+                    )
+                    .buckets(vec![0.0, 1.0, 2.0, 5.0, 10.0, 100.0, 1000.0]),
+                )
+                .unwrap(),
+            ),
+            authentication_failures: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("authentication_failures_total", "Rejected CONNECT packets"),
+                    &["reason"],
+                )
+                .unwrap(),
+            ),
+            audit_records_dropped: register(
+                &registry,
+                IntCounter::new(
+                    "audit_records_dropped_total",
+                    "Audit records lost because the writer fell behind",
+                )
+                .unwrap(),
+            ),
+            channel_send_seconds: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "channel_send_seconds",
+                        "Time waiting for a free slot in a channel",
+                    )
+                    .buckets(latency_buckets.clone()),
+                    &["channel"],
+                )
+                .unwrap(),
+            ),
+            messaging_queue_seconds: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new(
+                        "messaging_queue_seconds",
+                        "Time an operation waits for the messaging service",
+                    )
+                    .buckets(latency_buckets),
+                )
+                .unwrap(),
+            ),
+            registry,
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Metrics in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        self.uptime_seconds.set(self.uptime().as_secs() as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Metrics encoding failed");
+
+        String::from_utf8(buffer).expect("Metrics are not UTF-8")
+    }
+}
+
+/// Metrics of every session labeled by the client id, encoded from a snapshot of the sessions
+/// on each request, so the series of removed sessions do not linger in a registry.
+pub fn encode_clients(sessions: &[SessionInfo]) -> String {
+    let registry =
+        Registry::new_custom(Some(NAMESPACE.to_string()), None).expect("Invalid metrics namespace");
+    let counter = |name: &str, help: &str| {
+        register(
+            &registry,
+            IntCounterVec::new(Opts::new(name, help), &["client_id"]).unwrap(),
+        )
+    };
+    let packets_received = counter("client_packets_received_total", "Packets of the client");
+    let packets_sent = counter("client_packets_sent_total", "Packets sent to the client");
+    let bytes_received = counter("client_bytes_received_total", "Bytes read from the client");
+    let bytes_sent = counter("client_bytes_sent_total", "Bytes written to the client");
+    let messages_dropped = counter(
+        "client_messages_dropped_total",
+        "PUBLISH packets of the client not authorized or not delivered",
+    );
+    let queued_packets = register(
+        &registry,
+        IntGaugeVec::new(
+            Opts::new(
+                "client_queued_packets",
+                "Packets waiting to be written to the client",
+            ),
+            &["client_id"],
+        )
+        .unwrap(),
+    );
+
+    for session in sessions {
+        let labels = [session.client_id.as_str()];
+        packets_received
+            .with_label_values(&labels)
+            .inc_by(session.packets_received);
+        packets_sent
+            .with_label_values(&labels)
+            .inc_by(session.packets_sent);
+        bytes_received
+            .with_label_values(&labels)
+            .inc_by(session.bytes_received);
+        bytes_sent
+            .with_label_values(&labels)
+            .inc_by(session.bytes_sent);
+        messages_dropped
+            .with_label_values(&labels)
+            .inc_by(session.messages_dropped);
+        queued_packets
+            .with_label_values(&labels)
+            .set(session.queued_packets as i64);
+    }
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&registry.gather(), &mut buffer)
+        .expect("Metrics encoding failed");
+
+    String::from_utf8(buffer).expect("Metrics are not UTF-8")
+}
+
+impl Default for BrokerMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn register<C: Collector + Clone + 'static>(registry: &Registry, collector: C) -> C {
+    registry
+        .register(Box::new(collector.clone()))
+        .expect("Metric registered twice");
+    collector
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_encode() {
+        let metrics = BrokerMetrics::new();
+        metrics.connections.inc();
+        metrics
+            .packets_received
+            .with_label_values(&["CONNECT"])
+            .inc_by(2);
+        metrics.publish_fanout.observe(3.0);
+
+        let text = metrics.encode();
+
+        assert!(text.contains("ratelmq_connections_total 1\n"));
+        assert!(text.contains("ratelmq_packets_received_total{type=\"CONNECT\"} 2\n"));
+        assert!(text.contains("ratelmq_publish_fanout_bucket{le=\"5\"} 1\n"));
+        assert!(text.contains("ratelmq_uptime_seconds 0\n"));
+        assert!(text.contains(&format!(
+            "ratelmq_build_info{{commit=\"{}\",version=\"{}\"}} 1\n",
+            BUILD_INFO.commit_hash, BUILD_INFO.version
+        )));
+    }
+
+    #[test]
+    fn test_encode_clients() {
+        let session = SessionInfo {
+            client_id: "sensor-1".to_string(),
+            ip: "127.0.0.1".parse().unwrap(),
+            persistent: false,
+            keep_alive_seconds: 60,
+            protocol_version: None,
+            connected_at: Utc::now(),
+            last_activity: Utc::now(),
+            packets_received: 3,
+            packets_sent: 2,
+            bytes_received: 100,
+            bytes_sent: 10,
+            messages_dropped: 1,
+            queued_packets: 4,
+        };
+
+        let text = encode_clients(&[session]);
+
+        assert!(text.contains("ratelmq_client_packets_received_total{client_id=\"sensor-1\"} 3\n"));
+        assert!(text.contains("ratelmq_client_bytes_sent_total{client_id=\"sensor-1\"} 10\n"));
+        assert!(text.contains("ratelmq_client_messages_dropped_total{client_id=\"sensor-1\"} 1\n"));
+        assert!(text.contains("ratelmq_client_queued_packets{client_id=\"sensor-1\"} 4\n"));
+        assert_eq!(encode_clients(&[]), "");
+    }
+}
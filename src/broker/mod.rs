@@ -1,5 +1,13 @@
+pub mod audit;
 pub mod authentication;
+pub mod authorization;
 pub mod client_packet_handler;
 pub mod keepalive_checker;
+pub mod login_guard;
 pub mod messaging;
+pub mod metrics;
+pub mod mountpoint;
+pub mod reload;
 pub mod session;
+pub mod sys_topics;
+pub mod webhook;
@@ -0,0 +1,127 @@
+use crate::mqtt::packets::ClientId;
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Mountpoint {
+    prefix: String,
+}
+
+impl Mountpoint {
+    /// Builds a mountpoint from the configured pattern, see `substitute`.
+    pub fn new(pattern: &str, client_id: &ClientId, user_name: Option<&str>) -> Option<Self> {
+        let prefix = substitute(pattern, client_id, user_name)?;
+
+        Some(Mountpoint { prefix })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefix.is_empty()
+    }
+
+    pub fn mount(&self, topic: &str) -> String {
+        format!("{}{}", self.prefix, topic)
+    }
+
+    /// Returns the topic as seen from inside of the mountpoint or `None` if the topic
+    /// does not belong to the mountpoint.
+    pub fn unmount<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        topic.strip_prefix(self.prefix.as_str())
+    }
+}
+
+/// Replaces `%u` with the user name and `%c` with the client id in the topic pattern.
+/// The pattern is read once, so the substituted values are never substituted again.
+///
+/// Returns `None` when the pattern requires a value which is missing or which would
+/// change the structure of the topic (contains `/`, `+`, `#` or `%`).
+pub fn substitute(pattern: &str, client_id: &ClientId, user_name: Option<&str>) -> Option<String> {
+    let mut topic = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            topic.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('u') => topic.push_str(segment(user_name?)?),
+            Some('c') => topic.push_str(segment(client_id)?),
+            Some(other) => {
+                topic.push('%');
+                topic.push(other);
+            }
+            None => topic.push('%'),
+        }
+    }
+
+    Some(topic)
+}
+
+fn segment(value: &str) -> Option<&str> {
+    let invalid = value.is_empty() || value.contains(['/', '+', '#', '%']);
+
+    match invalid {
+        true => None,
+        false => Some(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mount_and_unmount() {
+        let mountpoint = Mountpoint::new("tenant-a/", &"c1".to_string(), None).unwrap();
+
+        assert_eq!(mountpoint.mount("a/b"), "tenant-a/a/b");
+        assert_eq!(mountpoint.unmount("tenant-a/a/b"), Some("a/b"));
+        assert_eq!(mountpoint.unmount("tenant-b/a/b"), None);
+    }
+
+    #[test]
+    fn test_empty_mountpoint() {
+        let mountpoint = Mountpoint::default();
+
+        assert!(mountpoint.is_empty());
+        assert_eq!(mountpoint.mount("a/b"), "a/b");
+        assert_eq!(mountpoint.unmount("a/b"), Some("a/b"));
+    }
+
+    #[test]
+    fn test_substitution() {
+        let mountpoint = Mountpoint::new("users/%u/%c/", &"c1".to_string(), Some("bob")).unwrap();
+
+        assert_eq!(mountpoint.mount("a"), "users/bob/c1/a");
+    }
+
+    #[test]
+    fn test_substitution_missing_user_name() {
+        assert_eq!(Mountpoint::new("%u/", &"c1".to_string(), None), None);
+    }
+
+    #[test]
+    fn test_substitution_rejects_wildcards_and_separators() {
+        let client_id = "c1".to_string();
+
+        assert_eq!(Mountpoint::new("%u/", &client_id, Some("a/b")), None);
+        assert_eq!(Mountpoint::new("%u/", &client_id, Some("#")), None);
+        assert_eq!(Mountpoint::new("%c/", &"+".to_string(), None), None);
+    }
+
+    #[test]
+    fn test_substituted_values_are_not_substituted_again() {
+        assert_eq!(
+            Mountpoint::new("tenants/%u/", &"alice".to_string(), Some("%c")),
+            None
+        );
+        assert_eq!(
+            Mountpoint::new("tenants/%c/", &"%u".to_string(), Some("alice")),
+            None
+        );
+        assert_eq!(
+            substitute("%u/100%/%x%", &"c1".to_string(), Some("bob")),
+            Some("bob/100%/%x%".to_string())
+        );
+    }
+}
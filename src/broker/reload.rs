@@ -0,0 +1,170 @@
+use crate::broker::authentication::jwt::JwtIdentityProviderError;
+use crate::broker::authentication::FileIdentityManagerError;
+use crate::broker::authorization::FileAuthorizerError;
+use crate::broker::webhook::WebhookError;
+use crate::settings::{IdentityProviderSettings, Settings};
+use log::{debug, error, info};
+use std::time::{Duration, SystemTime};
+use tokio::select;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::interval;
+
+#[derive(Debug)]
+pub enum ReloadError {
+    IdentityProvider(FileIdentityManagerError),
+    JwtIdentityProvider(JwtIdentityProviderError),
+    Authorizer(FileAuthorizerError),
+    Webhook(WebhookError),
+    /// Both ACL file and webhook are configured for authorization.
+    ConflictingAuthorizers,
+    /// Chain refers to an identity provider which is not configured or is a chain itself.
+    InvalidChain(String),
+}
+
+impl From<FileIdentityManagerError> for ReloadError {
+    fn from(error: FileIdentityManagerError) -> Self {
+        ReloadError::IdentityProvider(error)
+    }
+}
+
+impl From<JwtIdentityProviderError> for ReloadError {
+    fn from(error: JwtIdentityProviderError) -> Self {
+        ReloadError::JwtIdentityProvider(error)
+    }
+}
+
+impl From<FileAuthorizerError> for ReloadError {
+    fn from(error: FileAuthorizerError) -> Self {
+        ReloadError::Authorizer(error)
+    }
+}
+
+impl From<WebhookError> for ReloadError {
+    fn from(error: WebhookError) -> Self {
+        ReloadError::Webhook(error)
+    }
+}
+
+/// Files which are read again on reload.
+pub fn watched_files(settings: &Settings) -> Vec<String> {
+    let mut files = vec![settings.authentication.password_file.clone()];
+
+    for provider in settings.authentication.identity_providers.values() {
+        match provider {
+            IdentityProviderSettings::File { password_file } => files.push(password_file.clone()),
+            IdentityProviderSettings::Jwt(jwt) => {
+                files.extend(jwt.key_file.iter().chain(jwt.jwks_file.iter()).cloned())
+            }
+            IdentityProviderSettings::Webhook(_) | IdentityProviderSettings::Chain { .. } => {}
+        }
+    }
+
+    if let Some(acl_file) = &settings.authorization.acl_file {
+        files.push(acl_file.clone());
+    }
+
+    files
+}
+
+#[cfg(unix)]
+pub async fn reload_on_signal(reload_tx: mpsc::Sender<()>, mut ctrl_c_rx: broadcast::Receiver<()>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!(
+                "Unable to listen for SIGHUP, reload on signal is disabled: {}",
+                &e
+            );
+            return;
+        }
+    };
+
+    loop {
+        select! {
+            _ = ctrl_c_rx.recv() => break,
+            _ = hangup.recv() => {
+                info!("Received SIGHUP");
+                if reload_tx.send(()).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    debug!("Stopped reload on signal");
+}
+
+#[cfg(not(unix))]
+pub async fn reload_on_signal(_reload_tx: mpsc::Sender<()>, _ctrl_c_rx: broadcast::Receiver<()>) {}
+
+/// Polls the files and requests reload when modification time or size of any of them changes.
+pub async fn reload_on_file_change(
+    files: Vec<String>,
+    period: Duration,
+    reload_tx: mpsc::Sender<()>,
+    mut ctrl_c_rx: broadcast::Receiver<()>,
+) {
+    let mut versions: Vec<_> = files.iter().map(|file| file_version(file)).collect();
+    let mut ticks = interval(period);
+
+    loop {
+        select! {
+            _ = ctrl_c_rx.recv() => break,
+            _ = ticks.tick() => {
+                let current: Vec<_> = files.iter().map(|file| file_version(file)).collect();
+
+                if current != versions {
+                    info!("Watched files changed");
+                    versions = current;
+
+                    if reload_tx.send(()).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    debug!("Stopped reload on file change");
+}
+
+fn file_version(file: &str) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(file).ok()?;
+
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_reload_on_file_change() {
+        let file = std::env::temp_dir().join(format!("ratelmq-reload-{}", std::process::id()));
+        let filename = file.to_str().unwrap().to_string();
+        std::fs::write(&file, "user:hash").unwrap();
+
+        let (reload_tx, mut reload_rx) = mpsc::channel(1);
+        let (ctrl_c_tx, ctrl_c_rx) = broadcast::channel(1);
+        let watcher = tokio::spawn(reload_on_file_change(
+            vec![filename],
+            Duration::from_millis(10),
+            reload_tx,
+            ctrl_c_rx,
+        ));
+
+        let unchanged = timeout(Duration::from_millis(50), reload_rx.recv()).await;
+        assert!(unchanged.is_err(), "must not reload when nothing changed");
+
+        std::fs::write(&file, "user:hash\nuser2:hash").unwrap();
+        let changed = timeout(Duration::from_secs(1), reload_rx.recv()).await;
+        assert_eq!(changed, Ok(Some(())));
+
+        ctrl_c_tx.send(()).unwrap();
+        watcher.await.unwrap();
+        std::fs::remove_file(&file).unwrap();
+    }
+}
@@ -1,8 +1,10 @@
 mod session_entity;
 pub(crate) mod session_repository;
 mod session_service;
+mod statistics;
 
 pub use self::session_entity::Session;
 pub use self::session_repository::InMemorySessionRepository;
 pub use self::session_repository::SessionRepository;
 pub use self::session_service::SessionService;
+pub use self::statistics::ClientStatistics;
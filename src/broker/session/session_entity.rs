@@ -1,7 +1,10 @@
+use crate::broker::mountpoint::Mountpoint;
+use crate::broker::session::ClientStatistics;
 use crate::mqtt::events::ServerEvent;
 use crate::mqtt::packets::ClientId;
 use chrono::{DateTime, Duration, Utc};
 use std::net::IpAddr;
+use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
 
 #[derive(Debug)]
@@ -11,7 +14,9 @@ pub struct Session {
     persistent: bool,
     sender: Sender<ServerEvent>,
     pub keep_alive_seconds: u16,
-    last_activity: DateTime<Utc>,
+    mountpoint: Mountpoint,
+    /// Shared with the connection, which keeps it up to date.
+    statistics: Arc<ClientStatistics>,
 }
 
 impl Session {
@@ -21,7 +26,8 @@ impl Session {
         persistent: bool,
         sender: Sender<ServerEvent>,
         keep_alive_seconds: u16,
-        last_activity: DateTime<Utc>,
+        mountpoint: Mountpoint,
+        statistics: Arc<ClientStatistics>,
     ) -> Self {
         Session {
             client_id,
@@ -29,7 +35,8 @@ impl Session {
             persistent,
             sender,
             keep_alive_seconds,
-            last_activity,
+            mountpoint,
+            statistics,
         }
     }
 
@@ -41,12 +48,27 @@ impl Session {
         &self.client_id
     }
 
-    pub fn set_last_activity(&mut self, last_activity: DateTime<Utc>) {
-        self.last_activity = last_activity;
+    pub fn ip(&self) -> IpAddr {
+        self.ip
     }
 
-    pub fn last_activity(&self) -> &DateTime<Utc> {
-        &self.last_activity
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    /// Mountpoint of the connection, the subscriptions of the session are already mounted.
+    pub fn mountpoint(&self) -> &Mountpoint {
+        &self.mountpoint
+    }
+
+    pub fn statistics(&self) -> &Arc<ClientStatistics> {
+        &self.statistics
+    }
+
+    /// Packets waiting to be written to the connection. Not the in-flight QoS 1 and 2 messages,
+    /// their acknowledgements are not tracked yet.
+    pub fn queued_packets(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
     }
 
     pub fn is_keep_alive_expired(&self, now: &DateTime<Utc>) -> bool {
@@ -55,7 +77,8 @@ impl Session {
         }
 
         let leeway_seconds = (self.keep_alive_seconds as f32 * 1.5) as i64;
-        let keep_alive_expires_at = self.last_activity + Duration::seconds(leeway_seconds);
+        let keep_alive_expires_at =
+            self.statistics.last_activity() + Duration::seconds(leeway_seconds);
 
         &keep_alive_expires_at <= now
     }
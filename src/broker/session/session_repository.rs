@@ -65,8 +65,10 @@ impl Default for InMemorySessionRepository {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::{DateTime, Utc};
+    use crate::broker::mountpoint::Mountpoint;
+    use crate::broker::session::ClientStatistics;
     use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Arc;
     use tokio::sync::mpsc;
 
     fn create_session() -> Session {
@@ -77,7 +79,8 @@ mod tests {
             false,
             tx,
             0,
-            Utc::now(),
+            Mountpoint::default(),
+            Arc::new(ClientStatistics::new()),
         )
     }
 
@@ -0,0 +1,129 @@
+use crate::mqtt::packets::ProtocolVersion;
+use chrono::{DateTime, TimeZone, Utc};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use tokio::sync::OnceCell;
+
+/// Counters of a single connection, updated by its read and write tasks and by the handler
+/// without any locking and read by the management API.
+#[derive(Debug)]
+pub struct ClientStatistics {
+    connected_at: DateTime<Utc>,
+    /// Known once the CONNECT packet is read.
+    protocol_version: OnceCell<ProtocolVersion>,
+    /// Milliseconds since the epoch of the last packet received from the client.
+    last_activity: AtomicI64,
+    packets_received: AtomicU64,
+    packets_sent: AtomicU64,
+    /// Including the MQTT framing.
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    /// PUBLISH packets of the client which were not authorized and messages for the client
+    /// which were not delivered because its connection was gone.
+    messages_dropped: AtomicU64,
+}
+
+impl ClientStatistics {
+    pub fn new() -> ClientStatistics {
+        let now = Utc::now();
+
+        ClientStatistics {
+            connected_at: now,
+            protocol_version: OnceCell::new(),
+            last_activity: AtomicI64::new(now.timestamp_millis()),
+            packets_received: AtomicU64::new(0),
+            packets_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            messages_dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_protocol_version(&self, protocol_version: ProtocolVersion) {
+        let _ = self.protocol_version.set(protocol_version);
+    }
+
+    pub fn add_received(&self, bytes: u64, packet: bool) {
+        self.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+        if packet {
+            self.packets_received.fetch_add(1, Ordering::Relaxed);
+            self.last_activity
+                .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn add_sent(&self, bytes: u64, packet: bool) {
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+        if packet {
+            self.packets_sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn add_dropped(&self) {
+        self.messages_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connected_at(&self) -> DateTime<Utc> {
+        self.connected_at
+    }
+
+    pub fn protocol_version(&self) -> Option<&ProtocolVersion> {
+        self.protocol_version.get()
+    }
+
+    pub fn last_activity(&self) -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(self.last_activity.load(Ordering::Relaxed))
+            .unwrap()
+    }
+
+    pub fn packets_received(&self) -> u64 {
+        self.packets_received.load(Ordering::Relaxed)
+    }
+
+    pub fn packets_sent(&self) -> u64 {
+        self.packets_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn messages_dropped(&self) -> u64 {
+        self.messages_dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for ClientStatistics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        let statistics = ClientStatistics::new();
+        statistics.set_protocol_version(ProtocolVersion::Mqtt5);
+        statistics.set_protocol_version(ProtocolVersion::Mqtt3);
+
+        // a partially read packet counts only its bytes
+        statistics.add_received(10, false);
+        statistics.add_received(20, true);
+        statistics.add_sent(4, true);
+        statistics.add_dropped();
+
+        assert_eq!(statistics.protocol_version(), Some(&ProtocolVersion::Mqtt5));
+        assert_eq!(statistics.bytes_received(), 30);
+        assert_eq!(statistics.packets_received(), 1);
+        assert_eq!(statistics.bytes_sent(), 4);
+        assert_eq!(statistics.packets_sent(), 1);
+        assert_eq!(statistics.messages_dropped(), 1);
+        assert!(statistics.last_activity() <= Utc::now());
+    }
+}
@@ -0,0 +1,100 @@
+use crate::broker::metrics::BrokerMetrics;
+use crate::config::build_info::BUILD_INFO;
+
+/// Whether the topic belongs to the `$SYS` tree of the broker, which clients must not publish on,
+/// like mosquitto does. Other topics starting with `$` are not reserved for publishing.
+pub fn is_sys_topic(topic: &str) -> bool {
+    topic.starts_with("$SYS/")
+}
+
+/// Topics and payloads of the periodically published `$SYS` tree, named like the ones
+/// of mosquitto so existing dashboards work.
+pub fn sys_messages(metrics: &BrokerMetrics) -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "$SYS/broker/version",
+            format!("RatelMQ version {}", BUILD_INFO.version),
+        ),
+        (
+            "$SYS/broker/uptime",
+            format!("{} seconds", metrics.uptime().as_secs()),
+        ),
+        (
+            "$SYS/broker/clients/connected",
+            metrics.clients_connected.get().to_string(),
+        ),
+        (
+            "$SYS/broker/clients/total",
+            metrics.sessions.get().to_string(),
+        ),
+        (
+            "$SYS/broker/subscriptions/count",
+            metrics.subscriptions.get().to_string(),
+        ),
+        (
+            "$SYS/broker/retained messages/count",
+            metrics.retained_messages.get().to_string(),
+        ),
+        (
+            "$SYS/broker/publish/messages/received",
+            metrics.messages_received.get().to_string(),
+        ),
+        (
+            "$SYS/broker/publish/messages/sent",
+            metrics.messages_sent.get().to_string(),
+        ),
+        (
+            "$SYS/broker/publish/messages/dropped",
+            metrics.messages_dropped.get().to_string(),
+        ),
+        (
+            "$SYS/broker/publish/bytes/received",
+            metrics.publish_bytes_received.get().to_string(),
+        ),
+        (
+            "$SYS/broker/publish/bytes/sent",
+            metrics.publish_bytes_sent.get().to_string(),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sys_messages() {
+        let metrics = BrokerMetrics::new();
+        metrics.clients_connected.set(3);
+        metrics.publish_bytes_received.inc_by(10);
+        metrics.publish_bytes_received.inc_by(5);
+
+        let messages = sys_messages(&metrics);
+        let payload = |topic| {
+            messages
+                .iter()
+                .find(|(t, _)| *t == topic)
+                .map(|(_, payload)| payload.as_str())
+        };
+
+        assert_eq!(
+            payload("$SYS/broker/version"),
+            Some(format!("RatelMQ version {}", BUILD_INFO.version).as_str())
+        );
+        assert_eq!(payload("$SYS/broker/uptime"), Some("0 seconds"));
+        assert_eq!(payload("$SYS/broker/clients/connected"), Some("3"));
+        assert_eq!(payload("$SYS/broker/publish/bytes/received"), Some("15"));
+        assert_eq!(payload("$SYS/broker/publish/messages/dropped"), Some("0"));
+        assert!(messages
+            .iter()
+            .all(|(topic, _)| topic.starts_with("$SYS/broker/")));
+    }
+
+    #[test]
+    fn test_is_sys_topic() {
+        assert!(is_sys_topic("$SYS/broker/uptime"));
+        assert!(!is_sys_topic("$share/group/a"));
+        assert!(!is_sys_topic("$devices/a"));
+        assert!(!is_sys_topic("SYS/a"));
+    }
+}
@@ -0,0 +1,636 @@
+use crate::broker::authentication::{AuthenticationError, Credentials, Identity, IdentityProvider};
+use crate::broker::authorization::{Access, AuthorizationRequest, Authorizer};
+use crate::settings::WebhookSettings;
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::warn;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Expired entries are purged when the cache grows beyond this size.
+const CACHE_PURGE_SIZE: usize = 10_000;
+
+#[derive(Debug)]
+pub enum WebhookError {
+    Request(reqwest::Error),
+    UnexpectedStatus(StatusCode),
+}
+
+impl From<reqwest::Error> for WebhookError {
+    fn from(error: reqwest::Error) -> Self {
+        WebhookError::Request(error)
+    }
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookError::Request(e) => write!(f, "webhook request failed: {}", e),
+            WebhookError::UnexpectedStatus(status) => {
+                write!(f, "webhook returned unexpected status {}", status)
+            }
+        }
+    }
+}
+
+/// Identifies the requests sharing a result. The port changes with every connection and is left
+/// out, passwords are only kept hashed.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    client_id: String,
+    username: Option<String>,
+    ip: IpAddr,
+    password_hash: Option<[u8; 32]>,
+    topic: Option<String>,
+    action: Option<&'static str>,
+}
+
+impl CacheKey {
+    fn authentication(credentials: &Credentials<'_>) -> CacheKey {
+        CacheKey {
+            client_id: credentials.client_id.clone(),
+            username: Some(credentials.user_name.to_string()),
+            ip: credentials.address.ip(),
+            password_hash: Some(Sha256::digest(credentials.password).into()),
+            topic: None,
+            action: None,
+        }
+    }
+
+    fn authorization(request: &AuthorizationRequest<'_>, action: &'static str) -> CacheKey {
+        CacheKey {
+            client_id: request.client_id.clone(),
+            username: request.user_name.map(str::to_string),
+            ip: request.address.ip(),
+            password_hash: None,
+            topic: Some(request.topic.to_string()),
+            action: Some(action),
+        }
+    }
+}
+
+/// Posts the JSON request to the configured URL, 2xx response allows it,
+/// 401 and 403 deny it. Both results are cached for their own TTL, errors are not cached.
+/// 404 means the service has no decision for the request.
+struct Webhook {
+    client: reqwest::Client,
+    url: String,
+    allow_ttl: Duration,
+    deny_ttl: Duration,
+    cache: Mutex<HashMap<CacheKey, (bool, Instant)>>,
+}
+
+impl Webhook {
+    fn new(settings: &WebhookSettings) -> Result<Webhook, WebhookError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(settings.timeout_ms))
+            .build()?;
+
+        Ok(Webhook {
+            client,
+            url: settings.url.clone(),
+            allow_ttl: Duration::from_secs(settings.allow_ttl_seconds),
+            deny_ttl: Duration::from_secs(settings.deny_ttl_seconds),
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    async fn is_allowed(
+        &self,
+        key: CacheKey,
+        request: &Value,
+    ) -> Result<Option<bool>, WebhookError> {
+        if let Some(allowed) = self.cached(&key) {
+            return Ok(Some(allowed));
+        }
+
+        let response = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(request.to_string())
+            .send()
+            .await?;
+
+        let allowed = match response.status() {
+            status if status.is_success() => true,
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => false,
+            StatusCode::NOT_FOUND => return Ok(None),
+            status => return Err(WebhookError::UnexpectedStatus(status)),
+        };
+
+        self.cache(key, allowed);
+
+        Ok(Some(allowed))
+    }
+
+    fn cached(&self, key: &CacheKey) -> Option<bool> {
+        let cache = self.cache.lock().unwrap();
+
+        match cache.get(key) {
+            Some((allowed, expires_at)) if *expires_at > Instant::now() => Some(*allowed),
+            _ => None,
+        }
+    }
+
+    fn cache(&self, key: CacheKey, allowed: bool) {
+        let ttl = if allowed {
+            self.allow_ttl
+        } else {
+            self.deny_ttl
+        };
+        if ttl.is_zero() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+
+        if cache.len() >= CACHE_PURGE_SIZE {
+            cache.retain(|_, (_, expires_at)| *expires_at > now);
+        }
+
+        cache.insert(key, (allowed, now + ttl));
+    }
+}
+
+/// Authenticates clients with an external HTTP service.
+pub struct WebhookIdentityProvider {
+    webhook: Webhook,
+}
+
+impl WebhookIdentityProvider {
+    pub fn new(settings: &WebhookSettings) -> Result<WebhookIdentityProvider, WebhookError> {
+        Ok(WebhookIdentityProvider {
+            webhook: Webhook::new(settings)?,
+        })
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for WebhookIdentityProvider {
+    async fn authenticate(
+        &self,
+        credentials: &Credentials<'_>,
+    ) -> Result<Identity, AuthenticationError> {
+        let mut request = json!({
+            "client_id": credentials.client_id,
+            "username": credentials.user_name,
+            "ip": credentials.address.ip().to_string(),
+            "port": credentials.address.port(),
+        });
+        match std::str::from_utf8(credentials.password) {
+            Ok(password) => request["password"] = json!(password),
+            Err(_) => request["password_base64"] = json!(STANDARD.encode(credentials.password)),
+        }
+
+        let key = CacheKey::authentication(credentials);
+        match self.webhook.is_allowed(key, &request).await {
+            Ok(Some(true)) => Ok(Identity::default()),
+            Ok(Some(false)) => Err(AuthenticationError::InvalidPassword),
+            Ok(None) => Err(AuthenticationError::UserNotFound),
+            Err(e) => Err(AuthenticationError::Unavailable(e)),
+        }
+    }
+
+    fn contains_user(&self, _username: &str) -> bool {
+        // users are managed by the external service
+        true
+    }
+}
+
+/// Authorizes topic access with an external HTTP service, access is denied when the service fails.
+pub struct WebhookAuthorizer {
+    webhook: Webhook,
+}
+
+impl WebhookAuthorizer {
+    pub fn new(settings: &WebhookSettings) -> Result<WebhookAuthorizer, WebhookError> {
+        Ok(WebhookAuthorizer {
+            webhook: Webhook::new(settings)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Authorizer for WebhookAuthorizer {
+    async fn authorize(&self, request: &AuthorizationRequest<'_>) -> bool {
+        let action = match request.access {
+            Access::Read => "subscribe",
+            Access::Write => "publish",
+        };
+        let body = json!({
+            "client_id": request.client_id,
+            "username": request.user_name,
+            "ip": request.address.ip().to_string(),
+            "port": request.address.port(),
+            "topic": request.topic,
+            "action": action,
+        });
+
+        let key = CacheKey::authorization(request, action);
+        match self.webhook.is_allowed(key, &body).await {
+            Ok(allowed) => allowed.unwrap_or(false),
+            Err(e) => {
+                warn!(
+                    "Denying access of client {:?} to topic {:?}, {}",
+                    request.client_id, request.topic, &e
+                );
+                false
+            }
+        }
+    }
+
+    fn is_remote(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Minimal HTTP server answering every request with the status returned by `respond`.
+    struct StubServer {
+        address: SocketAddr,
+        requests: Arc<Mutex<Vec<Value>>>,
+    }
+
+    impl StubServer {
+        async fn start(respond: fn(&Value) -> u16) -> StubServer {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let requests = Arc::new(Mutex::new(Vec::new()));
+
+            let received = requests.clone();
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    let body = read_body(&mut stream).await;
+                    let request: Value = serde_json::from_slice(&body).unwrap();
+                    let status = respond(&request);
+                    received.lock().unwrap().push(request);
+
+                    let response = format!(
+                        "HTTP/1.1 {} Stub\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        status
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                }
+            });
+
+            StubServer { address, requests }
+        }
+
+        fn settings(&self, allow_ttl_seconds: u64, deny_ttl_seconds: u64) -> WebhookSettings {
+            WebhookSettings {
+                url: format!("http://{}/mqtt", self.address),
+                timeout_ms: 1000,
+                allow_ttl_seconds,
+                deny_ttl_seconds,
+            }
+        }
+
+        fn requests(&self) -> Vec<Value> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    async fn read_body(stream: &mut tokio::net::TcpStream) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut buffer = [0u8; 1024];
+
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            data.extend_from_slice(&buffer[..read]);
+
+            if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                let headers = String::from_utf8_lossy(&data[..end]).to_lowercase();
+                let length: usize = headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map(|length| length.trim().parse().unwrap())
+                    .unwrap_or(0);
+
+                if data.len() >= end + 4 + length {
+                    return data[end + 4..end + 4 + length].to_vec();
+                }
+            }
+        }
+    }
+
+    fn credentials<'a>(client_id: &'a String, password: &'a [u8]) -> Credentials<'a> {
+        Credentials {
+            client_id,
+            user_name: "alice",
+            password,
+            address: "10.0.0.1:51234".parse().unwrap(),
+        }
+    }
+
+    /// Same client reconnecting, its source port differs.
+    fn reconnected(request: AuthorizationRequest<'_>) -> AuthorizationRequest<'_> {
+        AuthorizationRequest {
+            address: "10.0.0.1:51235".parse().unwrap(),
+            ..request
+        }
+    }
+
+    fn authorization_request<'a>(
+        client_id: &'a String,
+        topic: &'a str,
+        access: Access,
+    ) -> AuthorizationRequest<'a> {
+        AuthorizationRequest {
+            client_id,
+            user_name: Some("alice"),
+            address: "10.0.0.1:51234".parse().unwrap(),
+            topic,
+            access,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let server = StubServer::start(|request| {
+            if request["password"] == "secret" {
+                200
+            } else {
+                401
+            }
+        })
+        .await;
+        let provider = WebhookIdentityProvider::new(&server.settings(0, 0)).unwrap();
+        let client_id = "client-1".to_string();
+
+        let result = provider
+            .authenticate(&credentials(&client_id, b"secret"))
+            .await;
+        assert!(result.is_ok());
+
+        let result = provider
+            .authenticate(&credentials(&client_id, b"wrong"))
+            .await;
+        assert!(matches!(result, Err(AuthenticationError::InvalidPassword)));
+
+        let requests = server.requests();
+        assert_eq!(
+            requests[0],
+            json!({
+                "client_id": "client-1",
+                "username": "alice",
+                "password": "secret",
+                "ip": "10.0.0.1",
+                "port": 51234,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_binary_password_is_base64_encoded() {
+        let server = StubServer::start(|_| 200).await;
+        let provider = WebhookIdentityProvider::new(&server.settings(0, 0)).unwrap();
+        let client_id = "client-1".to_string();
+
+        let result = provider
+            .authenticate(&credentials(&client_id, &[0xff, 0x00]))
+            .await;
+        assert!(result.is_ok());
+
+        let requests = server.requests();
+        assert_eq!(requests[0]["password_base64"], "/wA=");
+        assert!(requests[0].get("password").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_not_found_ignores_user() {
+        let server = StubServer::start(|_| 404).await;
+        let provider = WebhookIdentityProvider::new(&server.settings(60, 60)).unwrap();
+        let client_id = "client-1".to_string();
+
+        let result = provider
+            .authenticate(&credentials(&client_id, b"secret"))
+            .await;
+        assert!(matches!(result, Err(AuthenticationError::UserNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_server_error_is_unavailable() {
+        let server = StubServer::start(|_| 500).await;
+        let provider = WebhookIdentityProvider::new(&server.settings(60, 60)).unwrap();
+        let client_id = "client-1".to_string();
+
+        for _ in 0..2 {
+            let result = provider
+                .authenticate(&credentials(&client_id, b"secret"))
+                .await;
+            assert!(matches!(
+                result,
+                Err(AuthenticationError::Unavailable(
+                    WebhookError::UnexpectedStatus(StatusCode::INTERNAL_SERVER_ERROR)
+                ))
+            ));
+        }
+
+        assert_eq!(server.requests().len(), 2, "errors must not be cached");
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_is_unavailable() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let settings = WebhookSettings {
+            url: format!("http://{}/mqtt", listener.local_addr().unwrap()),
+            timeout_ms: 100,
+            allow_ttl_seconds: 0,
+            deny_ttl_seconds: 0,
+        };
+        drop(listener);
+
+        let provider = WebhookIdentityProvider::new(&settings).unwrap();
+        let client_id = "client-1".to_string();
+
+        let result = provider
+            .authenticate(&credentials(&client_id, b"secret"))
+            .await;
+        assert!(matches!(
+            result,
+            Err(AuthenticationError::Unavailable(WebhookError::Request(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_authorize() {
+        let server = StubServer::start(|request| {
+            match (request["action"].as_str(), request["topic"].as_str()) {
+                (Some("subscribe"), Some("public/#")) => 200,
+                (Some("publish"), Some("alice/status")) => 204,
+                _ => 403,
+            }
+        })
+        .await;
+        let authorizer = WebhookAuthorizer::new(&server.settings(0, 0)).unwrap();
+        let client_id = "client-1".to_string();
+
+        assert!(
+            authorizer
+                .authorize(&authorization_request(&client_id, "public/#", Access::Read))
+                .await
+        );
+        assert!(
+            authorizer
+                .authorize(&authorization_request(
+                    &client_id,
+                    "alice/status",
+                    Access::Write
+                ))
+                .await
+        );
+        assert!(
+            !authorizer
+                .authorize(&authorization_request(
+                    &client_id,
+                    "public/#",
+                    Access::Write
+                ))
+                .await
+        );
+
+        assert_eq!(
+            server.requests()[0],
+            json!({
+                "client_id": "client-1",
+                "username": "alice",
+                "ip": "10.0.0.1",
+                "port": 51234,
+                "topic": "public/#",
+                "action": "subscribe",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authorize_denies_on_error() {
+        let server = StubServer::start(|_| 502).await;
+        let authorizer = WebhookAuthorizer::new(&server.settings(60, 60)).unwrap();
+        let client_id = "client-1".to_string();
+
+        assert!(
+            !authorizer
+                .authorize(&authorization_request(&client_id, "a", Access::Read))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_results_are_cached() {
+        let server = StubServer::start(|request| {
+            if request["topic"] == "allowed" {
+                200
+            } else {
+                403
+            }
+        })
+        .await;
+        let authorizer = WebhookAuthorizer::new(&server.settings(60, 60)).unwrap();
+        let client_id = "client-1".to_string();
+
+        for _ in 0..3 {
+            assert!(
+                authorizer
+                    .authorize(&authorization_request(&client_id, "allowed", Access::Read))
+                    .await
+            );
+            assert!(
+                !authorizer
+                    .authorize(&authorization_request(&client_id, "denied", Access::Read))
+                    .await
+            );
+        }
+
+        assert_eq!(server.requests().len(), 2);
+
+        assert!(
+            authorizer
+                .authorize(&reconnected(authorization_request(
+                    &client_id,
+                    "allowed",
+                    Access::Read
+                )))
+                .await
+        );
+        assert_eq!(
+            server.requests().len(),
+            2,
+            "port must not be part of the key"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authentication_cache_key() {
+        let server = StubServer::start(|request| {
+            if request["password"] == "secret" {
+                200
+            } else {
+                401
+            }
+        })
+        .await;
+        let provider = WebhookIdentityProvider::new(&server.settings(60, 60)).unwrap();
+        let client_id = "client-1".to_string();
+
+        assert!(provider
+            .authenticate(&credentials(&client_id, b"secret"))
+            .await
+            .is_ok());
+        let mut reconnect = credentials(&client_id, b"secret");
+        reconnect.address = "10.0.0.1:51235".parse().unwrap();
+        assert!(provider.authenticate(&reconnect).await.is_ok());
+        assert_eq!(server.requests().len(), 1);
+
+        // a cached allow must not let another password in
+        let result = provider
+            .authenticate(&credentials(&client_id, b"wrong"))
+            .await;
+        assert!(matches!(result, Err(AuthenticationError::InvalidPassword)));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_zero_ttl_disables_cache() {
+        let server = StubServer::start(|request| {
+            if request["topic"] == "allowed" {
+                200
+            } else {
+                403
+            }
+        })
+        .await;
+        let authorizer = WebhookAuthorizer::new(&server.settings(60, 0)).unwrap();
+        let client_id = "client-1".to_string();
+
+        for _ in 0..3 {
+            authorizer
+                .authorize(&authorization_request(&client_id, "allowed", Access::Read))
+                .await;
+            authorizer
+                .authorize(&authorization_request(&client_id, "denied", Access::Read))
+                .await;
+        }
+
+        let requests = server.requests();
+        let denied = requests.iter().filter(|r| r["topic"] == "denied").count();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(denied, 3);
+    }
+}
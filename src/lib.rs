@@ -2,10 +2,13 @@ extern crate core;
 
 pub mod broker;
 pub mod config;
+pub mod management;
 pub mod mqtt;
 pub mod settings;
 
 mod application;
+mod logging;
+mod telemetry;
 
 pub async fn run(config_filename: &str) {
     application::run(config_filename).await;
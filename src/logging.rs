@@ -0,0 +1,207 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+
+use chrono::{SecondsFormat, Utc};
+use env_logger::fmt::Formatter;
+use log::kv::{Key, Value, VisitSource, VisitValue};
+use log::Record;
+use serde_json::Map;
+
+use crate::settings::{LogFormat, LogSettings};
+
+/// Installs the global logger, RUST_LOG overrides the configured level.
+///
+/// Records about clients carry the `client_id`, `user_name`, `remote_address`, `listener` and
+/// `packet_type` fields when known, e.g. `info!(client_id = client_id.as_str(); "...")`.
+pub fn init(settings: &LogSettings) {
+    let mut builder = env_logger::Builder::new();
+
+    builder.parse_filters(&settings.level);
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+
+    match settings.format {
+        LogFormat::Text => builder.format(|buf: &mut Formatter, record: &Record| {
+            writeln!(buf, "{}", text_line(&timestamp(), record))
+        }),
+        LogFormat::Json => builder.format(|buf: &mut Formatter, record: &Record| {
+            writeln!(buf, "{}", json_line(&timestamp(), record))
+        }),
+    };
+
+    builder.init();
+}
+
+fn timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// `[timestamp LEVEL target] message key=value...`, values with spaces are quoted.
+fn text_line(timestamp: &str, record: &Record) -> String {
+    let mut line = format!(
+        "[{} {:<5} {}] {}",
+        timestamp,
+        record.level(),
+        record.target(),
+        record.args()
+    );
+
+    for (key, value) in fields(record) {
+        let value = match value {
+            serde_json::Value::String(s) if s.is_empty() || s.contains(char::is_whitespace) => {
+                format!("{:?}", s)
+            }
+            serde_json::Value::String(s) => s,
+            value => value.to_string(),
+        };
+        let _ = write!(line, " {}={}", key, value);
+    }
+
+    line
+}
+
+fn json_line(timestamp: &str, record: &Record) -> String {
+    let mut object = Map::new();
+    object.insert("timestamp".to_string(), timestamp.into());
+    object.insert("level".to_string(), record.level().as_str().into());
+    object.insert("target".to_string(), record.target().into());
+    object.insert("message".to_string(), record.args().to_string().into());
+
+    for (key, value) in fields(record) {
+        object.insert(key, value);
+    }
+
+    serde_json::Value::Object(object).to_string()
+}
+
+/// Key-values of the record, numbers and booleans keep their JSON types and `None` values are
+/// left out.
+fn fields(record: &Record) -> Vec<(String, serde_json::Value)> {
+    let mut visitor = FieldsVisitor(Vec::new());
+    let _ = record.key_values().visit(&mut visitor);
+    visitor.0
+}
+
+struct FieldsVisitor(Vec<(String, serde_json::Value)>);
+
+impl<'kvs> VisitSource<'kvs> for FieldsVisitor {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let mut json = JsonValue(None);
+        value.visit(&mut json)?;
+
+        if let Some(json) = json.0 {
+            self.0.push((key.as_str().to_string(), json));
+        }
+        Ok(())
+    }
+}
+
+struct JsonValue(Option<serde_json::Value>);
+
+impl<'v> VisitValue<'v> for JsonValue {
+    fn visit_any(&mut self, value: Value) -> Result<(), log::kv::Error> {
+        self.0 = Some(value.to_string().into());
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), log::kv::Error> {
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), log::kv::Error> {
+        self.0 = Some(value.into());
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), log::kv::Error> {
+        self.0 = Some(value.into());
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), log::kv::Error> {
+        self.0 = Some(value.into());
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), log::kv::Error> {
+        self.0 = Some(value.into());
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), log::kv::Error> {
+        self.0 = Some(value.into());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::kv::ToValue;
+    use log::Level;
+
+    const TIMESTAMP: &str = "2021-01-02T03:04:05.006Z";
+
+    fn with_record<F>(key_values: &[(&str, Value)], f: F) -> String
+    where
+        F: Fn(&Record) -> String,
+    {
+        f(&Record::builder()
+            .args(format_args!("Client {:?} connected", "c1"))
+            .level(Level::Info)
+            .target("ratelmq::broker")
+            .key_values(&key_values)
+            .build())
+    }
+
+    #[test]
+    fn test_json_line() {
+        let address = "127.0.0.1:50000".parse::<std::net::SocketAddr>().unwrap();
+        let key_values = [
+            ("client_id", Value::from("c1")),
+            ("remote_address", Value::from_display(&address)),
+            ("keep_alive", Value::from(30u16)),
+            ("clean_session", Value::from(true)),
+            ("user_name", None::<&str>.to_value()),
+        ];
+
+        let line = with_record(&key_values, |record| json_line(TIMESTAMP, record));
+
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestamp": TIMESTAMP,
+                "level": "INFO",
+                "target": "ratelmq::broker",
+                "message": "Client \"c1\" connected",
+                "client_id": "c1",
+                "remote_address": "127.0.0.1:50000",
+                "keep_alive": 30,
+                "clean_session": true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_text_line() {
+        let key_values = [
+            ("client_id", Value::from("c1")),
+            ("user_name", Value::from("John Doe")),
+            ("packet_type", Value::from("CONNECT")),
+        ];
+
+        let line = with_record(&key_values, |record| text_line(TIMESTAMP, record));
+
+        assert_eq!(
+            line,
+            "[2021-01-02T03:04:05.006Z INFO  ratelmq::broker] Client \"c1\" connected \
+             client_id=c1 user_name=\"John Doe\" packet_type=CONNECT"
+        );
+        assert_eq!(
+            with_record(&[], |record| text_line(TIMESTAMP, record)),
+            "[2021-01-02T03:04:05.006Z INFO  ratelmq::broker] Client \"c1\" connected"
+        );
+    }
+}
@@ -6,7 +6,6 @@ use ratelmq::config::build_info::BUILD_INFO;
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-    env_logger::init();
 
     let version = format!("v{}({})", BUILD_INFO.version, &BUILD_INFO.commit_hash[..10]);
 
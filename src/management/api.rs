@@ -0,0 +1,907 @@
+use crate::broker::messaging::{self, MessagingOperation, MessagingTx, SessionInfo};
+use crate::broker::metrics::BrokerMetrics;
+use crate::config::build_info::BUILD_INFO;
+use crate::management::health::ListenerHealth;
+use crate::mqtt::events::ClientEvent;
+use crate::mqtt::packets::suback::SubAckReturnCode;
+use crate::mqtt::packets::{ProtocolVersion, PublishPacket, QoS};
+use crate::mqtt::subscription::Subscription;
+use crate::mqtt::tap::{TapFilter, Taps};
+use bytes::BytesMut;
+use hyper::body::HttpBody;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::info;
+use percent_encoding::percent_decode_str;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+
+pub const PREFIX: &str = "/api/v1/";
+
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/// Channels to the broker tasks, shared by all requests.
+pub struct BrokerHandle {
+    pub metrics: Arc<BrokerMetrics>,
+    pub messaging_tx: MessagingTx,
+    pub client_tx: mpsc::Sender<ClientEvent>,
+    pub reload_tx: mpsc::Sender<()>,
+    /// Set once, the broker does not stop draining until restarted.
+    pub drain_tx: watch::Sender<Option<Duration>>,
+    pub taps: Arc<Taps>,
+    pub listeners: Arc<ListenerHealth>,
+}
+
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    version: &'static str,
+    commit: &'static str,
+    uptime_seconds: u64,
+    clients_connected: i64,
+    sessions: i64,
+    subscriptions: i64,
+    retained_messages: i64,
+    messages_received: u64,
+    messages_sent: u64,
+    messages_dropped: u64,
+    draining: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct SubscriptionResponse {
+    client_id: String,
+    topic: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ClientResponse {
+    client_id: String,
+    ip: String,
+    persistent: bool,
+    keep_alive_seconds: u16,
+    protocol_version: Option<&'static str>,
+    connected_at: String,
+    last_activity: String,
+    packets_received: u64,
+    packets_sent: u64,
+    bytes_received: u64,
+    bytes_sent: u64,
+    messages_dropped: u64,
+    /// Packets waiting to be written to the connection.
+    queued_packets: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscriptions: Option<Vec<String>>,
+}
+
+impl From<SessionInfo> for ClientResponse {
+    fn from(session: SessionInfo) -> Self {
+        ClientResponse {
+            client_id: session.client_id,
+            ip: session.ip.to_string(),
+            persistent: session.persistent,
+            keep_alive_seconds: session.keep_alive_seconds,
+            protocol_version: session.protocol_version.map(|version| match version {
+                ProtocolVersion::Mqtt3 => "3.1.1",
+                ProtocolVersion::Mqtt5 => "5.0",
+            }),
+            connected_at: session.connected_at.to_rfc3339(),
+            last_activity: session.last_activity.to_rfc3339(),
+            packets_received: session.packets_received,
+            packets_sent: session.packets_sent,
+            bytes_received: session.bytes_received,
+            bytes_sent: session.bytes_sent,
+            messages_dropped: session.messages_dropped,
+            queued_packets: session.queued_packets,
+            subscriptions: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RetainedResponse {
+    topic: String,
+    /// Not valid UTF-8 sequences are replaced.
+    payload: String,
+    qos: u8,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubscribeRequest {
+    topic: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PublishRequest {
+    topic: String,
+    payload: String,
+    #[serde(default)]
+    retain: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct PublishResponse {
+    subscribers: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct DrainRequest {
+    /// Period to disconnect the clients over, all of them are disconnected at once by default.
+    #[serde(default)]
+    period_seconds: u64,
+}
+
+/// Handles a request below `PREFIX`, the token is already checked.
+///
+/// Topics are the ones used by the broker, i.e. including the mountpoints of the clients.
+/// Client ids and topics in paths are percent-encoded, e.g. `a%2Fb` for `a/b`.
+pub async fn handle(request: Request<Body>, broker: &BrokerHandle) -> Response<Body> {
+    let messaging_tx = &broker.messaging_tx;
+    let path = request
+        .uri()
+        .path()
+        .strip_prefix(PREFIX)
+        .unwrap_or_default();
+    let segments: Vec<String> = path
+        .split('/')
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    match (request.method(), segments.as_slice()) {
+        (&Method::GET, ["status"]) => status(broker),
+        (&Method::POST, ["reload"]) => reload(broker),
+        (&Method::POST, ["drain"]) => match read_json::<DrainRequest>(request).await {
+            Ok(body) => drain(broker, Duration::from_secs(body.period_seconds)),
+            Err(response) => response,
+        },
+        (&Method::GET, ["subscriptions"]) => respond(list_subscriptions(messaging_tx).await),
+        (&Method::GET, ["clients"]) => respond(list_clients(messaging_tx).await),
+        (&Method::GET, ["clients", client_id]) => {
+            respond(get_client(messaging_tx, client_id).await)
+        }
+        (&Method::DELETE, ["clients", client_id]) => respond(kick_client(broker, client_id).await),
+        (&Method::GET, ["clients", client_id, "subscriptions"]) => {
+            match client_subscriptions(messaging_tx, client_id).await {
+                Ok(Some(subscriptions)) => json(StatusCode::OK, &subscriptions),
+                Ok(None) => error(StatusCode::NOT_FOUND, "client not found"),
+                Err(response) => response,
+            }
+        }
+        (&Method::POST, ["clients", client_id, "subscriptions"]) => {
+            let client_id = client_id.to_string();
+            match read_json::<SubscribeRequest>(request).await {
+                Ok(body) => respond(subscribe(messaging_tx, client_id, body.topic).await),
+                Err(response) => response,
+            }
+        }
+        (&Method::DELETE, ["clients", client_id, "subscriptions", topic]) => {
+            respond(unsubscribe(messaging_tx, client_id, topic).await)
+        }
+        (&Method::POST, ["publish"]) => match read_json::<PublishRequest>(request).await {
+            Ok(body) => publish(messaging_tx, &broker.metrics, body).await,
+            Err(response) => response,
+        },
+        (&Method::GET, ["retained"]) => respond(list_retained(messaging_tx).await),
+        (&Method::DELETE, ["retained", topic]) => {
+            respond(delete_retained(messaging_tx, topic).await)
+        }
+        (&Method::GET, ["tap"]) => tap(broker, request.uri().query().unwrap_or_default()),
+        _ => error(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+fn status(broker: &BrokerHandle) -> Response<Body> {
+    let metrics = &broker.metrics;
+    let status = StatusResponse {
+        version: BUILD_INFO.version,
+        commit: BUILD_INFO.commit_hash,
+        uptime_seconds: metrics.uptime().as_secs(),
+        clients_connected: metrics.clients_connected.get(),
+        sessions: metrics.sessions.get(),
+        subscriptions: metrics.subscriptions.get(),
+        retained_messages: metrics.retained_messages.get(),
+        messages_received: metrics.messages_received.get(),
+        messages_sent: metrics.messages_sent.get(),
+        messages_dropped: metrics.messages_dropped.get(),
+        draining: broker.drain_tx.borrow().is_some(),
+    };
+
+    json(StatusCode::OK, &status)
+}
+
+/// Same as SIGHUP, the result is only logged.
+fn reload(broker: &BrokerHandle) -> Response<Body> {
+    // a full channel means a reload is already pending
+    let _ = broker.reload_tx.try_send(());
+    info!("Reload requested through the API");
+
+    empty(StatusCode::ACCEPTED)
+}
+
+fn drain(broker: &BrokerHandle, period: Duration) -> Response<Body> {
+    if broker.drain_tx.borrow().is_some() {
+        return error(StatusCode::CONFLICT, "already draining");
+    }
+
+    info!("Drain requested through the API");
+    broker.drain_tx.send_replace(Some(period));
+
+    empty(StatusCode::ACCEPTED)
+}
+
+async fn list_subscriptions(messaging_tx: &MessagingTx) -> Result<Response<Body>, Response<Body>> {
+    let (tx, rx) = oneshot::channel();
+    let op = MessagingOperation::SubscriptionList { resp: tx };
+
+    let subscriptions: Vec<SubscriptionResponse> = call(messaging_tx, op, rx)
+        .await?
+        .into_iter()
+        .map(|(topic, client_id)| SubscriptionResponse { client_id, topic })
+        .collect();
+
+    Ok(json(StatusCode::OK, &subscriptions))
+}
+
+async fn list_clients(messaging_tx: &MessagingTx) -> Result<Response<Body>, Response<Body>> {
+    let (tx, rx) = oneshot::channel();
+    let op = MessagingOperation::SessionList { resp: tx };
+
+    let mut clients: Vec<ClientResponse> = call(messaging_tx, op, rx)
+        .await?
+        .into_iter()
+        .map(ClientResponse::from)
+        .collect();
+    clients.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+
+    Ok(json(StatusCode::OK, &clients))
+}
+
+async fn get_client(
+    messaging_tx: &MessagingTx,
+    client_id: &str,
+) -> Result<Response<Body>, Response<Body>> {
+    let (tx, rx) = oneshot::channel();
+    let op = MessagingOperation::SessionDetails {
+        client_id: client_id.to_string(),
+        resp: tx,
+    };
+
+    let mut client = match call(messaging_tx, op, rx).await? {
+        Some(session) => ClientResponse::from(session),
+        None => return Err(error(StatusCode::NOT_FOUND, "client not found")),
+    };
+    client.subscriptions = client_subscriptions(messaging_tx, client_id).await?;
+
+    Ok(json(StatusCode::OK, &client))
+}
+
+/// The client packet handler closes the connection, its packets are not handled anymore.
+async fn kick_client(
+    broker: &BrokerHandle,
+    client_id: &str,
+) -> Result<Response<Body>, Response<Body>> {
+    let (tx, rx) = oneshot::channel();
+    let event = ClientEvent::Kick(client_id.to_string(), tx);
+
+    let kicked = match broker.client_tx.send(event).await {
+        Ok(()) => rx.await.map_err(|_| unavailable())?,
+        Err(_) => return Err(unavailable()),
+    };
+    match kicked {
+        true => {
+            info!("Client {:?} disconnected through the API", client_id);
+            Ok(empty(StatusCode::NO_CONTENT))
+        }
+        false => Err(error(StatusCode::NOT_FOUND, "client not found")),
+    }
+}
+
+async fn client_subscriptions(
+    messaging_tx: &MessagingTx,
+    client_id: &str,
+) -> Result<Option<Vec<String>>, Response<Body>> {
+    let (tx, rx) = oneshot::channel();
+    let op = MessagingOperation::ClientSubscriptions {
+        client_id: client_id.to_string(),
+        resp: tx,
+    };
+
+    call(messaging_tx, op, rx).await
+}
+
+async fn subscribe(
+    messaging_tx: &MessagingTx,
+    client_id: String,
+    topic: String,
+) -> Result<Response<Body>, Response<Body>> {
+    if !is_valid_topic_filter(&topic) {
+        return Err(error(StatusCode::BAD_REQUEST, "invalid topic filter"));
+    }
+    if client_subscriptions(messaging_tx, &client_id)
+        .await?
+        .is_none()
+    {
+        return Err(error(StatusCode::NOT_FOUND, "client not found"));
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let op = MessagingOperation::Subscribe {
+        client_id,
+        subscription: Subscription::new(topic, QoS::AtMostOnce),
+        resp: tx,
+    };
+
+    match call(messaging_tx, op, rx).await? {
+        SubAckReturnCode::Failure => {
+            Err(error(StatusCode::INTERNAL_SERVER_ERROR, "subscribe failed"))
+        }
+        _ => Ok(empty(StatusCode::NO_CONTENT)),
+    }
+}
+
+async fn unsubscribe(
+    messaging_tx: &MessagingTx,
+    client_id: &str,
+    topic: &str,
+) -> Result<Response<Body>, Response<Body>> {
+    match client_subscriptions(messaging_tx, client_id).await? {
+        Some(subscriptions) if subscriptions.iter().any(|s| s == topic) => {}
+        Some(_) => return Err(error(StatusCode::NOT_FOUND, "subscription not found")),
+        None => return Err(error(StatusCode::NOT_FOUND, "client not found")),
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let op = MessagingOperation::Unsubscribe {
+        client_id: client_id.to_string(),
+        topics: vec![topic.to_string()],
+        resp: tx,
+    };
+
+    call(messaging_tx, op, rx).await?;
+
+    Ok(empty(StatusCode::NO_CONTENT))
+}
+
+async fn publish(
+    messaging_tx: &MessagingTx,
+    metrics: &BrokerMetrics,
+    request: PublishRequest,
+) -> Response<Body> {
+    if request.topic.is_empty() || request.topic.contains(['+', '#']) {
+        return error(StatusCode::BAD_REQUEST, "invalid topic");
+    }
+
+    let publish = PublishPacket::new(
+        request.topic,
+        BytesMut::from(request.payload.as_bytes()),
+        QoS::AtMostOnce,
+        request.retain,
+        None,
+        false,
+    );
+    let subscribers = messaging::deliver(messaging_tx, metrics, publish).await;
+
+    json(StatusCode::OK, &PublishResponse { subscribers })
+}
+
+async fn list_retained(messaging_tx: &MessagingTx) -> Result<Response<Body>, Response<Body>> {
+    let (tx, rx) = oneshot::channel();
+    let op = MessagingOperation::RetainedList { resp: tx };
+
+    let messages: Vec<RetainedResponse> = call(messaging_tx, op, rx)
+        .await?
+        .into_iter()
+        .map(|publish| RetainedResponse {
+            payload: String::from_utf8_lossy(&publish.message.payload).into_owned(),
+            qos: publish.message.qos as u8,
+            topic: publish.message.topic,
+        })
+        .collect();
+
+    Ok(json(StatusCode::OK, &messages))
+}
+
+async fn delete_retained(
+    messaging_tx: &MessagingTx,
+    topic: &str,
+) -> Result<Response<Body>, Response<Body>> {
+    let (tx, rx) = oneshot::channel();
+    let op = MessagingOperation::RetainedDelete {
+        topic: topic.to_string(),
+        resp: tx,
+    };
+
+    match call(messaging_tx, op, rx).await? {
+        true => Ok(empty(StatusCode::NO_CONTENT)),
+        false => Err(error(StatusCode::NOT_FOUND, "retained message not found")),
+    }
+}
+
+/// Streams the packets of a client id or matching a topic filter as JSON lines.
+///
+/// Unlike the other endpoints, topics are the ones used by the clients, i.e. without mountpoints.
+/// Records are dropped while the reader is behind.
+fn tap(broker: &BrokerHandle, query: &str) -> Response<Body> {
+    let params: Vec<(String, String)> = query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .map(|(name, value)| {
+            let value = percent_decode_str(value).decode_utf8_lossy().into_owned();
+            (name.to_string(), value)
+        })
+        .collect();
+
+    let filter = match params.as_slice() {
+        [(name, client_id)] if name == "client_id" => TapFilter::ClientId(client_id.clone()),
+        [(name, topic)] if name == "topic" && is_valid_topic_filter(topic) => {
+            TapFilter::Topic(topic.clone())
+        }
+        [(name, _)] if name == "topic" => {
+            return error(StatusCode::BAD_REQUEST, "invalid topic filter")
+        }
+        _ => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "either client_id or topic is required",
+            )
+        }
+    };
+
+    info!("Tap of {:?} opened through the API", filter);
+    let receiver = broker.taps.open(filter);
+    let lines = futures::stream::unfold(receiver, |mut receiver| async move {
+        let record = receiver.recv().await?;
+        let mut line = serde_json::to_vec(&record).unwrap();
+        line.push(b'\n');
+        Some((Ok::<_, std::convert::Infallible>(line), receiver))
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::wrap_stream(lines))
+        .unwrap()
+}
+
+/// `#` must be the last level and wildcards must occupy the whole level.
+fn is_valid_topic_filter(topic: &str) -> bool {
+    let levels: Vec<&str> = topic.split('/').collect();
+
+    !topic.is_empty()
+        && levels.iter().enumerate().all(|(i, level)| match *level {
+            "#" => i == levels.len() - 1,
+            "+" => true,
+            level => !level.contains(['+', '#']),
+        })
+}
+
+/// The size is checked while reading, chunked bodies do not announce it.
+async fn read_json<T: DeserializeOwned>(request: Request<Body>) -> Result<T, Response<Body>> {
+    let too_large = || error(StatusCode::PAYLOAD_TOO_LARGE, "request body too large");
+    let mut body = request.into_body();
+    if body.size_hint().lower() > MAX_BODY_SIZE {
+        return Err(too_large());
+    }
+
+    let mut bytes = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk =
+            chunk.map_err(|_| error(StatusCode::BAD_REQUEST, "unable to read request body"))?;
+        if (bytes.len() + chunk.len()) as u64 > MAX_BODY_SIZE {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    let body = bytes.freeze();
+
+    // requests without parameters may have no body at all
+    let body: &[u8] = match body.is_empty() {
+        true => b"{}",
+        false => &body,
+    };
+    serde_json::from_slice(body).map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string()))
+}
+
+/// Sends the operation and waits for its result, both fail once the broker is shutting down.
+async fn call<T>(
+    messaging_tx: &MessagingTx,
+    op: MessagingOperation,
+    rx: oneshot::Receiver<T>,
+) -> Result<T, Response<Body>> {
+    messaging_tx.send(op).await.map_err(|_| unavailable())?;
+
+    rx.await.map_err(|_| unavailable())
+}
+
+fn unavailable() -> Response<Body> {
+    error(StatusCode::SERVICE_UNAVAILABLE, "broker unavailable")
+}
+
+fn respond(result: Result<Response<Body>, Response<Body>>) -> Response<Body> {
+    result.unwrap_or_else(|response| response)
+}
+
+pub fn json<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(value).unwrap()))
+        .unwrap()
+}
+
+pub fn error(status: StatusCode, message: &str) -> Response<Body> {
+    json(status, &serde_json::json!({ "error": message }))
+}
+
+fn empty(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broker::messaging::{channel, MessagingService};
+    use crate::broker::mountpoint::Mountpoint;
+    use crate::broker::session::{ClientStatistics, Session};
+    use crate::mqtt::events::ServerEvent;
+    use crate::mqtt::packets::ControlPacket;
+    use crate::mqtt::tap::Direction;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    struct Broker {
+        handle: BrokerHandle,
+        reload_rx: mpsc::Receiver<()>,
+        drain_rx: watch::Receiver<Option<Duration>>,
+        client_rx: Option<mpsc::Receiver<ClientEvent>>,
+    }
+
+    impl Broker {
+        fn start() -> Broker {
+            let metrics = Arc::new(BrokerMetrics::new());
+            let (messaging_tx, messaging_rx) = channel(32, Arc::clone(&metrics));
+            tokio::spawn(MessagingService::new(Arc::clone(&metrics)).run(messaging_rx));
+            let (reload_tx, reload_rx) = mpsc::channel(1);
+            let (drain_tx, drain_rx) = watch::channel(None);
+            let (client_tx, client_rx) = mpsc::channel(1);
+
+            Broker {
+                handle: BrokerHandle {
+                    metrics,
+                    messaging_tx,
+                    client_tx,
+                    reload_tx,
+                    drain_tx,
+                    taps: Arc::new(Taps::default()),
+                    listeners: ListenerHealth::new(0),
+                },
+                reload_rx,
+                drain_rx,
+                client_rx: Some(client_rx),
+            }
+        }
+
+        async fn connect(&self, client_id: &str) -> mpsc::Receiver<ServerEvent> {
+            let (sender, receiver) = mpsc::channel(32);
+            let statistics = Arc::new(ClientStatistics::new());
+            statistics.set_protocol_version(ProtocolVersion::Mqtt3);
+            statistics.add_received(14, true);
+            let session = Session::new(
+                client_id.to_string(),
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                false,
+                sender,
+                60,
+                Mountpoint::default(),
+                statistics,
+            );
+            let (tx, rx) = oneshot::channel();
+            let op = MessagingOperation::SessionInsert { session, resp: tx };
+            self.handle.messaging_tx.send(op).await.unwrap();
+            rx.await.unwrap();
+
+            receiver
+        }
+
+        async fn request(&self, method: Method, uri: &str, body: &str) -> (StatusCode, String) {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::from(body.to_string()))
+                .unwrap();
+
+            let response = handle(request, &self.handle).await;
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clients() {
+        let mut broker = Broker::start();
+        let _receiver = broker.connect("c 1").await;
+
+        let (status, body) = broker.request(Method::GET, "/api/v1/clients", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#""client_id":"c 1","ip":"127.0.0.1","persistent":false"#));
+        assert!(body.contains(r#""protocol_version":"3.1.1""#));
+        assert!(body.contains(r#""packets_received":1,"packets_sent":0,"bytes_received":14"#));
+        assert!(body.contains(r#""queued_packets":0"#));
+        assert!(!body.contains("subscriptions"));
+
+        let (status, body) = broker
+            .request(Method::GET, "/api/v1/clients/c%201", "")
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#""subscriptions":[]"#));
+
+        // answers the kicks like the client packet handler
+        let mut client_rx = broker.client_rx.take().unwrap();
+        tokio::spawn(async move {
+            while let Some(event) = client_rx.recv().await {
+                if let ClientEvent::Kick(client_id, resp) = event {
+                    let _ = resp.send(client_id == "c 1");
+                }
+            }
+        });
+        let (status, _) = broker
+            .request(Method::DELETE, "/api/v1/clients/c%201", "")
+            .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = broker
+            .request(Method::DELETE, "/api/v1/clients/c2", "")
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = broker.request(Method::GET, "/api/v1/clients/c2", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, r#"{"error":"client not found"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions_and_publish() {
+        let broker = Broker::start();
+        let mut receiver = broker.connect("c1").await;
+        let subscriptions = "/api/v1/clients/c1/subscriptions";
+
+        let (status, _) = broker
+            .request(Method::POST, subscriptions, r#"{"topic":"a/+"}"#)
+            .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, body) = broker.request(Method::GET, subscriptions, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"["a/+"]"#);
+
+        let (status, body) = broker
+            .request(
+                Method::POST,
+                "/api/v1/publish",
+                r#"{"topic":"a/b","payload":"hello"}"#,
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"subscribers":1}"#);
+        match receiver.recv().await {
+            Some(ServerEvent::Publish(p, _)) => {
+                assert_eq!(p.message.topic, "a/b");
+                assert_eq!(p.message.payload, BytesMut::from("hello"));
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+
+        let (status, _) = broker
+            .request(
+                Method::DELETE,
+                "/api/v1/clients/c1/subscriptions/a%2F%2B",
+                "",
+            )
+            .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, body) = broker.request(Method::GET, subscriptions, "").await;
+        assert_eq!(body, "[]");
+
+        let (status, _) = broker
+            .request(Method::POST, subscriptions, r#"{"topic":"a/#/b"}"#)
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = broker
+            .request(
+                Method::POST,
+                "/api/v1/clients/c2/subscriptions",
+                r#"{"topic":"a"}"#,
+            )
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_retained() {
+        let broker = Broker::start();
+        let publish = r#"{"topic":"a/b","payload":"hello","retain":true}"#;
+
+        let (status, body) = broker
+            .request(Method::POST, "/api/v1/publish", publish)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"subscribers":0}"#);
+
+        let (status, body) = broker.request(Method::GET, "/api/v1/retained", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"[{"topic":"a/b","payload":"hello","qos":0}]"#);
+        let (_, body) = broker.request(Method::GET, "/api/v1/status", "").await;
+        assert!(body.contains(r#""retained_messages":1"#));
+
+        let (status, _) = broker
+            .request(Method::DELETE, "/api/v1/retained/a%2Fb", "")
+            .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, body) = broker.request(Method::GET, "/api/v1/status", "").await;
+        assert!(body.contains(r#""retained_messages":0"#));
+        let (status, _) = broker
+            .request(Method::DELETE, "/api/v1/retained/a%2Fb", "")
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_status_reload_and_drain() {
+        let mut broker = Broker::start();
+        broker.handle.metrics.clients_connected.set(2);
+
+        let (status, body) = broker.request(Method::GET, "/api/v1/status", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#""clients_connected":2,"#));
+        assert!(body.contains(r#""draining":false"#));
+
+        let (status, _) = broker.request(Method::POST, "/api/v1/reload", "").await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(broker.reload_rx.try_recv(), Ok(()));
+
+        let (status, _) = broker
+            .request(Method::POST, "/api/v1/drain", r#"{"period_seconds":30}"#)
+            .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(*broker.drain_rx.borrow(), Some(Duration::from_secs(30)));
+        let (status, _) = broker.request(Method::POST, "/api/v1/drain", "").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (_, body) = broker.request(Method::GET, "/api/v1/status", "").await;
+        assert!(body.contains(r#""draining":true"#));
+    }
+
+    #[tokio::test]
+    async fn test_list_subscriptions() {
+        let broker = Broker::start();
+        let _receiver = broker.connect("c1").await;
+        broker
+            .request(
+                Method::POST,
+                "/api/v1/clients/c1/subscriptions",
+                r#"{"topic":"a/#"}"#,
+            )
+            .await;
+
+        let (status, body) = broker
+            .request(Method::GET, "/api/v1/subscriptions", "")
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"[{"client_id":"c1","topic":"a/#"}]"#);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_requests() {
+        let broker = Broker::start();
+
+        let (status, _) = broker.request(Method::GET, "/api/v1/unknown", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = broker
+            .request(
+                Method::POST,
+                "/api/v1/publish",
+                r#"{"topic":"a/#","payload":""}"#,
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, body) = broker
+            .request(Method::POST, "/api/v1/publish", "not json")
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.starts_with(r#"{"error":"#));
+
+        // chunked bodies have no size hint
+        let chunks =
+            (0..=MAX_BODY_SIZE / 1024).map(|_| Ok::<_, std::convert::Infallible>(vec![b' '; 1024]));
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/api/v1/publish")
+            .body(Body::wrap_stream(futures::stream::iter(chunks)))
+            .unwrap();
+        let response = handle(request, &broker.handle).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_broker_unavailable() {
+        let mut broker = Broker::start();
+        // the messaging service is gone while the broker shuts down
+        broker.handle.messaging_tx = channel(1, Arc::clone(&broker.handle.metrics)).0;
+
+        let (status, body) = broker.request(Method::GET, "/api/v1/clients", "").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, r#"{"error":"broker unavailable"}"#);
+        let (status, _) = broker
+            .request(Method::DELETE, "/api/v1/retained/a", "")
+            .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        broker.client_rx = None;
+        let (status, _) = broker
+            .request(Method::DELETE, "/api/v1/clients/c1", "")
+            .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_tap() {
+        let broker = Broker::start();
+        let request = Request::builder()
+            .uri("/api/v1/tap?topic=a%2F%2B")
+            .body(Body::empty())
+            .unwrap();
+        let response = handle(request, &broker.handle).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let taps = &broker.handle.taps;
+        let publish = |topic: &str| {
+            ControlPacket::Publish(PublishPacket::new(
+                topic.to_string(),
+                BytesMut::from("hello"),
+                QoS::AtMostOnce,
+                false,
+                None,
+                false,
+            ))
+        };
+        taps.packet("c1", Direction::Received, &publish("b/c"));
+        taps.packet("c1", Direction::Received, &publish("a/b"));
+        taps.close_all();
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(
+            lines[0].contains(r#""client_id":"c1","direction":"received","packet_type":"PUBLISH""#)
+        );
+
+        for query in [
+            "",
+            "?topic=a%2F%23%2Fb",
+            "?client_id=c1&topic=a",
+            "?other=a",
+        ] {
+            let (status, _) = broker
+                .request(Method::GET, &format!("/api/v1/tap{}", query), "")
+                .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_is_valid_topic_filter() {
+        for filter in ["a", "a/b", "+", "#", "a/+/c", "a/#", "+/+"] {
+            assert!(is_valid_topic_filter(filter), "{}", filter);
+        }
+        for filter in ["", "a/#/c", "a+", "a/b#", "#/a"] {
+            assert!(!is_valid_topic_filter(filter), "{}", filter);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::broker::messaging::{MessagingOperation, MessagingTx};
+use crate::management::api::{self, BrokerHandle};
+use crate::mqtt::events::ClientEvent;
+use hyper::{Body, Response, StatusCode};
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::timeout;
+
+/// A task not responding within it is considered stuck.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Counts the MQTT listeners accepting connections.
+pub struct ListenerHealth {
+    expected: usize,
+    accepting: AtomicUsize,
+}
+
+impl ListenerHealth {
+    pub fn new(expected: usize) -> Arc<ListenerHealth> {
+        Arc::new(ListenerHealth {
+            expected,
+            accepting: AtomicUsize::new(0),
+        })
+    }
+
+    /// The listener counts as accepting until the guard is dropped with its task.
+    pub fn accepting(self: &Arc<Self>) -> AcceptingGuard {
+        self.accepting.fetch_add(1, Ordering::AcqRel);
+
+        AcceptingGuard(Arc::clone(self))
+    }
+
+    fn all_accepting(&self) -> bool {
+        self.accepting.load(Ordering::Acquire) == self.expected
+    }
+}
+
+pub struct AcceptingGuard(Arc<ListenerHealth>);
+
+impl Drop for AcceptingGuard {
+    fn drop(&mut self) {
+        self.0.accepting.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct HealthReport {
+    listeners: bool,
+    messaging_service: bool,
+    client_packet_handler: bool,
+    draining: bool,
+}
+
+impl HealthReport {
+    async fn check(broker: &BrokerHandle) -> HealthReport {
+        HealthReport {
+            listeners: broker.listeners.all_accepting(),
+            messaging_service: messaging_service_responds(&broker.messaging_tx).await,
+            client_packet_handler: client_packet_handler_responds(&broker.client_tx).await,
+            draining: broker.drain_tx.borrow().is_some(),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        self.listeners && self.messaging_service && self.client_packet_handler
+    }
+}
+
+/// Liveness, the broker should be restarted when it fails.
+pub async fn healthz(broker: &BrokerHandle) -> Response<Body> {
+    let report = HealthReport::check(broker).await;
+    let alive = report.is_alive();
+
+    respond(alive, &report)
+}
+
+/// Readiness, new clients should not be routed to the broker when it fails, e.g. while draining.
+pub async fn readyz(broker: &BrokerHandle) -> Response<Body> {
+    let report = HealthReport::check(broker).await;
+    let ready = report.is_alive() && !report.draining;
+
+    respond(ready, &report)
+}
+
+fn respond(ok: bool, report: &HealthReport) -> Response<Body> {
+    let status = match ok {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    api::json(status, report)
+}
+
+async fn messaging_service_responds(messaging_tx: &MessagingTx) -> bool {
+    let (tx, rx) = oneshot::channel();
+    // any operation would do, counting the sessions is cheap
+    let op = MessagingOperation::SessionCount { resp: tx };
+    let round_trip = async { messaging_tx.send(op).await.is_ok() && rx.await.is_ok() };
+
+    timeout(RESPONSE_TIMEOUT, round_trip).await.unwrap_or(false)
+}
+
+async fn client_packet_handler_responds(client_tx: &mpsc::Sender<ClientEvent>) -> bool {
+    let (tx, rx) = oneshot::channel();
+    let round_trip =
+        async { client_tx.send(ClientEvent::HealthCheck(tx)).await.is_ok() && rx.await.is_ok() };
+
+    timeout(RESPONSE_TIMEOUT, round_trip).await.unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broker::messaging::{channel, MessagingService};
+    use crate::broker::metrics::BrokerMetrics;
+    use crate::mqtt::tap::Taps;
+    use tokio::sync::watch;
+
+    fn broker_handle(
+        listeners: Arc<ListenerHealth>,
+    ) -> (BrokerHandle, mpsc::Receiver<ClientEvent>) {
+        let metrics = Arc::new(BrokerMetrics::new());
+        let (messaging_tx, messaging_rx) = channel(32, Arc::clone(&metrics));
+        tokio::spawn(MessagingService::new(Arc::clone(&metrics)).run(messaging_rx));
+        let (client_tx, client_rx) = mpsc::channel(1);
+        let (reload_tx, _) = mpsc::channel(1);
+        let (drain_tx, _) = watch::channel(None);
+
+        let broker = BrokerHandle {
+            metrics,
+            messaging_tx,
+            client_tx,
+            reload_tx,
+            drain_tx,
+            taps: Arc::new(Taps::default()),
+            listeners,
+        };
+        (broker, client_rx)
+    }
+
+    /// Answers the health checks like the client packet handler.
+    fn spawn_handler(mut client_rx: mpsc::Receiver<ClientEvent>) {
+        tokio::spawn(async move {
+            while let Some(event) = client_rx.recv().await {
+                if let ClientEvent::HealthCheck(resp) = event {
+                    let _ = resp.send(());
+                }
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_ready_until_draining() {
+        let listeners = ListenerHealth::new(1);
+        let _accepting = listeners.accepting();
+        let (broker, client_rx) = broker_handle(listeners);
+        spawn_handler(client_rx);
+
+        assert_eq!(healthz(&broker).await.status(), StatusCode::OK);
+        assert_eq!(readyz(&broker).await.status(), StatusCode::OK);
+
+        broker.drain_tx.send_replace(Some(Duration::ZERO));
+
+        assert_eq!(healthz(&broker).await.status(), StatusCode::OK);
+        let response = readyz(&broker).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            body,
+            r#"{"listeners":true,"messaging_service":true,"client_packet_handler":true,"draining":true}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_not_alive() {
+        let listeners = ListenerHealth::new(2);
+        let accepting = listeners.accepting();
+        let _accepting = listeners.accepting();
+        let (broker, client_rx) = broker_handle(Arc::clone(&listeners));
+        spawn_handler(client_rx);
+        assert_eq!(healthz(&broker).await.status(), StatusCode::OK);
+
+        drop(accepting);
+        assert_eq!(
+            healthz(&broker).await.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        let (broker, client_rx) = broker_handle(listeners);
+        drop(client_rx);
+        let response = healthz(&broker).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(body.starts_with(
+            br#"{"listeners":false,"messaging_service":true,"client_packet_handler":false"#
+        ));
+    }
+}
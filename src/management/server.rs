@@ -0,0 +1,304 @@
+use crate::broker::messaging::MessagingOperation;
+use crate::broker::metrics;
+use crate::management::api::{self, BrokerHandle};
+use crate::management::health;
+use crate::settings::ManagementSettings;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{debug, error, info, warn};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{broadcast, oneshot};
+
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+#[derive(Debug)]
+pub enum ManagementError {
+    InvalidAddress(std::net::AddrParseError),
+    Bind(hyper::Error),
+}
+
+impl From<std::net::AddrParseError> for ManagementError {
+    fn from(error: std::net::AddrParseError) -> Self {
+        ManagementError::InvalidAddress(error)
+    }
+}
+
+impl From<hyper::Error> for ManagementError {
+    fn from(error: hyper::Error) -> Self {
+        ManagementError::Bind(error)
+    }
+}
+
+/// Shared by all requests.
+struct State {
+    broker: BrokerHandle,
+    api_token: Option<String>,
+    client_metrics: bool,
+}
+
+/// HTTP listener for operators and monitoring, separate from the MQTT listeners.
+pub struct ManagementServer {
+    builder: hyper::server::Builder<AddrIncoming>,
+    state: Arc<State>,
+    ctrl_c_rx: broadcast::Receiver<()>,
+}
+
+impl ManagementServer {
+    pub fn bind(
+        settings: &ManagementSettings,
+        broker: BrokerHandle,
+        ctrl_c_rx: broadcast::Receiver<()>,
+    ) -> Result<ManagementServer, ManagementError> {
+        debug!("Binding management HTTP listener to {}", &settings.address);
+
+        let address: SocketAddr = settings.address.parse()?;
+        let builder = Server::try_bind(&address)?;
+        info!("Listening for management HTTP requests on {}", address);
+        if settings.api_token.is_none() {
+            warn!("Management API disabled, management.api_token is not set");
+        }
+
+        let state = State {
+            broker,
+            api_token: settings.api_token.clone(),
+            client_metrics: settings.client_metrics,
+        };
+        Ok(ManagementServer {
+            builder,
+            state: Arc::new(state),
+            ctrl_c_rx,
+        })
+    }
+
+    pub async fn run(self) {
+        let state = self.state;
+        let make_service = make_service_fn(move |_| {
+            let state = Arc::clone(&state);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    Self::handle(request, Arc::clone(&state))
+                }))
+            }
+        });
+
+        let mut ctrl_c_rx = self.ctrl_c_rx;
+        let server = self
+            .builder
+            .serve(make_service)
+            .with_graceful_shutdown(async move {
+                let _ = ctrl_c_rx.recv().await;
+            });
+
+        if let Err(e) = server.await {
+            error!("Management HTTP server failed: {:?}", &e);
+        }
+
+        debug!("Management HTTP server stopped");
+    }
+
+    async fn handle(
+        request: Request<Body>,
+        state: Arc<State>,
+    ) -> Result<Response<Body>, Infallible> {
+        let path = request.uri().path();
+
+        if path.starts_with(api::PREFIX) {
+            let api_token = match &state.api_token {
+                Some(api_token) => api_token,
+                None => return Ok(api::error(StatusCode::NOT_FOUND, "API disabled")),
+            };
+            if !is_authorized(&request, api_token) {
+                let mut response = api::error(StatusCode::UNAUTHORIZED, "invalid API token");
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+                return Ok(response);
+            }
+
+            return Ok(api::handle(request, &state.broker).await);
+        }
+
+        let response = match (request.method(), path) {
+            (&Method::GET, "/metrics") => {
+                let mut text = state.broker.metrics.encode();
+                if state.client_metrics {
+                    match Self::encode_clients(&state.broker).await {
+                        Some(clients) => text.push_str(&clients),
+                        None => {
+                            return Ok(api::error(
+                                StatusCode::SERVICE_UNAVAILABLE,
+                                "broker unavailable",
+                            ))
+                        }
+                    }
+                }
+                Response::builder()
+                    .header(CONTENT_TYPE, METRICS_CONTENT_TYPE)
+                    .body(Body::from(text))
+            }
+            (&Method::GET, "/healthz") => Ok(health::healthz(&state.broker).await),
+            (&Method::GET, "/readyz") => Ok(health::readyz(&state.broker).await),
+            _ => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty()),
+        };
+
+        Ok(response.expect("Invalid response"))
+    }
+
+    /// Returns `None` when the messaging service is gone, e.g. while the broker shuts down.
+    async fn encode_clients(broker: &BrokerHandle) -> Option<String> {
+        let (tx, rx) = oneshot::channel();
+        let op = MessagingOperation::SessionList { resp: tx };
+
+        broker.messaging_tx.send(op).await.ok()?;
+        let sessions = rx.await.ok()?;
+
+        Some(metrics::encode_clients(&sessions))
+    }
+}
+
+/// Expects `Authorization: Bearer <token>`.
+fn is_authorized(request: &Request<Body>, api_token: &str) -> bool {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match token {
+        Some(token) => constant_time_eq(token.as_bytes(), api_token.as_bytes()),
+        None => false,
+    }
+}
+
+/// Compares without an early exit, so the time does not reveal the matching prefix.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broker::messaging::channel;
+    use crate::broker::metrics::BrokerMetrics;
+    use crate::management::health::ListenerHealth;
+    use crate::mqtt::tap::Taps;
+    use tokio::sync::{mpsc, watch};
+
+    fn request(method: Method, uri: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn state(metrics: Arc<BrokerMetrics>, api_token: Option<&str>) -> Arc<State> {
+        let (messaging_tx, _) = channel(1, Arc::clone(&metrics));
+        let (reload_tx, _) = mpsc::channel(1);
+        let (drain_tx, _) = watch::channel(None);
+
+        Arc::new(State {
+            broker: BrokerHandle {
+                metrics,
+                messaging_tx,
+                client_tx: mpsc::channel(1).0,
+                reload_tx,
+                drain_tx,
+                taps: Arc::new(Taps::default()),
+                listeners: ListenerHealth::new(0),
+            },
+            api_token: api_token.map(String::from),
+            client_metrics: false,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let metrics = Arc::new(BrokerMetrics::new());
+        metrics.connections.inc();
+
+        let response =
+            ManagementServer::handle(request(Method::GET, "/metrics"), state(metrics, None))
+                .await
+                .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], METRICS_CONTENT_TYPE);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("ratelmq_connections_total 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_client_metrics_while_shutting_down() {
+        // the messaging service of the state is not running
+        let state = state(Arc::new(BrokerMetrics::new()), None);
+        let state = Arc::new(State {
+            client_metrics: true,
+            ..Arc::into_inner(state).unwrap()
+        });
+
+        let response = ManagementServer::handle(request(Method::GET, "/metrics"), state)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_not_found() {
+        let state = state(Arc::new(BrokerMetrics::new()), None);
+
+        for (method, uri) in [
+            (Method::GET, "/"),
+            (Method::POST, "/metrics"),
+            (Method::GET, "/api/v1/clients"),
+        ] {
+            let response = ManagementServer::handle(request(method, uri), Arc::clone(&state))
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_api_token() {
+        let state = state(Arc::new(BrokerMetrics::new()), Some("secret"));
+
+        for authorization in [None, Some("Bearer wrong"), Some("secret")] {
+            let mut request = request(Method::GET, "/api/v1/clients");
+            if let Some(authorization) = authorization {
+                request
+                    .headers_mut()
+                    .insert(AUTHORIZATION, authorization.parse().unwrap());
+            }
+
+            let response = ManagementServer::handle(request, Arc::clone(&state))
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+        }
+
+        let response = ManagementServer::handle(request(Method::GET, "/metrics"), state)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "metrics need no token");
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
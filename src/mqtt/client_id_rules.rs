@@ -0,0 +1,160 @@
+use crate::settings::ClientIdSettings;
+use regex::Regex;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum ClientIdViolation {
+    TooLong { length: usize, max_length: usize },
+    InvalidCharacters,
+    MissingPrefix(String),
+}
+
+impl fmt::Display for ClientIdViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientIdViolation::TooLong { length, max_length } => write!(
+                f,
+                "client id has {} bytes, at most {} allowed",
+                length, max_length
+            ),
+            ClientIdViolation::InvalidCharacters => {
+                write!(f, "client id contains characters which are not allowed")
+            }
+            ClientIdViolation::MissingPrefix(prefix) => {
+                write!(f, "client id does not start with {:?}", prefix)
+            }
+        }
+    }
+}
+
+/// Restrictions of client ids sent by clients, generated client ids are not checked.
+#[derive(Debug)]
+pub struct ClientIdRules {
+    max_length: Option<usize>,
+    allowed_characters: Option<Regex>,
+    user_prefix: Option<String>,
+}
+
+impl ClientIdRules {
+    pub fn new(settings: &ClientIdSettings) -> Result<ClientIdRules, regex::Error> {
+        let allowed_characters = settings
+            .allowed_characters
+            .as_ref()
+            .map(|class| Regex::new(&format!("^[{}]*$", class)))
+            .transpose()?;
+
+        Ok(ClientIdRules {
+            max_length: settings.max_length,
+            allowed_characters,
+            user_prefix: settings.user_prefix.clone(),
+        })
+    }
+
+    pub fn check(&self, client_id: &str, user_name: Option<&str>) -> Result<(), ClientIdViolation> {
+        if let Some(max_length) = self.max_length {
+            // the length is in bytes of the UTF-8 encoded string - MQTT-3.1.3-5
+            if client_id.len() > max_length {
+                return Err(ClientIdViolation::TooLong {
+                    length: client_id.len(),
+                    max_length,
+                });
+            }
+        }
+
+        if let Some(allowed_characters) = &self.allowed_characters {
+            if !allowed_characters.is_match(client_id) {
+                return Err(ClientIdViolation::InvalidCharacters);
+            }
+        }
+
+        if let (Some(user_prefix), Some(user_name)) = (&self.user_prefix, user_name) {
+            let prefix = user_prefix.replace("%u", user_name);
+            if !client_id.starts_with(&prefix) {
+                return Err(ClientIdViolation::MissingPrefix(prefix));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(
+        max_length: Option<usize>,
+        allowed_characters: Option<&str>,
+        user_prefix: Option<&str>,
+    ) -> ClientIdRules {
+        ClientIdRules::new(&ClientIdSettings {
+            max_length,
+            allowed_characters: allowed_characters.map(String::from),
+            user_prefix: user_prefix.map(String::from),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_no_rules() {
+        let rules = rules(None, None, None);
+
+        assert_eq!(rules.check("any client id ☺", Some("alice")), Ok(()));
+    }
+
+    #[test]
+    fn test_max_length() {
+        let rules = rules(Some(4), None, None);
+
+        assert_eq!(rules.check("abcd", None), Ok(()));
+        assert_eq!(
+            rules.check("abcde", None),
+            Err(ClientIdViolation::TooLong {
+                length: 5,
+                max_length: 4
+            })
+        );
+        assert!(rules.check("ab☺", None).is_err(), "length is in bytes");
+    }
+
+    #[test]
+    fn test_allowed_characters() {
+        let rules = rules(None, Some("a-z0-9_-"), None);
+
+        assert_eq!(rules.check("sensor-1_a", None), Ok(()));
+        assert_eq!(
+            rules.check("Sensor-1", None),
+            Err(ClientIdViolation::InvalidCharacters)
+        );
+        assert_eq!(
+            rules.check("sensor/1", None),
+            Err(ClientIdViolation::InvalidCharacters)
+        );
+    }
+
+    #[test]
+    fn test_invalid_character_class() {
+        let settings = ClientIdSettings {
+            allowed_characters: Some("z-a".to_string()),
+            ..ClientIdSettings::default()
+        };
+
+        assert!(ClientIdRules::new(&settings).is_err());
+    }
+
+    #[test]
+    fn test_user_prefix() {
+        let rules = rules(None, None, Some("%u-"));
+
+        assert_eq!(rules.check("alice-1", Some("alice")), Ok(()));
+        assert_eq!(
+            rules.check("bob-1", Some("alice")),
+            Err(ClientIdViolation::MissingPrefix("alice-".to_string()))
+        );
+        assert_eq!(
+            rules.check("bob-1", None),
+            Ok(()),
+            "anonymous clients have no prefix"
+        );
+    }
+}
@@ -1,17 +1,36 @@
-use crate::mqtt::packets::{ClientId, ConnectPacket, ControlPacket};
+use crate::broker::session::ClientStatistics;
+use crate::mqtt::packets::{ClientId, ConnectPacket, ControlPacket, PublishPacket};
+use crate::settings::ListenerSettings;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use tracing::Span;
 
 #[derive(Debug)]
 pub enum ClientEvent {
-    Connected(ConnectPacket, SocketAddr, Sender<ServerEvent>),
-    ControlPacket(ClientId, ControlPacket, Sender<ServerEvent>),
+    Connected(
+        ConnectPacket,
+        SocketAddr,
+        Arc<ListenerSettings>,
+        Sender<ServerEvent>,
+        Arc<ClientStatistics>,
+    ),
+    /// The span of reading the packet is the parent of the spans of handling it.
+    ControlPacket(ClientId, ControlPacket, Sender<ServerEvent>, Span),
     Disconnected(ClientId),
-    ConnectionLost(ClientId),
+    /// The sender tells the lost connection apart from a newer connection of the same client.
+    ConnectionLost(ClientId, Sender<ServerEvent>),
+    /// Answered as soon as it is received, shows that the handler is not stuck.
+    HealthCheck(oneshot::Sender<()>),
+    /// Closes the connection of the client, answered with false when it is not connected.
+    Kick(ClientId, oneshot::Sender<bool>),
 }
 
 #[derive(Debug)]
 pub enum ServerEvent {
     ControlPacket(ControlPacket),
+    /// Message for a subscriber, the span of its delivery is the parent of writing it.
+    Publish(PublishPacket, Span),
     Disconnect,
 }
@@ -1,36 +1,78 @@
+use crate::broker::metrics::BrokerMetrics;
+use crate::broker::session::ClientStatistics;
+use crate::mqtt::client_id_rules::ClientIdRules;
 use crate::mqtt::events::{ClientEvent, ServerEvent};
-use crate::mqtt::packets::ControlPacket;
+use crate::mqtt::packets::connack::ConnAckReturnCode;
+use crate::mqtt::packets::{ConnAckPacket, ControlPacket, ProtocolVersion};
+use crate::mqtt::rate_limiter::RateLimiter;
+use crate::mqtt::tap::{ConnectionTap, Direction, Taps};
 use crate::mqtt::transport::mqtt_bytes_stream::{MqttBytesReadStream, MqttBytesWriteStream};
-use crate::mqtt::transport::packet_decoder::read_packet;
+use crate::mqtt::transport::packet_decoder::{read_packet, DecodeError};
 use crate::mqtt::transport::packet_encoder::write_packet;
+use crate::mqtt::transport::proxy_protocol::read_proxy_header;
+use crate::settings::ListenerSettings;
 use log::{debug, error, info, trace, warn};
 use std::net::SocketAddr;
-use tokio::io::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{Error, ErrorKind};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::select;
 use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{broadcast, mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+use tracing::{info_span, Instrument};
 use uuid::Uuid;
 
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct MqttListener {
     listener: TcpListener,
+    settings: Arc<ListenerSettings>,
+    connections: Option<Arc<Semaphore>>,
+    connection_rate: Option<Arc<RateLimiter>>,
+    client_id_rules: Arc<ClientIdRules>,
+    metrics: Arc<BrokerMetrics>,
+    taps: Arc<Taps>,
     client_event_tx: mpsc::Sender<ClientEvent>,
     ctrl_c_rx: broadcast::Receiver<()>,
 }
 
 impl MqttListener {
     pub async fn bind(
-        address: &str,
+        settings: ListenerSettings,
+        connection_rate: Option<Arc<RateLimiter>>,
+        client_id_rules: Arc<ClientIdRules>,
+        metrics: Arc<BrokerMetrics>,
+        taps: Arc<Taps>,
         client_event_tx: mpsc::Sender<ClientEvent>,
         ctrl_c_rx: broadcast::Receiver<()>,
     ) -> Result<MqttListener, Error> {
-        debug!("Binding MQTT TCP listener to {}", &address);
+        let address = settings.address.as_str();
+        debug!("Binding MQTT TCP listener to {}", address);
 
         let listener = TcpListener::bind(address).await.unwrap();
-        info!("Listening for MQTT TCP connections on {}", &address);
+        if settings.proxy_protocol {
+            info!(
+                "Listening for MQTT TCP connections on {} (PROXY protocol required)",
+                address
+            );
+        } else {
+            info!("Listening for MQTT TCP connections on {}", address);
+        }
+
+        let connections = settings
+            .max_connections
+            .map(|max_connections| Arc::new(Semaphore::new(max_connections)));
 
         let mqtt_listener = MqttListener {
             listener,
+            settings: Arc::new(settings),
+            connections,
+            connection_rate,
+            client_id_rules,
+            metrics,
+            taps,
             client_event_tx,
             ctrl_c_rx,
         };
@@ -44,17 +86,101 @@ impl MqttListener {
                     trace!("Stopping listener");
                     break;
                 }
-                _ = Self::accept(&self.listener, &self.client_event_tx) => {}
+                _ = Self::accept(
+                    &self.listener,
+                    &self.settings,
+                    &self.connections,
+                    &self.connection_rate,
+                    &self.client_id_rules,
+                    &self.metrics,
+                    &self.taps,
+                    &self.client_event_tx,
+                ) => {}
             }
         }
     }
 
-    async fn accept(listener: &TcpListener, client_event_tx: &mpsc::Sender<ClientEvent>) {
+    #[allow(clippy::too_many_arguments)]
+    async fn accept(
+        listener: &TcpListener,
+        settings: &Arc<ListenerSettings>,
+        connections: &Option<Arc<Semaphore>>,
+        connection_rate: &Option<Arc<RateLimiter>>,
+        client_id_rules: &Arc<ClientIdRules>,
+        metrics: &Arc<BrokerMetrics>,
+        taps: &Arc<Taps>,
+        client_event_tx: &mpsc::Sender<ClientEvent>,
+    ) {
         match listener.accept().await {
-            Ok((socket, address)) => {
+            Ok((mut socket, mut address)) => {
+                if let Some(connection_rate) = connection_rate {
+                    if !connection_rate.try_acquire() {
+                        // not a warning, it would flood the log during a connection flood
+                        debug!(
+                            remote_address:% = address, listener = settings.address.as_str();
+                            "Rejecting connection from {}, connection rate limit exceeded",
+                            &address
+                        );
+                        metrics
+                            .connections_rejected
+                            .with_label_values(&["rate_limit"])
+                            .inc();
+                        return;
+                    }
+                }
+
+                let permit = match connections {
+                    Some(connections) => match Arc::clone(connections).try_acquire_owned() {
+                        Ok(permit) => Some(permit),
+                        Err(_) => {
+                            warn!(
+                                remote_address:% = address, listener = settings.address.as_str();
+                                "Rejecting connection from {}, listener {} reached the connections limit",
+                                &address, &settings.address
+                            );
+                            metrics
+                                .connections_rejected
+                                .with_label_values(&["max_connections"])
+                                .inc();
+                            return;
+                        }
+                    },
+                    None => None,
+                };
+
+                let settings = Arc::clone(settings);
+                metrics.connections.inc();
+
+                let client_id_rules = Arc::clone(client_id_rules);
+                let metrics = Arc::clone(metrics);
+                let tap = ConnectionTap::new(Arc::clone(taps));
                 let client_event_tx = client_event_tx.clone();
                 tokio::spawn(async move {
-                    Self::handle_connection(socket, client_event_tx, address).await;
+                    if settings.proxy_protocol {
+                        address = match Self::read_proxy_address(&mut socket, address).await {
+                            Some(client_address) => client_address,
+                            None => {
+                                metrics
+                                    .connections_rejected
+                                    .with_label_values(&["proxy_protocol"])
+                                    .inc();
+                                return;
+                            }
+                        };
+                    }
+                    trace!("Accepted connection from {}", &address);
+
+                    Self::handle_connection(
+                        socket,
+                        client_event_tx,
+                        address,
+                        settings,
+                        client_id_rules,
+                        metrics,
+                        tap,
+                        permit,
+                    )
+                    .await;
                 });
             }
             Err(e) => {
@@ -63,86 +189,288 @@ impl MqttListener {
         }
     }
 
+    async fn read_proxy_address(
+        socket: &mut TcpStream,
+        proxy_address: SocketAddr,
+    ) -> Option<SocketAddr> {
+        match timeout(PROXY_HEADER_TIMEOUT, read_proxy_header(socket)).await {
+            Ok(Ok(Some(client_address))) => {
+                trace!(
+                    "Connection from {} proxied by {}",
+                    &client_address,
+                    &proxy_address
+                );
+                Some(client_address)
+            }
+            Ok(Ok(None)) => Some(proxy_address),
+            Ok(Err(e)) => {
+                warn!(
+                    "Invalid PROXY protocol header from {}: {}",
+                    &proxy_address, &e
+                );
+                None
+            }
+            Err(_) => {
+                warn!(
+                    "Timed out waiting for PROXY protocol header from {}",
+                    &proxy_address
+                );
+                None
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn handle_connection(
         socket: TcpStream,
         client_event_tx: Sender<ClientEvent>,
         address: SocketAddr,
+        settings: Arc<ListenerSettings>,
+        client_id_rules: Arc<ClientIdRules>,
+        metrics: Arc<BrokerMetrics>,
+        tap: ConnectionTap,
+        permit: Option<OwnedSemaphorePermit>,
     ) {
         let (tcp_read, tcp_write) = socket.into_split();
         let (server_event_tx, server_event_rx) = mpsc::channel(32);
 
         let mut write_stream = MqttBytesWriteStream::new(4096, tcp_write);
 
+        let statistics = Arc::new(ClientStatistics::new());
+
+        let write_metrics = Arc::clone(&metrics);
+        let write_statistics = Arc::clone(&statistics);
+        let write_tap = tap.clone();
         tokio::spawn(async move {
-            Self::connection_write_loop(server_event_rx, &mut write_stream).await;
+            Self::connection_write_loop(
+                server_event_rx,
+                &mut write_stream,
+                write_metrics,
+                write_statistics,
+                write_tap,
+            )
+            .await;
         });
 
         let mut read_stream = MqttBytesReadStream::new(4096, tcp_read);
 
         tokio::spawn(async move {
-            Self::connection_read_loop(client_event_tx, server_event_tx, &mut read_stream, address)
-                .await;
+            Self::connection_read_loop(
+                client_event_tx,
+                server_event_tx,
+                &mut read_stream,
+                address,
+                settings,
+                client_id_rules,
+                metrics,
+                statistics,
+                tap,
+            )
+            .await;
+
+            // the connection counts towards the listener limit until the client is gone
+            drop(permit);
         });
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn connection_read_loop(
         client_event_tx: Sender<ClientEvent>,
         server_event_tx: Sender<ServerEvent>,
         mut read_stream: &mut MqttBytesReadStream,
         address: SocketAddr,
+        settings: Arc<ListenerSettings>,
+        client_id_rules: Arc<ClientIdRules>,
+        metrics: Arc<BrokerMetrics>,
+        statistics: Arc<ClientStatistics>,
+        tap: ConnectionTap,
     ) {
+        let max_packet_size = settings.max_packet_size;
+
         // the first packet must be CONNECT - MQTT-3.1.0-1
         let client_id;
-        if let Ok(packet) = read_packet(&mut read_stream).await {
-            trace!("Read the first packet: {:?}", &packet);
-
-            if let ControlPacket::Connect(mut c) = packet {
-                // todo: handle empty client id
-                client_id = if c.client_id.is_empty() {
-                    trace!("Client did not provide client id, id will be generated");
-                    Uuid::new_v4().to_string()
-                } else {
-                    c.client_id.clone()
-                };
+        let result = read_packet(&mut read_stream, max_packet_size).await;
+        Self::count_received(&metrics, &statistics, read_stream, &result);
+        match result {
+            Ok(packet) => {
+                trace!("Read the first packet: {:?}", &packet);
+
+                if let ControlPacket::Connect(mut c) = packet {
+                    client_id = if c.client_id.is_empty() {
+                        // a generated client id cannot be used to resume a session - MQTT-3.1.3-8
+                        if !c.clean_session && c.version == ProtocolVersion::Mqtt3 {
+                            info!(
+                                user_name = c.user_name.as_deref(), remote_address:% = address, listener = settings.address.as_str(), packet_type = "CONNECT";
+                                "Rejecting client from {}, empty client id requires clean session",
+                                &address
+                            );
+                            Self::reject(&server_event_tx, ConnAckReturnCode::IdentifierRejected)
+                                .await;
+                            return;
+                        }
+
+                        trace!("Client did not provide client id, id will be generated");
+                        Uuid::new_v4().to_string()
+                    } else {
+                        if let Err(violation) =
+                            client_id_rules.check(&c.client_id, c.user_name.as_deref())
+                        {
+                            info!(
+                                client_id = c.client_id.as_str(), user_name = c.user_name.as_deref(), remote_address:% = address, listener = settings.address.as_str(), packet_type = "CONNECT";
+                                "Rejecting client {:?} from {}: {}",
+                                &c.client_id, &address, violation
+                            );
+                            Self::reject(&server_event_tx, ConnAckReturnCode::IdentifierRejected)
+                                .await;
+                            return;
+                        }
+
+                        c.client_id.clone()
+                    };
+                    statistics.set_protocol_version(c.version.clone());
 
-                c.client_id = client_id.clone();
-                let event = ClientEvent::Connected(c, address, server_event_tx.clone());
-                if let Err(e) = client_event_tx.send(event).await {
-                    error!("Error while sending client event to be processed: {}", &e);
+                    c.client_id = client_id.clone();
+                    tap.set_client_id(&client_id);
+                    tap.connect(&c);
+                    let event = ClientEvent::Connected(
+                        c,
+                        address,
+                        Arc::clone(&settings),
+                        server_event_tx.clone(),
+                        Arc::clone(&statistics),
+                    );
+                    if let Err(e) = client_event_tx.send(event).await {
+                        error!("Error while sending client event to be processed: {}", &e);
+                    }
+                } else {
+                    warn!(
+                        remote_address:% = address, listener = settings.address.as_str(), packet_type = packet.name();
+                        "The first received packet is not CONNECT"
+                    );
+                    return;
                 }
-            } else {
-                warn!("The first received packet is not CONNECT");
+            }
+            Err(e) => {
+                warn!(
+                    remote_address:% = address, listener = settings.address.as_str();
+                    "Closing connection from {}: {}", &address, &e
+                );
                 return;
             }
-        } else {
-            return;
         }
 
-        while let Ok(packet) = read_packet(&mut read_stream).await {
-            trace!("Read packet: {:?}", &packet);
+        loop {
+            // the broker closes the connection by ending the write loop, the client must not be
+            // able to send anything afterwards
+            let result = select! {
+                result = read_packet(&mut read_stream, max_packet_size) => result,
+                _ = server_event_tx.closed() => {
+                    trace!("Connection of client {:?} closed by the broker", &client_id);
+                    break;
+                }
+            };
+            Self::count_received(&metrics, &statistics, read_stream, &result);
+            match result {
+                Ok(packet) => {
+                    trace!("Read packet: {:?}", &packet);
+
+                    tap.packet(Direction::Received, &packet);
 
-            let event =
-                ClientEvent::ControlPacket(client_id.clone(), packet, server_event_tx.clone());
-            if let Err(e) = client_event_tx.send(event).await {
-                error!("Error while sending client event to be processed: {}", &e);
+                    // the client is not allowed to send anything after DISCONNECT - MQTT-3.14.4-2
+                    let disconnected = matches!(packet, ControlPacket::Disconnect(_));
+
+                    // starts once the packet is read, waiting for the client is not traced
+                    let span = info_span!(
+                        "receive_packet",
+                        client_id = client_id.as_str(),
+                        packet_type = packet.name()
+                    );
+                    let event = ClientEvent::ControlPacket(
+                        client_id.clone(),
+                        packet,
+                        server_event_tx.clone(),
+                        span.clone(),
+                    );
+                    if let Err(e) = client_event_tx.send(event).instrument(span).await {
+                        error!("Error while sending client event to be processed: {}", &e);
+                    }
+
+                    if disconnected {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    match &e {
+                        DecodeError::Io(e) if e.kind() == ErrorKind::ConnectionReset => {
+                            trace!("Client {:?} closed the connection: {}", &client_id, e);
+                        }
+                        _ => warn!(
+                            client_id = client_id.as_str(), remote_address:% = address, listener = settings.address.as_str();
+                            "Closing connection of client {:?}: {}", &client_id, &e
+                        ),
+                    }
+
+                    let _ = server_event_tx.send(ServerEvent::Disconnect).await;
+
+                    let event =
+                        ClientEvent::ConnectionLost(client_id.clone(), server_event_tx.clone());
+                    if let Err(e) = client_event_tx.send(event).await {
+                        error!("Error while sending client event to be processed: {}", &e);
+                    }
+                    break;
+                }
             }
         }
         trace!("Client read task ended");
     }
 
+    fn count_received(
+        metrics: &BrokerMetrics,
+        statistics: &ClientStatistics,
+        read_stream: &mut MqttBytesReadStream,
+        result: &Result<ControlPacket, DecodeError>,
+    ) {
+        let bytes = read_stream.take_bytes_read();
+        metrics.bytes_received.inc_by(bytes);
+        if let Ok(packet) = result {
+            metrics
+                .packets_received
+                .with_label_values(&[packet.name()])
+                .inc();
+        }
+        statistics.add_received(bytes, result.is_ok());
+    }
+
+    async fn reject(server_event_tx: &Sender<ServerEvent>, return_code: ConnAckReturnCode) {
+        let conn_ack = ConnAckPacket::new(false, return_code);
+        let _ = server_event_tx
+            .send(ServerEvent::ControlPacket(ControlPacket::ConnAck(conn_ack)))
+            .await;
+        let _ = server_event_tx.send(ServerEvent::Disconnect).await;
+    }
+
     async fn connection_write_loop(
         mut server_event_rx: Receiver<ServerEvent>,
-        mut write_stream: &mut MqttBytesWriteStream,
+        write_stream: &mut MqttBytesWriteStream,
+        metrics: Arc<BrokerMetrics>,
+        statistics: Arc<ClientStatistics>,
+        tap: ConnectionTap,
     ) {
         while let Some(event) = server_event_rx.recv().await {
             trace!("Received server event: {:?}", &event);
 
             match event {
                 ServerEvent::ControlPacket(packet) => {
-                    trace!("Writing packet: {:?}", &packet);
-                    if let Err(e) = write_packet(&mut write_stream, packet).await {
-                        error!("Error while writing packet: {:?}", &e);
-                    }
+                    tap.packet(Direction::Sent, &packet);
+                    Self::write(write_stream, packet, &metrics, &statistics).await;
+                }
+                ServerEvent::Publish(publish, span) => {
+                    let packet = ControlPacket::Publish(publish);
+                    tap.packet(Direction::Sent, &packet);
+                    let span = info_span!(parent: &span, "write_publish");
+                    Self::write(write_stream, packet, &metrics, &statistics)
+                        .instrument(span)
+                        .await;
                 }
                 ServerEvent::Disconnect => {
                     break;
@@ -152,4 +480,22 @@ impl MqttListener {
 
         trace!("Client write task ended");
     }
+
+    async fn write(
+        write_stream: &mut MqttBytesWriteStream,
+        packet: ControlPacket,
+        metrics: &BrokerMetrics,
+        statistics: &ClientStatistics,
+    ) {
+        trace!("Writing packet: {:?}", &packet);
+        let name = packet.name();
+        let result = write_packet(write_stream, packet).await;
+        match &result {
+            Ok(()) => metrics.packets_sent.with_label_values(&[name]).inc(),
+            Err(e) => error!("Error while writing packet: {:?}", e),
+        }
+        let bytes = write_stream.take_bytes_written();
+        metrics.bytes_sent.inc_by(bytes);
+        statistics.add_sent(bytes, result.is_ok());
+    }
 }
@@ -1,9 +1,12 @@
+pub mod client_id_rules;
 pub mod listener;
 pub mod packets;
+pub mod rate_limiter;
 
 pub mod transport;
 
 pub mod message;
 pub mod subscription;
+pub mod tap;
 
 pub mod events;
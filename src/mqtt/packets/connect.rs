@@ -1,6 +1,7 @@
 use crate::mqtt::packets::{ClientId, ProtocolVersion};
 
 use crate::mqtt::message::Message;
+use bytes::BytesMut;
 
 #[derive(Debug, PartialEq, Clone, Default)]
 pub struct ConnectPacket {
@@ -12,7 +13,8 @@ pub struct ConnectPacket {
     pub will_message: Option<Message>,
 
     pub user_name: Option<String>,
-    pub password: Option<String>,
+    // binary data, not necessarily UTF-8 - MQTT-3.1.3.5
+    pub password: Option<BytesMut>,
 }
 
 impl ConnectPacket {
@@ -23,7 +25,7 @@ impl ConnectPacket {
         clean_session: bool,
         will_message: Option<Message>,
         user_name: Option<String>,
-        password: Option<String>,
+        password: Option<BytesMut>,
     ) -> Self {
         ConnectPacket {
             version,
@@ -16,6 +16,7 @@ use crate::mqtt::packets::ControlPacket::{
 };
 use crate::mqtt::packets::ProtocolVersion::Mqtt3;
 use crate::mqtt::packets::QoS::{AtLeastOnce, AtMostOnce, ExactlyOnce};
+use serde::Deserialize;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 
@@ -53,7 +54,8 @@ pub const PACKET_TYPE_DISCONNECT: u8 = 14;
 
 pub type ClientId = String;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ProtocolVersion {
     Mqtt3,
     Mqtt5,
@@ -83,12 +85,12 @@ impl Display for QoS {
 }
 
 impl QoS {
-    pub fn from_bits(bits: u8) -> QoS {
+    pub fn from_bits(bits: u8) -> Option<QoS> {
         match bits {
-            0 => AtMostOnce,
-            1 => AtLeastOnce,
-            2 => ExactlyOnce,
-            _ => panic!("Invalid QoS"),
+            0 => Some(AtMostOnce),
+            1 => Some(AtLeastOnce),
+            2 => Some(ExactlyOnce),
+            _ => None,
         }
     }
 }
@@ -119,28 +121,33 @@ pub enum ControlPacket {
 
 impl Display for ControlPacket {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        match *self {
-            Connect(_) => write!(f, "CONNECT"),
-            ConnAck(_) => write!(f, "CONNACK"),
-            Publish(_) => write!(f, "PUBLISH"),
-            PubAck(_) => write!(f, "PUBACK"),
-            PubRec(_) => write!(f, "PUBREC"),
-            PubRel(_) => write!(f, "PUBREL"),
-            PubComp(_) => write!(f, "PUBCOMP"),
-            Subscribe(_) => write!(f, "SUBSCRIBE"),
-            SubAck(_) => write!(f, "SUBACK"),
-            Unsubscribe(_) => write!(f, "UNSUBSCRIBE"),
-            UnsubAck(_) => write!(f, "UNSUBACK"),
-            PingReq => write!(f, "PINGREQ"),
-            PingResp => write!(f, "PINGRESP"),
-            Disconnect(_) => write!(f, "DISCONNECT"),
-        }
+        write!(f, "{}", self.name())
     }
 }
 
 impl ControlPacket {
-    pub fn new(packet_id: u8) -> ControlPacket {
-        match packet_id {
+    /// Name of the packet type as used by the specification.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Connect(_) => "CONNECT",
+            ConnAck(_) => "CONNACK",
+            Publish(_) => "PUBLISH",
+            PubAck(_) => "PUBACK",
+            PubRec(_) => "PUBREC",
+            PubRel(_) => "PUBREL",
+            PubComp(_) => "PUBCOMP",
+            Subscribe(_) => "SUBSCRIBE",
+            SubAck(_) => "SUBACK",
+            Unsubscribe(_) => "UNSUBSCRIBE",
+            UnsubAck(_) => "UNSUBACK",
+            PingReq => "PINGREQ",
+            PingResp => "PINGRESP",
+            Disconnect(_) => "DISCONNECT",
+        }
+    }
+
+    pub fn new(packet_id: u8) -> Option<ControlPacket> {
+        let packet = match packet_id {
             PACKET_TYPE_CONNECT => Connect(ConnectPacket::default()),
             PACKET_TYPE_CONN_ACK => ConnAck(ConnAckPacket::default()),
             PACKET_TYPE_PUBLISH => Publish(PublishPacket::default()),
@@ -155,7 +162,9 @@ impl ControlPacket {
             PACKET_TYPE_PING_REQ => PingReq,
             PACKET_TYPE_PING_RESP => PingResp,
             PACKET_TYPE_DISCONNECT => Disconnect(DisconnectPacket::default()),
-            _ => panic!("Invalid packet id!"),
-        }
+            _ => return None,
+        };
+
+        Some(packet)
     }
 }
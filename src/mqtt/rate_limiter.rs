@@ -0,0 +1,84 @@
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Token bucket allowing `rate` acquisitions per second on average with bursts
+/// of at most `rate` acquisitions.
+pub struct RateLimiter {
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32) -> RateLimiter {
+        RateLimiter {
+            rate: rate as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: rate as f64,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> bool {
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_burst_is_limited() {
+        let limiter = RateLimiter::new(3);
+        let now = Instant::now();
+
+        assert!(limiter.try_acquire_at(now));
+        assert!(limiter.try_acquire_at(now));
+        assert!(limiter.try_acquire_at(now));
+        assert!(!limiter.try_acquire_at(now));
+    }
+
+    #[test]
+    fn test_tokens_are_refilled() {
+        let limiter = RateLimiter::new(10);
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            assert!(limiter.try_acquire_at(now));
+        }
+        assert!(!limiter.try_acquire_at(now));
+
+        let later = now + Duration::from_millis(100);
+        assert!(limiter.try_acquire_at(later));
+        assert!(!limiter.try_acquire_at(later));
+
+        // the bucket never holds more than one second of tokens
+        let much_later = now + Duration::from_secs(60);
+        for _ in 0..10 {
+            assert!(limiter.try_acquire_at(much_later));
+        }
+        assert!(!limiter.try_acquire_at(much_later));
+    }
+}
@@ -22,4 +22,29 @@ impl Subscription {
     pub fn topic(&self) -> &str {
         self.topic.as_str()
     }
+
+    pub fn qos(&self) -> QoS {
+        self.qos
+    }
+}
+
+/// Whether the topic filter with `+` and `#` wildcards matches the topic.
+pub fn filter_matches(filter: &str, topic: &str) -> bool {
+    // wildcards at the first level do not match topics starting with $ - MQTT-4.7.2-1
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(filter_level), Some(topic_level)) if filter_level == topic_level => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
 }
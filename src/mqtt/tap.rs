@@ -0,0 +1,281 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::BytesMut;
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use tokio::sync::{mpsc, OnceCell};
+
+use crate::mqtt::packets::{ClientId, ConnectPacket, ControlPacket};
+use crate::mqtt::subscription::filter_matches;
+
+/// Records kept for a tap reader which is behind, newer ones are dropped.
+const TAP_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TapFilter {
+    /// All packets of the client.
+    ClientId(ClientId),
+    /// PUBLISH packets on matching topics, as sent by and to the clients, i.e. without mountpoints.
+    Topic(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TapRecord {
+    pub timestamp: String,
+    pub client_id: ClientId,
+    pub direction: Direction,
+    pub packet_type: &'static str,
+    /// The decoded packet in the debug format.
+    pub packet: String,
+}
+
+impl TapRecord {
+    fn new(client_id: &str, direction: Direction, packet: &ControlPacket) -> TapRecord {
+        TapRecord {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            client_id: client_id.to_string(),
+            direction,
+            packet_type: packet.name(),
+            packet: format!("{:?}", packet),
+        }
+    }
+}
+
+struct Tap {
+    id: u64,
+    filter: TapFilter,
+    tx: mpsc::Sender<TapRecord>,
+}
+
+impl Tap {
+    fn matches(&self, client_id: &str, packet: &ControlPacket) -> bool {
+        match (&self.filter, packet) {
+            (TapFilter::ClientId(tapped), _) => tapped == client_id,
+            (TapFilter::Topic(filter), ControlPacket::Publish(publish)) => {
+                filter_matches(filter, &publish.message.topic)
+            }
+            (TapFilter::Topic(_), _) => false,
+        }
+    }
+}
+
+/// Copies of the packets of selected clients or topics, for debugging in production without
+/// the trace log. The connections pay a single atomic load per packet while no tap is open.
+#[derive(Default)]
+pub struct Taps {
+    open: AtomicUsize,
+    next_id: AtomicU64,
+    taps: Mutex<Vec<Tap>>,
+}
+
+impl Taps {
+    /// The tap is closed once the receiver is dropped.
+    pub fn open(self: &Arc<Self>, filter: TapFilter) -> TapReceiver {
+        let (tx, rx) = mpsc::channel(TAP_CAPACITY);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        let mut taps = self.taps.lock().unwrap();
+        taps.push(Tap { id, filter, tx });
+        self.open.store(taps.len(), Ordering::Release);
+
+        TapReceiver {
+            id,
+            rx,
+            taps: Arc::clone(self),
+        }
+    }
+
+    /// Ends all the receivers, e.g. on shutdown.
+    pub fn close_all(&self) {
+        self.taps.lock().unwrap().clear();
+        self.open.store(0, Ordering::Release);
+    }
+
+    fn close(&self, id: u64) {
+        let mut taps = self.taps.lock().unwrap();
+        taps.retain(|tap| tap.id != id);
+        self.open.store(taps.len(), Ordering::Release);
+    }
+
+    fn is_open(&self) -> bool {
+        self.open.load(Ordering::Acquire) > 0
+    }
+
+    pub fn packet(&self, client_id: &str, direction: Direction, packet: &ControlPacket) {
+        if !self.is_open() {
+            return;
+        }
+
+        let taps = self.taps.lock().unwrap();
+        let mut record = None;
+        for tap in taps.iter().filter(|tap| tap.matches(client_id, packet)) {
+            let record = record.get_or_insert_with(|| TapRecord::new(client_id, direction, packet));
+            // the connection must not wait for the reader of the tap
+            let _ = tap.tx.try_send(record.clone());
+        }
+    }
+}
+
+pub struct TapReceiver {
+    id: u64,
+    rx: mpsc::Receiver<TapRecord>,
+    taps: Arc<Taps>,
+}
+
+impl TapReceiver {
+    pub async fn recv(&mut self) -> Option<TapRecord> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for TapReceiver {
+    fn drop(&mut self) {
+        self.taps.close(self.id);
+    }
+}
+
+/// Taps of a single connection, its packets are tapped once the client id is known.
+#[derive(Clone)]
+pub struct ConnectionTap {
+    taps: Arc<Taps>,
+    client_id: Arc<OnceCell<ClientId>>,
+}
+
+impl ConnectionTap {
+    pub fn new(taps: Arc<Taps>) -> ConnectionTap {
+        ConnectionTap {
+            taps,
+            client_id: Arc::new(OnceCell::new()),
+        }
+    }
+
+    pub fn set_client_id(&self, client_id: &str) {
+        let _ = self.client_id.set(client_id.to_string());
+    }
+
+    pub fn packet(&self, direction: Direction, packet: &ControlPacket) {
+        if let Some(client_id) = self.client_id.get() {
+            self.taps.packet(client_id, direction, packet);
+        }
+    }
+
+    /// The password is not revealed to the taps.
+    pub fn connect(&self, connect: &ConnectPacket) {
+        if self.taps.is_open() {
+            let connect = ConnectPacket {
+                password: connect
+                    .password
+                    .as_ref()
+                    .map(|_| BytesMut::from("<redacted>")),
+                ..connect.clone()
+            };
+            self.packet(Direction::Received, &ControlPacket::Connect(connect));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::packets::{PublishPacket, QoS};
+    use bytes::BytesMut;
+
+    fn publish(topic: &str) -> ControlPacket {
+        ControlPacket::Publish(PublishPacket::new(
+            topic.to_string(),
+            BytesMut::from("hello"),
+            QoS::AtMostOnce,
+            false,
+            None,
+            false,
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_client_id_tap() {
+        let taps = Arc::new(Taps::default());
+        let mut receiver = taps.open(TapFilter::ClientId("c1".to_string()));
+
+        taps.packet("c2", Direction::Received, &ControlPacket::PingReq);
+        taps.packet("c1", Direction::Sent, &publish("a/b"));
+
+        let record = receiver.recv().await.unwrap();
+        assert_eq!(record.client_id, "c1");
+        assert_eq!(record.direction, Direction::Sent);
+        assert_eq!(record.packet_type, "PUBLISH");
+        assert!(record.packet.contains("a/b"), "{}", record.packet);
+        assert!(receiver.rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_topic_tap() {
+        let taps = Arc::new(Taps::default());
+        let mut receiver = taps.open(TapFilter::Topic("a/+".to_string()));
+
+        taps.packet("c1", Direction::Received, &ControlPacket::PingReq);
+        taps.packet("c1", Direction::Received, &publish("b/c"));
+        taps.packet("c2", Direction::Received, &publish("a/c"));
+
+        let record = receiver.recv().await.unwrap();
+        assert_eq!(record.client_id, "c2");
+        assert!(receiver.rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_closed_on_drop() {
+        let taps = Arc::new(Taps::default());
+        let receiver = taps.open(TapFilter::ClientId("c1".to_string()));
+        let mut other = taps.open(TapFilter::ClientId("c1".to_string()));
+        assert_eq!(taps.open.load(Ordering::Acquire), 2);
+
+        drop(receiver);
+        assert_eq!(taps.open.load(Ordering::Acquire), 1);
+
+        taps.close_all();
+        assert_eq!(taps.open.load(Ordering::Acquire), 0);
+        assert!(other.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_connection_tap_waits_for_client_id() {
+        let taps = Arc::new(Taps::default());
+        let mut receiver = taps.open(TapFilter::ClientId("c1".to_string()));
+        let connection = ConnectionTap::new(Arc::clone(&taps));
+
+        connection.packet(Direction::Received, &ControlPacket::PingReq);
+        assert!(receiver.rx.try_recv().is_err());
+
+        connection.set_client_id("c1");
+        connection.packet(Direction::Received, &ControlPacket::PingReq);
+        assert_eq!(receiver.rx.try_recv().unwrap().packet_type, "PINGREQ");
+    }
+
+    #[test]
+    fn test_connect_password_redacted() {
+        let taps = Arc::new(Taps::default());
+        let mut receiver = taps.open(TapFilter::ClientId("c1".to_string()));
+        let connection = ConnectionTap::new(Arc::clone(&taps));
+        let connect = ConnectPacket {
+            client_id: "c1".to_string(),
+            user_name: Some("user".to_string()),
+            password: Some(BytesMut::from("secret")),
+            ..ConnectPacket::default()
+        };
+
+        connection.set_client_id("c1");
+        connection.connect(&connect);
+
+        let record = receiver.rx.try_recv().unwrap();
+        assert_eq!(record.packet_type, "CONNECT");
+        assert!(record.packet.contains("user"), "{}", record.packet);
+        assert!(!record.packet.contains("secret"), "{}", record.packet);
+    }
+}
@@ -1,3 +1,4 @@
 pub mod mqtt_bytes_stream;
 pub mod packet_decoder;
 pub mod packet_encoder;
+pub mod proxy_protocol;
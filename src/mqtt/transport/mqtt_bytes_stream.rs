@@ -1,6 +1,6 @@
 use bytes::{Buf, BufMut, BytesMut};
 use log::trace;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, Error, ErrorKind};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, Error, ErrorKind};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
 
@@ -13,11 +13,13 @@ pub struct MqttBytesStream {
 pub struct MqttBytesWriteStream {
     write_buffer: BytesMut,
     write_stream: OwnedWriteHalf,
+    bytes_written: u64,
 }
 
-pub struct MqttBytesReadStream {
+pub struct MqttBytesReadStream<R = OwnedReadHalf> {
     read_buffer: BytesMut,
-    read_stream: OwnedReadHalf,
+    read_stream: R,
+    bytes_read: u64,
 }
 
 impl MqttBytesStream {
@@ -68,7 +70,8 @@ impl MqttBytesStream {
     }
 
     pub async fn get_bytes(&mut self, size: usize) -> Result<BytesMut, Error> {
-        let mut bytes = BytesMut::with_capacity(size);
+        // the size comes from the client, do not trust it before the data actually arrives
+        let mut bytes = BytesMut::with_capacity(size.min(self.read_buffer.capacity()));
 
         let mut remaining_length = size;
 
@@ -175,14 +178,20 @@ impl MqttBytesStream {
     }
 }
 
-impl MqttBytesReadStream {
-    pub fn new(buffer_size: usize, read_stream: OwnedReadHalf) -> Self {
+impl<R: AsyncRead + Unpin> MqttBytesReadStream<R> {
+    pub fn new(buffer_size: usize, read_stream: R) -> Self {
         MqttBytesReadStream {
             read_buffer: BytesMut::with_capacity(buffer_size),
             read_stream,
+            bytes_read: 0,
         }
     }
 
+    /// Returns the number of bytes read since the last call.
+    pub fn take_bytes_read(&mut self) -> u64 {
+        std::mem::take(&mut self.bytes_read)
+    }
+
     pub async fn get_u8(&mut self) -> Result<u8, Error> {
         self.wait_for_data(1).await?;
 
@@ -199,6 +208,12 @@ impl MqttBytesReadStream {
         trace!("Parsing string size");
         let string_size = self.get_u16().await? as usize;
         trace!("String size: {} ({:#04x})", string_size, string_size);
+
+        self.get_string_of_size(string_size).await
+    }
+
+    /// Reads the string after its length prefix, so the caller can check the length first.
+    pub async fn get_string_of_size(&mut self, string_size: usize) -> Result<String, Error> {
         trace!("Parsing string buf");
         let str_buf = self.get_bytes(string_size).await?;
 
@@ -216,7 +231,8 @@ impl MqttBytesReadStream {
     }
 
     pub async fn get_bytes(&mut self, size: usize) -> Result<BytesMut, Error> {
-        let mut bytes = BytesMut::with_capacity(size);
+        // the size comes from the client, do not trust it before the data actually arrives
+        let mut bytes = BytesMut::with_capacity(size.min(self.read_buffer.capacity()));
 
         let mut remaining_length = size;
 
@@ -241,6 +257,7 @@ impl MqttBytesReadStream {
     async fn wait_for_data(&mut self, bytes: usize) -> Result<(), Error> {
         while self.read_buffer.len() < bytes {
             let read_bytes_num = self.read_stream.read_buf(&mut self.read_buffer).await?;
+            self.bytes_read += read_bytes_num as u64;
 
             // todo: Handle better?
             let connection_closed = read_bytes_num == 0; // end of file
@@ -270,9 +287,15 @@ impl MqttBytesWriteStream {
         MqttBytesWriteStream {
             write_buffer: BytesMut::with_capacity(buffer_size),
             write_stream,
+            bytes_written: 0,
         }
     }
 
+    /// Returns the number of bytes written since the last call.
+    pub fn take_bytes_written(&mut self) -> u64 {
+        std::mem::take(&mut self.bytes_written)
+    }
+
     pub async fn put_u8(&mut self, n: u8) -> Result<(), Error> {
         self.write_buffer_if_too_small(1).await?;
 
@@ -316,14 +339,14 @@ impl MqttBytesWriteStream {
     }
 
     pub async fn finish_packet(&mut self) -> Result<(), Error> {
-        self.write_stream.write_buf(&mut self.write_buffer).await?;
+        self.bytes_written += self.write_stream.write_buf(&mut self.write_buffer).await? as u64;
         // self.tcp_stream.flush().await?;
         Ok(())
     }
 
     async fn write_buffer_if_too_small(&mut self, size: usize) -> Result<(), Error> {
         if self.write_buffer.len() + size >= self.write_buffer.capacity() {
-            self.write_stream.write_buf(&mut self.write_buffer).await?;
+            self.bytes_written += self.write_stream.write_buf(&mut self.write_buffer).await? as u64;
         }
 
         Ok(())
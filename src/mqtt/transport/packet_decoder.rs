@@ -1,3 +1,4 @@
+use crate::mqtt::message::Message;
 use crate::mqtt::packets::puback::PubAckPacket;
 use crate::mqtt::packets::pubcomp::PubCompPacket;
 use crate::mqtt::packets::pubrec::PubRecPacket;
@@ -16,7 +17,55 @@ use crate::mqtt::subscription::Subscription;
 use crate::mqtt::transport::mqtt_bytes_stream::MqttBytesReadStream;
 use async_trait::async_trait;
 use bitflags::bitflags;
-use tokio::io::{Error, ErrorKind};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use tokio::io::{AsyncRead, Error};
+
+const PROTOCOL_LEVEL_MQTT_5: u8 = 5;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(Error),
+    PacketTooLarge { size: u64, max_packet_size: usize },
+    UnsupportedPacketType(u8),
+    MalformedRemainingLength,
+    MalformedFixedHeader(u8),
+    InvalidQoS(u8),
+    MalformedPacket(&'static str),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Io(e) => write!(f, "{}", e),
+            DecodeError::PacketTooLarge {
+                size,
+                max_packet_size,
+            } => write!(
+                f,
+                "Packet size {} exceeds maximum packet size {}",
+                size, max_packet_size
+            ),
+            DecodeError::UnsupportedPacketType(packet_type) => {
+                write!(f, "Unsupported packet type {}", packet_type)
+            }
+            DecodeError::MalformedRemainingLength => write!(f, "Malformed remaining length"),
+            DecodeError::MalformedFixedHeader(first_byte) => {
+                write!(f, "Malformed fixed header {:#04x}", first_byte)
+            }
+            DecodeError::InvalidQoS(qos) => write!(f, "Invalid QoS {}", qos),
+            DecodeError::MalformedPacket(reason) => write!(f, "Malformed packet: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<Error> for DecodeError {
+    fn from(error: Error) -> Self {
+        DecodeError::Io(error)
+    }
+}
 
 #[async_trait]
 pub trait PacketDecoder {
@@ -42,22 +91,25 @@ pub trait PacketDecoder {
     }
 }
 
-pub async fn decode_remaining_length(buffer: &mut MqttBytesReadStream) -> Result<u64, Error> {
+pub async fn decode_remaining_length<R>(
+    buffer: &mut MqttBytesReadStream<R>,
+) -> Result<u64, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
     let mut remaining_length = 0u64;
     let mut multiplier = 1u64;
 
     loop {
         let byte = buffer.get_u8().await?;
         remaining_length += (byte & 127) as u64 * multiplier;
-        multiplier *= 128;
 
         if multiplier > 128 * 128 * 128 {
-            return Err(tokio::io::Error::new(
-                ErrorKind::InvalidData,
-                "Malformed remaining length multiplier",
-            ));
+            return Err(DecodeError::MalformedRemainingLength);
         }
 
+        multiplier *= 128;
+
         let continuation_bit = byte & 128;
         if continuation_bit == 0 {
             break;
@@ -67,11 +119,37 @@ pub async fn decode_remaining_length(buffer: &mut MqttBytesReadStream) -> Result
     Ok(remaining_length)
 }
 
-pub async fn read_packet(mqtt_stream: &mut MqttBytesReadStream) -> Result<ControlPacket, Error> {
+fn remaining_length_size(remaining_length: u64) -> u64 {
+    match remaining_length {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        _ => 4,
+    }
+}
+
+pub async fn read_packet<R>(
+    mqtt_stream: &mut MqttBytesReadStream<R>,
+    max_packet_size: Option<usize>,
+) -> Result<ControlPacket, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
     let first_byte = mqtt_stream.get_u8().await?;
     let packet_type = first_byte >> 4;
     let remaining_length = decode_remaining_length(mqtt_stream).await?;
 
+    // checked before reading the rest of the packet to not allocate memory for it
+    if let Some(max_packet_size) = max_packet_size {
+        let size = 1 + remaining_length_size(remaining_length) + remaining_length;
+        if size > max_packet_size as u64 {
+            return Err(DecodeError::PacketTooLarge {
+                size,
+                max_packet_size,
+            });
+        }
+    }
+
     let packet = match packet_type {
         PACKET_TYPE_CONNECT => decode_connect(mqtt_stream, first_byte, remaining_length).await?,
         PACKET_TYPE_PUBLISH => decode_publish(mqtt_stream, first_byte, remaining_length).await?,
@@ -85,19 +163,25 @@ pub async fn read_packet(mqtt_stream: &mut MqttBytesReadStream) -> Result<Contro
         PACKET_TYPE_UNSUBSCRIBE => {
             decode_unsubscribe(mqtt_stream, first_byte, remaining_length).await?
         }
-        PACKET_TYPE_PING_REQ => ControlPacket::PingReq,
-        PACKET_TYPE_DISCONNECT => ControlPacket::Disconnect(DisconnectPacket::default()),
-        _ => unimplemented!(),
+        PACKET_TYPE_PING_REQ => decode_ping_req(first_byte, remaining_length)?,
+        PACKET_TYPE_DISCONNECT => {
+            decode_disconnect(mqtt_stream, first_byte, remaining_length).await?
+        }
+        // reserved and server to client packets
+        _ => return Err(DecodeError::UnsupportedPacketType(packet_type)),
     };
 
     Ok(packet)
 }
 
-async fn decode_connect(
-    buffer: &mut MqttBytesReadStream,
+async fn decode_connect<R>(
+    buffer: &mut MqttBytesReadStream<R>,
     _first_byte: u8,
-    _remaining_length: u64,
-) -> Result<ControlPacket, Error> {
+    mut remaining_length: u64,
+) -> Result<ControlPacket, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
     bitflags! {
         struct ConnectFlags: u8 {
             const RESERVED =        0b00000001;
@@ -113,37 +197,92 @@ async fn decode_connect(
     }
 
     // variable header
-    let _protocol_name = buffer.get_string().await?;
-    let _protocol_level = buffer.get_u8().await?;
+    let _protocol_name = get_string(buffer, &mut remaining_length).await?;
+
+    let protocol_level = buffer.get_u8().await?;
+    let version = match protocol_level {
+        PROTOCOL_LEVEL_MQTT_5 => ProtocolVersion::Mqtt5,
+        _ => ProtocolVersion::Mqtt3,
+    };
+
     let connect_flags_byte = buffer.get_u8().await?;
     let connect_flags = ConnectFlags::from_bits_truncate(connect_flags_byte);
+    if connect_flags.contains(ConnectFlags::RESERVED) {
+        // MQTT-3.1.2-3
+        return Err(DecodeError::MalformedPacket("CONNECT reserved flag set"));
+    }
 
     let clean_session = connect_flags.contains(ConnectFlags::CLEAN_SESSION);
 
     let keep_alive_seconds = buffer.get_u16().await?;
+    consume(&mut remaining_length, 4)?;
+
+    if version == ProtocolVersion::Mqtt5 {
+        // properties are not supported yet, skip them to get to the client id
+        skip_properties(buffer, &mut remaining_length).await?;
+    }
 
     // payload
-    let client_id = buffer.get_string().await?;
+    let client_id = get_string(buffer, &mut remaining_length).await?;
+
+    let will_message = if connect_flags.contains(ConnectFlags::WILL) {
+        if version == ProtocolVersion::Mqtt5 {
+            skip_properties(buffer, &mut remaining_length).await?;
+        }
+
+        let will_qos_bits = (connect_flags & ConnectFlags::WILL_QOS).bits >> 3;
+        let qos = QoS::from_bits(will_qos_bits).ok_or(DecodeError::InvalidQoS(will_qos_bits))?;
+
+        let topic = get_string(buffer, &mut remaining_length).await?;
+
+        let payload_size = buffer.get_u16().await? as u64;
+        consume(&mut remaining_length, 2 + payload_size)?;
+        let payload = buffer.get_bytes(payload_size as usize).await?;
+
+        Some(Message {
+            topic,
+            payload,
+            qos,
+            retain: connect_flags.contains(ConnectFlags::WILL_RETAIN),
+        })
+    } else {
+        None
+    };
 
     let user_name = if connect_flags.contains(ConnectFlags::USERNAME) {
-        Some(buffer.get_string().await?)
+        let user_name = get_string(buffer, &mut remaining_length).await?;
+        Some(user_name)
     } else {
         None
     };
 
     let password = if connect_flags.contains(ConnectFlags::PASSWORD) {
-        // todo: allow non-utf8 bytes?
-        Some(buffer.get_string().await?)
+        if user_name.is_none() && version != ProtocolVersion::Mqtt5 {
+            // MQTT-3.1.2-22
+            return Err(DecodeError::MalformedPacket(
+                "CONNECT password without user name",
+            ));
+        }
+
+        let password_size = buffer.get_u16().await? as u64;
+        consume(&mut remaining_length, 2 + password_size)?;
+        Some(buffer.get_bytes(password_size as usize).await?)
     } else {
         None
     };
 
+    if remaining_length != 0 {
+        return Err(DecodeError::MalformedPacket(
+            "CONNECT length does not match its content",
+        ));
+    }
+
     let connect_packet = ConnectPacket::new(
-        ProtocolVersion::Mqtt3,
+        version,
         client_id,
         keep_alive_seconds,
         clean_session,
-        None,
+        will_message,
         user_name,
         password,
     );
@@ -151,11 +290,14 @@ async fn decode_connect(
     Ok(ControlPacket::Connect(connect_packet))
 }
 
-async fn decode_publish(
-    buffer: &mut MqttBytesReadStream,
+async fn decode_publish<R>(
+    buffer: &mut MqttBytesReadStream<R>,
     first_byte: u8,
     mut remaining_length: u64,
-) -> Result<ControlPacket, Error> {
+) -> Result<ControlPacket, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
     bitflags! {
         struct FixedHeaderFlags: u8 {
             const RETAIN =  0b00000001;
@@ -171,15 +313,14 @@ async fn decode_publish(
 
     let dup = flags.contains(FixedHeaderFlags::DUP);
     let retain = flags.contains(FixedHeaderFlags::RETAIN);
-    let qos = QoS::from_bits((flags & FixedHeaderFlags::QOS).bits >> 1);
+    let qos_bits = (flags & FixedHeaderFlags::QOS).bits >> 1;
+    let qos = QoS::from_bits(qos_bits).ok_or(DecodeError::InvalidQoS(qos_bits))?;
 
     // variable header
-    let topic = buffer.get_string().await?;
-    remaining_length -= topic.len() as u64;
-    remaining_length -= 2;
+    let topic = get_string(buffer, &mut remaining_length).await?;
 
     let packet_id = if qos > QoS::AtMostOnce {
-        remaining_length -= 2;
+        consume(&mut remaining_length, 2)?;
         Some(buffer.get_u16().await?)
     } else {
         None
@@ -192,73 +333,88 @@ async fn decode_publish(
     Ok(ControlPacket::Publish(packet))
 }
 
-async fn decode_pub_ack(
-    buffer: &mut MqttBytesReadStream,
+async fn decode_pub_ack<R>(
+    buffer: &mut MqttBytesReadStream<R>,
     first_byte: u8,
-    _remaining_length: u64,
-) -> Result<ControlPacket, Error> {
-    let packet_id = decode_packet_with_packet_id(buffer, first_byte, 0b01000000).await?;
+    remaining_length: u64,
+) -> Result<ControlPacket, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let packet_id =
+        decode_packet_with_packet_id(buffer, first_byte, 0b01000000, remaining_length).await?;
     Ok(ControlPacket::PubAck(PubAckPacket::new(packet_id)))
 }
 
-async fn decode_pub_rec(
-    buffer: &mut MqttBytesReadStream,
+async fn decode_pub_rec<R>(
+    buffer: &mut MqttBytesReadStream<R>,
     first_byte: u8,
-    _remaining_length: u64,
-) -> Result<ControlPacket, Error> {
-    let packet_id = decode_packet_with_packet_id(buffer, first_byte, 0b01010000).await?;
+    remaining_length: u64,
+) -> Result<ControlPacket, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let packet_id =
+        decode_packet_with_packet_id(buffer, first_byte, 0b01010000, remaining_length).await?;
     Ok(ControlPacket::PubRec(PubRecPacket::new(packet_id)))
 }
 
-async fn decode_pub_rel(
-    buffer: &mut MqttBytesReadStream,
+async fn decode_pub_rel<R>(
+    buffer: &mut MqttBytesReadStream<R>,
     first_byte: u8,
-    _remaining_length: u64,
-) -> Result<ControlPacket, Error> {
-    let packet_id = decode_packet_with_packet_id(buffer, first_byte, 0b01100010).await?;
+    remaining_length: u64,
+) -> Result<ControlPacket, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let packet_id =
+        decode_packet_with_packet_id(buffer, first_byte, 0b01100010, remaining_length).await?;
     Ok(ControlPacket::PubRel(PubRelPacket::new(packet_id)))
 }
 
-async fn decode_pub_comp(
-    buffer: &mut MqttBytesReadStream,
+async fn decode_pub_comp<R>(
+    buffer: &mut MqttBytesReadStream<R>,
     first_byte: u8,
-    _remaining_length: u64,
-) -> Result<ControlPacket, Error> {
-    let packet_id = decode_packet_with_packet_id(buffer, first_byte, 0b01110000).await?;
+    remaining_length: u64,
+) -> Result<ControlPacket, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let packet_id =
+        decode_packet_with_packet_id(buffer, first_byte, 0b01110000, remaining_length).await?;
     Ok(ControlPacket::PubComp(PubCompPacket::new(packet_id)))
 }
 
-async fn decode_subscribe(
-    buffer: &mut MqttBytesReadStream,
+async fn decode_subscribe<R>(
+    buffer: &mut MqttBytesReadStream<R>,
     first_byte: u8,
     mut remaining_length: u64,
-) -> Result<ControlPacket, Error> {
-    let packet_id = decode_packet_with_packet_id(buffer, first_byte, 0b10000010).await?;
-    remaining_length -= 2;
+) -> Result<ControlPacket, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    validate_first_byte(first_byte, 0b10000010)?;
+
+    consume(&mut remaining_length, 2)?;
+    let packet_id = buffer.get_u16().await?;
 
     // payload
 
     if remaining_length == 0 {
-        return Err(tokio::io::Error::new(
-            ErrorKind::InvalidData,
-            "Malformed payload",
+        return Err(DecodeError::MalformedPacket(
+            "SUBSCRIBE without topic filters",
         ));
     }
 
     let mut subscriptions = Vec::new();
     while remaining_length > 0 {
-        let topic = buffer.get_string().await?;
-        let qos = buffer.get_u8().await?;
-        if qos > 2 {
-            return Err(tokio::io::Error::new(
-                ErrorKind::InvalidData,
-                "Malformed QoS",
-            ));
-        }
+        let topic = get_string(buffer, &mut remaining_length).await?;
+        consume(&mut remaining_length, 1 /* QoS */)?;
 
-        remaining_length -= topic.len() as u64 + 3u64 /* 2 topic length + QoS*/;
+        let qos = buffer.get_u8().await?;
+        let qos = QoS::from_bits(qos).ok_or(DecodeError::InvalidQoS(qos))?;
 
-        let subscription = Subscription::new(topic, QoS::from_bits(qos));
+        let subscription = Subscription::new(topic, qos);
         subscriptions.push(subscription);
     }
 
@@ -266,26 +422,28 @@ async fn decode_subscribe(
     Ok(ControlPacket::Subscribe(packet))
 }
 
-async fn decode_unsubscribe(
-    buffer: &mut MqttBytesReadStream,
+async fn decode_unsubscribe<R>(
+    buffer: &mut MqttBytesReadStream<R>,
     first_byte: u8,
     mut remaining_length: u64,
-) -> Result<ControlPacket, Error> {
-    let packet_id = decode_packet_with_packet_id(buffer, first_byte, 0b10100010).await?;
-    remaining_length -= 2;
+) -> Result<ControlPacket, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    validate_first_byte(first_byte, 0b10100010)?;
+
+    consume(&mut remaining_length, 2)?;
+    let packet_id = buffer.get_u16().await?;
 
     if remaining_length == 0 {
-        return Err(tokio::io::Error::new(
-            ErrorKind::InvalidData,
-            "Malformed payload",
+        return Err(DecodeError::MalformedPacket(
+            "UNSUBSCRIBE without topic filters",
         ));
     }
 
     let mut topics = Vec::new();
     while remaining_length > 0 {
-        let topic = buffer.get_string().await?;
-
-        remaining_length -= topic.len() as u64 + 2u64/* 2 topic length */;
+        let topic = get_string(buffer, &mut remaining_length).await?;
 
         topics.push(topic);
     }
@@ -294,24 +452,100 @@ async fn decode_unsubscribe(
     Ok(ControlPacket::Unsubscribe(packet))
 }
 
-async fn decode_packet_with_packet_id(
-    buffer: &mut MqttBytesReadStream,
+fn decode_ping_req(first_byte: u8, remaining_length: u64) -> Result<ControlPacket, DecodeError> {
+    validate_first_byte(first_byte, 0b11000000)?;
+
+    if remaining_length != 0 {
+        return Err(DecodeError::MalformedPacket("PINGREQ with payload"));
+    }
+
+    Ok(ControlPacket::PingReq)
+}
+
+async fn decode_disconnect<R>(
+    buffer: &mut MqttBytesReadStream<R>,
+    first_byte: u8,
+    remaining_length: u64,
+) -> Result<ControlPacket, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    validate_first_byte(first_byte, 0b11100000)?;
+
+    // MQTT 5 reason code and properties are not supported yet
+    buffer.get_bytes(remaining_length as usize).await?;
+
+    Ok(ControlPacket::Disconnect(DisconnectPacket::default()))
+}
+
+async fn decode_packet_with_packet_id<R>(
+    buffer: &mut MqttBytesReadStream<R>,
     first_byte: u8,
     expected_first_byte: u8,
-) -> Result<u16, Error> {
+    mut remaining_length: u64,
+) -> Result<u16, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
     validate_first_byte(first_byte, expected_first_byte)?;
 
+    consume(&mut remaining_length, 2)?;
     let packet_id = buffer.get_u16().await?;
+
+    // MQTT 5 reason code and properties are not supported yet
+    buffer.get_bytes(remaining_length as usize).await?;
+
     Ok(packet_id)
 }
 
-fn validate_first_byte(actual: u8, expected: u8) -> Result<(), Error> {
+async fn skip_properties<R>(
+    buffer: &mut MqttBytesReadStream<R>,
+    remaining_length: &mut u64,
+) -> Result<(), DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let properties_length = decode_remaining_length(buffer).await?;
+    consume(
+        remaining_length,
+        remaining_length_size(properties_length) + properties_length,
+    )?;
+    buffer.get_bytes(properties_length as usize).await?;
+
+    Ok(())
+}
+
+/// Reads a length prefixed string, failing before reading it when it does not fit into the packet.
+async fn get_string<R>(
+    buffer: &mut MqttBytesReadStream<R>,
+    remaining_length: &mut u64,
+) -> Result<String, DecodeError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    consume(remaining_length, 2 /* length */)?;
+    let string_size = buffer.get_u16().await?;
+    consume(remaining_length, string_size as u64)?;
+
+    Ok(buffer.get_string_of_size(string_size as usize).await?)
+}
+
+/// Subtracts size of the decoded field from the remaining length, failing when the field
+/// does not fit into the packet.
+fn consume(remaining_length: &mut u64, size: u64) -> Result<(), DecodeError> {
+    *remaining_length = remaining_length
+        .checked_sub(size)
+        .ok_or(DecodeError::MalformedPacket(
+            "content exceeds remaining length",
+        ))?;
+
+    Ok(())
+}
+
+fn validate_first_byte(actual: u8, expected: u8) -> Result<(), DecodeError> {
     if actual == expected {
         Ok(())
     } else {
-        Err(tokio::io::Error::new(
-            ErrorKind::InvalidData,
-            "Malformed fixed header",
-        ))
+        Err(DecodeError::MalformedFixedHeader(actual))
     }
 }
@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 use bitflags::bitflags;
-use tokio::io::Error;
+use tokio::io::{Error, ErrorKind};
 
 use crate::mqtt::packets::puback::PubAckPacket;
 use crate::mqtt::packets::pubcomp::PubCompPacket;
@@ -61,7 +61,13 @@ pub async fn write_packet(
         ControlPacket::SubAck(sub_ack) => write_sub_ack(mqtt_stream, sub_ack).await?,
         ControlPacket::UnsubAck(unsub_ack) => write_unsub_ack(mqtt_stream, unsub_ack).await?,
         ControlPacket::PingResp => write_ping_resp(mqtt_stream).await?,
-        _ => unimplemented!(),
+        // client to server packets are never sent by the broker
+        packet => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unable to write client packet {:?}", packet),
+            ))
+        }
     };
 
     mqtt_stream.finish_packet().await?;
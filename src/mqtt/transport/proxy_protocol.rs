@@ -0,0 +1,194 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt, Error, ErrorKind};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LENGTH: usize = 107;
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_VERSION: u8 = 0x20;
+const V2_COMMAND_LOCAL: u8 = 0x00;
+const V2_COMMAND_PROXY: u8 = 0x01;
+const V2_FAMILY_INET: u8 = 0x10;
+const V2_FAMILY_INET6: u8 = 0x20;
+
+/// Reads PROXY protocol (v1 or v2) header from the beginning of the stream.
+///
+/// Returns the source address of the proxied connection or `None` when the proxy
+/// does not provide it (`UNKNOWN` in v1, `LOCAL` command or unsupported family in v2).
+/// Only the header bytes are consumed, so the stream can be used for MQTT afterwards.
+pub async fn read_proxy_header<R>(stream: &mut R) -> Result<Option<SocketAddr>, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    // both v1 ("PROXY UNKNOWN\r\n") and v2 headers are at least 12 bytes long
+    let mut signature = [0u8; 12];
+    stream.read_exact(&mut signature).await?;
+
+    if &signature == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if signature.starts_with(V1_PREFIX) {
+        read_v1(stream, &signature).await
+    } else {
+        Err(malformed("Missing PROXY protocol header"))
+    }
+}
+
+async fn read_v1<R>(stream: &mut R, signature: &[u8]) -> Result<Option<SocketAddr>, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = signature.to_vec();
+
+    while !header.ends_with(b"\r\n") {
+        if header.len() >= V1_MAX_LENGTH {
+            return Err(malformed("PROXY protocol v1 header too long"));
+        }
+        header.push(stream.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&header[V1_PREFIX.len()..header.len() - 2])
+        .map_err(|_| malformed("PROXY protocol v1 header is not valid ASCII"))?;
+
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields[0] {
+        "UNKNOWN" => Ok(None),
+        "TCP4" | "TCP6" => {
+            if fields.len() != 5 {
+                return Err(malformed("Invalid PROXY protocol v1 header"));
+            }
+
+            let ip: IpAddr = fields[1]
+                .parse()
+                .map_err(|_| malformed("Invalid PROXY protocol v1 source address"))?;
+            let port: u16 = fields[3]
+                .parse()
+                .map_err(|_| malformed("Invalid PROXY protocol v1 source port"))?;
+
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(malformed("Unsupported PROXY protocol v1 transport")),
+    }
+}
+
+async fn read_v2<R>(stream: &mut R) -> Result<Option<SocketAddr>, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let version_command = stream.read_u8().await?;
+    let family = stream.read_u8().await?;
+    let length = stream.read_u16().await? as usize;
+
+    let mut addresses = vec![0u8; length];
+    stream.read_exact(&mut addresses).await?;
+
+    if version_command & 0xF0 != V2_VERSION {
+        return Err(malformed("Unsupported PROXY protocol version"));
+    }
+
+    match version_command & 0x0F {
+        V2_COMMAND_LOCAL => Ok(None),
+        V2_COMMAND_PROXY => match family & 0xF0 {
+            V2_FAMILY_INET if length >= 12 => {
+                let mut ip = [0u8; 4];
+                ip.copy_from_slice(&addresses[0..4]);
+                let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+
+                Ok(Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(ip)), port)))
+            }
+            V2_FAMILY_INET6 if length >= 36 => {
+                let mut ip = [0u8; 16];
+                ip.copy_from_slice(&addresses[0..16]);
+                let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+
+                Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(ip)), port)))
+            }
+            V2_FAMILY_INET | V2_FAMILY_INET6 => {
+                Err(malformed("PROXY protocol v2 address block too short"))
+            }
+            // UNSPEC or UNIX sockets - there is no meaningful IP address to use
+            _ => Ok(None),
+        },
+        _ => Err(malformed("Unsupported PROXY protocol v2 command")),
+    }
+}
+
+fn malformed(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_v1_tcp4() {
+        let mut data: &[u8] = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 1883\r\n\x10";
+
+        let address = read_proxy_header(&mut data).await.unwrap();
+
+        assert_eq!(address, Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(data, &[0x10], "must not consume bytes after the header");
+    }
+
+    #[tokio::test]
+    async fn test_v1_tcp6() {
+        let mut data: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 1883\r\n";
+
+        let address = read_proxy_header(&mut data).await.unwrap();
+
+        assert_eq!(address, Some("[2001:db8::1]:4000".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_v1_unknown() {
+        let mut data: &[u8] = b"PROXY UNKNOWN\r\n\x10";
+
+        let address = read_proxy_header(&mut data).await.unwrap();
+
+        assert_eq!(address, None);
+        assert_eq!(data, &[0x10]);
+    }
+
+    #[tokio::test]
+    async fn test_v1_too_long() {
+        let line = format!("PROXY TCP4 {}\r\n", "1".repeat(120));
+        let mut data = line.as_bytes();
+
+        assert!(read_proxy_header(&mut data).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_v2_tcp4() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0x00, 0x0c]);
+        header.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90, 0x07, 0x5b]);
+        header.push(0x10);
+        let mut data = header.as_slice();
+
+        let address = read_proxy_header(&mut data).await.unwrap();
+
+        assert_eq!(address, Some("10.0.0.1:8080".parse().unwrap()));
+        assert_eq!(data, &[0x10]);
+    }
+
+    #[tokio::test]
+    async fn test_v2_local() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        let mut data = header.as_slice();
+
+        let address = read_proxy_header(&mut data).await.unwrap();
+
+        assert_eq!(address, None);
+    }
+
+    #[tokio::test]
+    async fn test_missing_header() {
+        let mut data: &[u8] = &[
+            0x10, 0x23, 0x00, 0x04, 0x4d, 0x51, 0x54, 0x54, 0x04, 0x02, 0x00, 0x3c, 0x00,
+        ];
+
+        assert!(read_proxy_header(&mut data).await.is_err());
+    }
+}
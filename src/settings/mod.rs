@@ -1,31 +1,769 @@
-use config::{Config, ConfigError, Environment, File, FileFormat};
+use std::collections::HashMap;
+
+use config::{Config, ConfigError, Environment, File, FileFormat, Source};
 use serde::Deserialize;
 
+use crate::mqtt::packets::ProtocolVersion;
+
 #[derive(Debug, Deserialize)]
 pub struct MqttSettings {
-    pub listeners_tcp: Vec<String>,
+    #[serde(default = "default_max_packet_size")]
+    pub max_packet_size: usize,
+    /// Accepted connections per second across all listeners.
+    #[serde(default)]
+    pub max_connection_rate: Option<u32>,
+    #[serde(default)]
+    pub client_ids: ClientIdSettings,
+    /// Interval of publishing the `$SYS` topics, 0 disables them.
+    #[serde(default = "default_sys_interval_seconds")]
+    pub sys_interval_seconds: u64,
+    #[serde(default)]
+    pub listeners: Vec<ListenerSettings>,
+    /// Replaced by `listeners`, only read to reject configuration files of older versions.
+    #[serde(default)]
+    listeners_tcp: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize)]
+fn default_max_packet_size() -> usize {
+    1024 * 1024
+}
+
+fn default_sys_interval_seconds() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ClientIdSettings {
+    /// Maximum length in bytes, the spec requires at least 23 to be allowed.
+    #[serde(default)]
+    pub max_length: Option<usize>,
+    /// Content of a regex character class, e.g. `a-zA-Z0-9_-`.
+    #[serde(default)]
+    pub allowed_characters: Option<String>,
+    /// Client ids of clients with a user name must start with it, `%u` is the user name.
+    #[serde(default)]
+    pub user_prefix: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ListenerSettings {
+    pub address: String,
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    #[serde(default)]
+    pub max_packet_size: Option<usize>,
+    #[serde(default = "default_protocol_versions")]
+    pub protocol_versions: Vec<ProtocolVersion>,
+    #[serde(default)]
+    pub allow_anonymous: Option<bool>,
+    #[serde(default)]
+    pub identity_provider: Option<String>,
+    #[serde(default)]
+    pub mountpoint: Option<String>,
+}
+
+fn default_protocol_versions() -> Vec<ProtocolVersion> {
+    vec![ProtocolVersion::Mqtt3]
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct AuthenticationSettings {
     pub password_file: String,
+    #[serde(default = "default_allow_anonymous")]
+    pub allow_anonymous: bool,
+    #[serde(default)]
+    pub disconnect_removed_users: bool,
+    #[serde(default)]
+    pub identity_providers: HashMap<String, IdentityProviderSettings>,
+    #[serde(default)]
+    pub failed_logins: FailedLoginSettings,
+}
+
+fn default_allow_anonymous() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct FailedLoginSettings {
+    #[serde(default = "default_track")]
+    pub by_ip: bool,
+    #[serde(default = "default_track")]
+    pub by_user_name: bool,
+    /// Failed logins allowed before the IP or user name is banned.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// The first ban, every following failed login doubles it.
+    #[serde(default = "default_ban_seconds")]
+    pub ban_seconds: u64,
+    #[serde(default = "default_max_ban_seconds")]
+    pub max_ban_seconds: u64,
+    /// Failed logins are forgotten when there is none for this long.
+    #[serde(default = "default_reset_seconds")]
+    pub reset_seconds: u64,
+}
+
+fn default_track() -> bool {
+    true
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_ban_seconds() -> u64 {
+    1
+}
+
+fn default_max_ban_seconds() -> u64 {
+    300
+}
+
+fn default_reset_seconds() -> u64 {
+    900
+}
+
+impl Default for FailedLoginSettings {
+    fn default() -> Self {
+        FailedLoginSettings {
+            by_ip: default_track(),
+            by_user_name: default_track(),
+            max_attempts: default_max_attempts(),
+            ban_seconds: default_ban_seconds(),
+            max_ban_seconds: default_max_ban_seconds(),
+            reset_seconds: default_reset_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IdentityProviderSettings {
+    File {
+        password_file: String,
+    },
+    Jwt(JwtSettings),
+    Webhook(WebhookSettings),
+    /// Names of other identity providers asked in order.
+    Chain {
+        providers: Vec<String>,
+    },
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct JwtSettings {
+    pub algorithm: JwtAlgorithm,
+    #[serde(default)]
+    pub key_file: Option<String>,
+    #[serde(default)]
+    pub jwks_file: Option<String>,
+    #[serde(default)]
+    pub audience: Option<String>,
+    #[serde(default)]
+    pub issuer: Option<String>,
+    #[serde(default = "default_username_claim")]
+    pub username_claim: String,
+    #[serde(default)]
+    pub topics_claim: Option<String>,
+}
+
+fn default_username_claim() -> String {
+    "sub".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JwtAlgorithm {
+    Hs256,
+    Rs256,
+    Es256,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub url: String,
+    #[serde(default = "default_webhook_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "default_webhook_allow_ttl_seconds")]
+    pub allow_ttl_seconds: u64,
+    #[serde(default = "default_webhook_deny_ttl_seconds")]
+    pub deny_ttl_seconds: u64,
+}
+
+fn default_webhook_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_webhook_allow_ttl_seconds() -> u64 {
+    60
+}
+
+fn default_webhook_deny_ttl_seconds() -> u64 {
+    5
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct AuthorizationSettings {
+    #[serde(default)]
+    pub acl_file: Option<String>,
+    #[serde(default)]
+    pub webhook: Option<WebhookSettings>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReloadSettings {
+    #[serde(default)]
+    pub watch_files: bool,
+    #[serde(default = "default_watch_interval_seconds")]
+    pub watch_interval_seconds: u64,
+}
+
+fn default_watch_interval_seconds() -> u64 {
+    5
+}
+
+impl Default for ReloadSettings {
+    fn default() -> Self {
+        ReloadSettings {
+            watch_files: false,
+            watch_interval_seconds: default_watch_interval_seconds(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ManagementSettings {
+    pub address: String,
+    /// Bearer token of the REST API, the API is disabled without it.
+    #[serde(default)]
+    pub api_token: Option<String>,
+    /// Adds the statistics of every session to the metrics, labeled by the client id.
+    #[serde(default)]
+    pub client_metrics: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AuditSettings {
+    pub file: String,
+    /// The file is rotated when it would grow over this size.
+    #[serde(default = "default_audit_max_file_size_mb")]
+    pub max_file_size_mb: u64,
+    /// Rotated files kept next to the current one, the oldest ones are deleted.
+    #[serde(default = "default_audit_max_files")]
+    pub max_files: usize,
+    /// Records waiting for the writer, further ones are dropped rather than stalling the clients.
+    #[serde(default = "default_audit_queue_size")]
+    pub queue_size: usize,
+}
+
+fn default_audit_max_file_size_mb() -> u64 {
+    100
+}
+
+fn default_audit_max_files() -> usize {
+    10
+}
+
+fn default_audit_queue_size() -> usize {
+    1024
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TracingSettings {
+    /// OTLP/HTTP collector, the spans are sent to `<otlp_endpoint>/v1/traces`.
+    pub otlp_endpoint: String,
+    #[serde(default = "default_tracing_service_name")]
+    pub service_name: String,
+    /// Share of the traces exported, from 0.0 to 1.0.
+    #[serde(default = "default_tracing_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+fn default_tracing_service_name() -> String {
+    "ratelmq".to_string()
+}
+
+fn default_tracing_sample_ratio() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogSettings {
+    /// env_logger filter, e.g. `info,ratelmq::mqtt=debug`, RUST_LOG takes precedence.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    #[serde(default = "default_log_format")]
+    pub format: LogFormat,
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_format() -> LogFormat {
+    LogFormat::Text
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            level: default_log_level(),
+            format: default_log_format(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line.
+    Json,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
     pub mqtt: MqttSettings,
     pub authentication: AuthenticationSettings,
+    #[serde(default)]
+    pub authorization: AuthorizationSettings,
+    #[serde(default)]
+    pub reload: ReloadSettings,
+    #[serde(default)]
+    pub management: Option<ManagementSettings>,
+    #[serde(default)]
+    pub log: LogSettings,
+    #[serde(default)]
+    pub audit: Option<AuditSettings>,
+    #[serde(default)]
+    pub tracing: Option<TracingSettings>,
 }
 
 impl Settings {
     pub fn new(config_filename: &str) -> Result<Self, ConfigError> {
+        Self::from_source(File::with_name(config_filename).format(FileFormat::Toml))
+    }
+
+    fn from_source<T>(source: T) -> Result<Self, ConfigError>
+    where
+        T: Source + Send + Sync + 'static,
+    {
         let mut config = Config::new();
 
-        config.merge(File::with_name(config_filename).format(FileFormat::Toml))?;
+        config.merge(source)?;
         config.merge(Environment::with_prefix("ratelmq").separator("__"))?;
 
-        config.try_into()
+        let mut settings: Settings = config.try_into()?;
+        settings.apply_defaults();
+        settings.validate()?;
+
+        Ok(settings)
+    }
+
+    /// Settings for the tests of other modules.
+    #[cfg(test)]
+    pub(crate) fn from_toml(content: &str) -> Settings {
+        Self::from_source(File::from_str(content, FileFormat::Toml)).unwrap()
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.mqtt.listeners_tcp.is_some() {
+            return Err(ConfigError::Message(
+                "mqtt.listeners_tcp is no longer supported, configure each listener in a [[mqtt.listeners]] table with its address instead".to_string(),
+            ));
+        }
+        if self.mqtt.listeners.is_empty() {
+            return Err(ConfigError::Message(
+                "no listener configured, add a [[mqtt.listeners]] table".to_string(),
+            ));
+        }
+
+        if matches!(&self.audit, Some(audit) if audit.queue_size == 0) {
+            return Err(ConfigError::Message(
+                "audit.queue_size must be greater than 0".to_string(),
+            ));
+        }
+
+        for listener in &self.mqtt.listeners {
+            // MQTT 5 properties are neither decoded nor encoded yet
+            if listener.protocol_versions.contains(&ProtocolVersion::Mqtt5) {
+                return Err(ConfigError::Message(format!(
+                    "listener {}: protocol version mqtt5 is not supported yet",
+                    &listener.address
+                )));
+            }
+            if let Some(name) = &listener.identity_provider {
+                if !self.authentication.identity_providers.contains_key(name) {
+                    return Err(ConfigError::Message(format!(
+                        "listener {}: unknown identity provider {:?}",
+                        &listener.address, name
+                    )));
+                }
+            }
+            // the prefix and the topics of the clients must not run into each other
+            if let Some(mountpoint) = &listener.mountpoint {
+                if !mountpoint.ends_with('/') || mountpoint.contains(['+', '#']) {
+                    return Err(ConfigError::Message(format!(
+                        "listener {}: mountpoint {:?} must end with / and must not contain wildcards",
+                        &listener.address, mountpoint
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_defaults(&mut self) {
+        for listener in &mut self.mqtt.listeners {
+            listener
+                .max_packet_size
+                .get_or_insert(self.mqtt.max_packet_size);
+            listener
+                .allow_anonymous
+                .get_or_insert(self.authentication.allow_anonymous);
+        }
     }
 }
 
 // todo: tests for envs precedence
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_file() {
+        let settings = Settings::new("config/ratelmq.toml").unwrap();
+
+        assert_eq!(settings.mqtt.listeners.len(), 1);
+        assert_eq!(settings.mqtt.listeners[0].address, "0.0.0.0:1883");
+    }
+
+    #[test]
+    fn test_listener_defaults() {
+        let settings = from_str(
+            r#"
+            [[mqtt.listeners]]
+            address = "127.0.0.1:1883"
+
+            [authentication]
+            password_file = "passwd"
+            "#,
+        );
+
+        let listener = &settings.mqtt.listeners[0];
+        assert!(!listener.proxy_protocol);
+        assert_eq!(listener.max_connections, None);
+        assert_eq!(listener.max_packet_size, Some(1024 * 1024));
+        assert_eq!(listener.protocol_versions, vec![ProtocolVersion::Mqtt3]);
+        assert_eq!(listener.allow_anonymous, Some(true));
+        assert_eq!(listener.identity_provider, None);
+        assert_eq!(listener.mountpoint, None);
+        assert_eq!(settings.authorization.acl_file, None);
+        assert!(settings.authorization.webhook.is_none());
+        assert!(!settings.authentication.disconnect_removed_users);
+        assert!(!settings.reload.watch_files);
+        assert_eq!(settings.reload.watch_interval_seconds, 5);
+        assert!(settings.management.is_none());
+        assert_eq!(settings.mqtt.max_connection_rate, None);
+        assert_eq!(settings.mqtt.client_ids.max_length, None);
+        assert_eq!(settings.mqtt.client_ids.allowed_characters, None);
+        assert_eq!(settings.mqtt.client_ids.user_prefix, None);
+        assert_eq!(settings.mqtt.sys_interval_seconds, 10);
+        assert_eq!(settings.log.level, "info");
+        assert_eq!(settings.log.format, LogFormat::Text);
+        assert!(settings.audit.is_none());
+        assert!(settings.tracing.is_none());
+
+        let failed_logins = &settings.authentication.failed_logins;
+        assert!(failed_logins.by_ip);
+        assert!(failed_logins.by_user_name);
+        assert_eq!(failed_logins.max_attempts, 5);
+        assert_eq!(failed_logins.ban_seconds, 1);
+        assert_eq!(failed_logins.max_ban_seconds, 300);
+        assert_eq!(failed_logins.reset_seconds, 900);
+    }
+
+    #[test]
+    fn test_connection_limits() {
+        let settings = from_str(
+            r#"
+            [mqtt]
+            max_connection_rate = 100
+
+            [mqtt.client_ids]
+            max_length = 23
+            allowed_characters = "a-zA-Z0-9_-"
+            user_prefix = "%u-"
+
+            [[mqtt.listeners]]
+            address = "127.0.0.1:1883"
+
+            [authentication]
+            password_file = "passwd"
+
+            [authentication.failed_logins]
+            by_user_name = false
+            max_attempts = 3
+            max_ban_seconds = 60
+            "#,
+        );
+
+        assert_eq!(settings.mqtt.max_connection_rate, Some(100));
+        assert_eq!(settings.mqtt.client_ids.max_length, Some(23));
+        assert_eq!(
+            settings.mqtt.client_ids.allowed_characters.as_deref(),
+            Some("a-zA-Z0-9_-")
+        );
+        assert_eq!(settings.mqtt.client_ids.user_prefix.as_deref(), Some("%u-"));
+
+        let failed_logins = &settings.authentication.failed_logins;
+        assert!(failed_logins.by_ip);
+        assert!(!failed_logins.by_user_name);
+        assert_eq!(failed_logins.max_attempts, 3);
+        assert_eq!(failed_logins.ban_seconds, 1);
+        assert_eq!(failed_logins.max_ban_seconds, 60);
+    }
+
+    #[test]
+    fn test_observability() {
+        let settings = from_str(
+            r#"
+            [[mqtt.listeners]]
+            address = "127.0.0.1:1883"
+
+            [authentication]
+            password_file = "passwd"
+
+            [log]
+            level = "warn,ratelmq::broker=debug"
+            format = "json"
+
+            [audit]
+            file = "/var/log/ratelmq/audit.log"
+            max_files = 30
+
+            [tracing]
+            otlp_endpoint = "http://127.0.0.1:4318"
+            sample_ratio = 0.1
+            "#,
+        );
+
+        assert_eq!(settings.log.level, "warn,ratelmq::broker=debug");
+        assert_eq!(settings.log.format, LogFormat::Json);
+
+        let audit = settings.audit.unwrap();
+        assert_eq!(audit.file, "/var/log/ratelmq/audit.log");
+        assert_eq!(audit.max_file_size_mb, 100);
+        assert_eq!(audit.max_files, 30);
+        assert_eq!(audit.queue_size, 1024);
+
+        let tracing = settings.tracing.unwrap();
+        assert_eq!(tracing.otlp_endpoint, "http://127.0.0.1:4318");
+        assert_eq!(tracing.service_name, "ratelmq");
+        assert_eq!(tracing.sample_ratio, 0.1);
+    }
+
+    #[test]
+    fn test_listeners_with_identity_providers() {
+        let settings = from_str(
+            r#"
+            [[mqtt.listeners]]
+            address = "127.0.0.1:1883"
+            max_connections = 100
+            max_packet_size = 1024
+            protocol_versions = [ "mqtt3" ]
+            identity_provider = "internal"
+            mountpoint = "tenants/%u/"
+
+            [[mqtt.listeners]]
+            address = "127.0.0.1:1884"
+            proxy_protocol = true
+            allow_anonymous = true
+
+            [mqtt]
+            max_packet_size = 2048
+
+            [authentication]
+            password_file = "passwd"
+            allow_anonymous = false
+
+            [authentication.identity_providers.internal]
+            type = "file"
+            password_file = "passwd-internal"
+
+            [authentication.identity_providers.sso]
+            type = "jwt"
+            algorithm = "rs256"
+            jwks_file = "jwks.json"
+            audience = "mqtt"
+            topics_claim = "mqtt_topics"
+
+            [authentication.identity_providers.external]
+            type = "webhook"
+            url = "http://127.0.0.1:8080/authenticate"
+
+            [authentication.identity_providers.chained]
+            type = "chain"
+            providers = ["internal", "sso", "external"]
+
+            [authorization]
+            acl_file = "acl"
+
+            [authorization.webhook]
+            url = "http://127.0.0.1:8080/authorize"
+            timeout_ms = 500
+            allow_ttl_seconds = 30
+            deny_ttl_seconds = 0
+            "#,
+        );
+
+        let internal = &settings.mqtt.listeners[0];
+        assert_eq!(internal.max_connections, Some(100));
+        assert_eq!(internal.max_packet_size, Some(1024));
+        assert_eq!(internal.protocol_versions, vec![ProtocolVersion::Mqtt3]);
+        assert_eq!(internal.allow_anonymous, Some(false));
+        assert_eq!(internal.identity_provider, Some("internal".to_string()));
+        assert_eq!(internal.mountpoint, Some("tenants/%u/".to_string()));
+
+        assert_eq!(settings.authorization.acl_file, Some("acl".to_string()));
+        let webhook = settings.authorization.webhook.as_ref().unwrap();
+        assert_eq!(webhook.url, "http://127.0.0.1:8080/authorize");
+        assert_eq!(webhook.timeout_ms, 500);
+        assert_eq!(webhook.allow_ttl_seconds, 30);
+        assert_eq!(webhook.deny_ttl_seconds, 0);
+
+        assert!(settings.mqtt.listeners[1].proxy_protocol);
+        assert_eq!(settings.mqtt.listeners[1].allow_anonymous, Some(true));
+        assert_eq!(settings.mqtt.listeners[1].max_packet_size, Some(2048));
+
+        match &settings.authentication.identity_providers["internal"] {
+            IdentityProviderSettings::File { password_file } => {
+                assert_eq!(password_file, "passwd-internal")
+            }
+            _ => panic!("Invalid identity provider type"),
+        }
+
+        match &settings.authentication.identity_providers["sso"] {
+            IdentityProviderSettings::Jwt(jwt) => {
+                assert_eq!(jwt.algorithm, JwtAlgorithm::Rs256);
+                assert_eq!(jwt.key_file, None);
+                assert_eq!(jwt.jwks_file, Some("jwks.json".to_string()));
+                assert_eq!(jwt.audience, Some("mqtt".to_string()));
+                assert_eq!(jwt.issuer, None);
+                assert_eq!(jwt.username_claim, "sub");
+                assert_eq!(jwt.topics_claim, Some("mqtt_topics".to_string()));
+            }
+            _ => panic!("Invalid identity provider type"),
+        }
+
+        match &settings.authentication.identity_providers["chained"] {
+            IdentityProviderSettings::Chain { providers } => {
+                assert_eq!(providers, &vec!["internal", "sso", "external"]);
+            }
+            _ => panic!("Invalid identity provider type"),
+        }
+
+        match &settings.authentication.identity_providers["external"] {
+            IdentityProviderSettings::Webhook(webhook) => {
+                assert_eq!(webhook.url, "http://127.0.0.1:8080/authenticate");
+                assert_eq!(webhook.timeout_ms, 1000);
+                assert_eq!(webhook.allow_ttl_seconds, 60);
+                assert_eq!(webhook.deny_ttl_seconds, 5);
+            }
+            _ => panic!("Invalid identity provider type"),
+        }
+    }
+
+    #[test]
+    fn test_mqtt5_is_rejected() {
+        let result = Settings::from_source(File::from_str(
+            r#"
+            [[mqtt.listeners]]
+            address = "127.0.0.1:1883"
+            protocol_versions = [ "mqtt3", "mqtt5" ]
+
+            [authentication]
+            password_file = "passwd"
+            "#,
+            FileFormat::Toml,
+        ));
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "listener 127.0.0.1:1883: protocol version mqtt5 is not supported yet"
+        );
+    }
+
+    #[test]
+    fn test_mountpoint_without_separator_is_rejected() {
+        for mountpoint in ["tenant-a", "", "tenant-a/#/"] {
+            let result = Settings::from_source(File::from_str(
+                &format!(
+                    r#"
+                    [[mqtt.listeners]]
+                    address = "127.0.0.1:1883"
+                    mountpoint = "{}"
+
+                    [authentication]
+                    password_file = "passwd"
+                    "#,
+                    mountpoint
+                ),
+                FileFormat::Toml,
+            ));
+
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                format!(
+                    "listener 127.0.0.1:1883: mountpoint {:?} must end with / and must not contain wildcards",
+                    mountpoint
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_identity_provider_is_rejected() {
+        let result = Settings::from_source(File::from_str(
+            r#"
+            [[mqtt.listeners]]
+            address = "127.0.0.1:1883"
+            identity_provider = "sso"
+
+            [authentication]
+            password_file = "passwd"
+            "#,
+            FileFormat::Toml,
+        ));
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "listener 127.0.0.1:1883: unknown identity provider \"sso\""
+        );
+    }
+
+    #[test]
+    fn test_listeners_tcp_is_rejected() {
+        let result = Settings::from_source(File::from_str(
+            r#"
+            [mqtt]
+            listeners_tcp = [ "0.0.0.0:1883" ]
+
+            [authentication]
+            password_file = "passwd"
+            "#,
+            FileFormat::Toml,
+        ));
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("mqtt.listeners_tcp is no longer supported"));
+    }
+
+    fn from_str(content: &str) -> Settings {
+        Settings::from_toml(content)
+    }
+}
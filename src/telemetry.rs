@@ -0,0 +1,47 @@
+use opentelemetry::trace::TraceError;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{self, Sampler};
+use opentelemetry_sdk::Resource;
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Layer;
+
+use crate::settings::TracingSettings;
+
+/// Exports the spans of the broker to the OpenTelemetry collector in batches, without it the spans
+/// are not recorded at all. The log is not affected.
+pub fn init(settings: &TracingSettings) -> Result<(), TraceError> {
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(&settings.otlp_endpoint),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                    settings.sample_ratio,
+                ))))
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    settings.service_name.clone(),
+                )])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+    // spans of the HTTP client sending the spans must not be exported
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(Targets::new().with_target("ratelmq", Level::TRACE));
+
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        .map_err(|e| TraceError::Other(Box::new(e)))
+}
+
+/// Exports the spans which are still buffered.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
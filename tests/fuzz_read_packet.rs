@@ -0,0 +1,148 @@
+//! Feeds random and mutated packets to the decoder to make sure that malformed input
+//! from a client is always reported as an error and never panics.
+
+use ratelmq::mqtt::transport::mqtt_bytes_stream::MqttBytesReadStream;
+use ratelmq::mqtt::transport::packet_decoder;
+
+const ITERATIONS: usize = 20_000;
+
+const VALID_PACKETS: &[&[u8]] = &[
+    // CONNECT with user name and password
+    &[
+        0x10, 0x1a, 0x00, 0x04, 0x4d, 0x51, 0x54, 0x54, 0x04, 0xc2, 0x00, 0x3c, 0x00, 0x02, 0x63,
+        0x31, 0x00, 0x04, 0x75, 0x73, 0x65, 0x72, 0x00, 0x04, 0x70, 0x61, 0x73, 0x73,
+    ],
+    // CONNECT with will message
+    &[
+        0x10, 0x1a, 0x00, 0x04, 0x4d, 0x51, 0x54, 0x54, 0x04, 0x2e, 0x00, 0x3c, 0x00, 0x02, 0x63,
+        0x31, 0x00, 0x05, 0x77, 0x2f, 0x6c, 0x77, 0x74, 0x00, 0x03, 0x62, 0x79, 0x65,
+    ],
+    // MQTT 5 CONNECT with properties
+    &[
+        0x10, 0x12, 0x00, 0x04, 0x4d, 0x51, 0x54, 0x54, 0x05, 0x02, 0x00, 0x3c, 0x03, 0x21, 0x00,
+        0x14, 0x00, 0x02, 0x63, 0x31,
+    ],
+    // PUBLISH QoS 1
+    &[
+        0x32, 0x0b, 0x00, 0x03, 0x61, 0x2f, 0x62, 0x00, 0x01, 0x62, 0x6f, 0x64, 0x79,
+    ],
+    // PUBACK, PUBREC, PUBREL, PUBCOMP
+    &[0x40, 0x02, 0x00, 0x01],
+    &[0x50, 0x02, 0x00, 0x01],
+    &[0x62, 0x02, 0x00, 0x01],
+    &[0x70, 0x02, 0x00, 0x01],
+    // SUBSCRIBE
+    &[
+        0x82, 0x0a, 0x00, 0x01, 0x00, 0x01, 0x61, 0x01, 0x00, 0x01, 0x23, 0x02,
+    ],
+    // UNSUBSCRIBE
+    &[0xa2, 0x05, 0x00, 0x01, 0x00, 0x01, 0x61],
+    // PINGREQ
+    &[0xc0, 0x00],
+    // DISCONNECT
+    &[0xe0, 0x00],
+];
+
+/// Deterministic xorshift generator, so a failing input can be reproduced.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, max: usize) -> usize {
+        (self.next() % max as u64) as usize
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.next() as u8
+    }
+}
+
+#[tokio::test]
+async fn fuzz_random_bytes() {
+    let mut random = Random(0x2545_f491_4f6c_dd1d);
+
+    for _ in 0..ITERATIONS {
+        let length = random.below(64);
+        let data: Vec<u8> = (0..length).map(|_| random.byte()).collect();
+
+        read_packets(&data).await;
+    }
+}
+
+#[tokio::test]
+async fn fuzz_random_bytes_with_valid_packet_type() {
+    let mut random = Random(0x9e37_79b9_7f4a_7c15);
+
+    for _ in 0..ITERATIONS {
+        let length = random.below(64);
+        let mut data: Vec<u8> = (0..length).map(|_| random.byte()).collect();
+
+        // keep the remaining length small to exercise the packet decoders
+        data.insert(0, random.byte() & 0x3f);
+        data.insert(0, random.byte());
+
+        read_packets(&data).await;
+    }
+}
+
+#[tokio::test]
+async fn fuzz_mutated_valid_packets() {
+    let mut random = Random(0xdead_beef_cafe_babe);
+
+    for _ in 0..ITERATIONS {
+        let mut data = VALID_PACKETS[random.below(VALID_PACKETS.len())].to_vec();
+
+        for _ in 0..=random.below(4) {
+            match random.below(4) {
+                0 => {
+                    let index = random.below(data.len());
+                    data[index] = random.byte();
+                }
+                1 => {
+                    let index = random.below(data.len());
+                    data[index] ^= 1 << random.below(8);
+                }
+                2 => data.truncate(random.below(data.len() + 1)),
+                _ => {
+                    let index = random.below(data.len() + 1);
+                    data.insert(index, random.byte());
+                }
+            }
+
+            if data.is_empty() {
+                break;
+            }
+        }
+
+        read_packets(&data).await;
+    }
+}
+
+#[tokio::test]
+async fn fuzz_valid_packets_are_decoded() {
+    for packet in VALID_PACKETS {
+        let mut data: &[u8] = packet;
+        let mut stream = MqttBytesReadStream::new(4096, &mut data);
+
+        packet_decoder::read_packet(&mut stream, None)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to decode {:02x?}: {}", packet, e));
+        assert!(data.is_empty(), "{:02x?} was not fully consumed", packet);
+    }
+}
+
+/// Reads packets until the decoder fails, the same way a connection does.
+async fn read_packets(mut data: &[u8]) {
+    let mut stream = MqttBytesReadStream::new(4096, &mut data);
+
+    while packet_decoder::read_packet(&mut stream, Some(1024))
+        .await
+        .is_ok()
+    {}
+}
@@ -5,6 +5,7 @@ use ratelmq::mqtt::packets::{ControlPacket, ProtocolVersion, QoS};
 use ratelmq::mqtt::subscription::Subscription;
 use ratelmq::mqtt::transport::mqtt_bytes_stream::MqttBytesReadStream;
 use ratelmq::mqtt::transport::packet_decoder;
+use ratelmq::mqtt::transport::packet_decoder::DecodeError;
 
 #[tokio::test]
 async fn it_read_connect_min() {
@@ -63,7 +64,7 @@ async fn it_read_connect_password() {
 
     match packet {
         ControlPacket::Connect(connect) => {
-            assert_eq!(connect.password, Some("password".to_string()));
+            assert_eq!(connect.password.as_deref(), Some(&b"password"[..]));
         }
         _ => panic!("Invalid packet type"),
     };
@@ -282,7 +283,192 @@ async fn it_read_ping_req() {
     };
 }
 
+#[tokio::test]
+async fn it_read_publish_exceeding_max_packet_size() {
+    // PUBLISH declaring 256 MB remaining length, followed by no data
+    const DATA: &[u8] = &[0x30, 0xff, 0xff, 0xff, 0x7f];
+
+    let error = read_packet_with_limit(DATA, Some(1024)).await.unwrap_err();
+
+    match error {
+        DecodeError::PacketTooLarge {
+            size,
+            max_packet_size,
+        } => {
+            assert_eq!(size, 268_435_460);
+            assert_eq!(max_packet_size, 1024);
+        }
+        _ => panic!("Invalid error {:?}", error),
+    }
+}
+
+#[tokio::test]
+async fn it_read_publish_within_max_packet_size() {
+    const DATA: &[u8] = &[
+        0x30, 0x10, 0x00, 0x05, 0x61, 0x2f, 0x62, 0x2f, 0x63, 0x74, 0x65, 0x73, 0x74, 0x20, 0x62,
+        0x6f, 0x64, 0x79,
+    ];
+
+    let packet = read_packet_with_limit(DATA, Some(DATA.len()))
+        .await
+        .unwrap();
+
+    assert!(matches!(packet, ControlPacket::Publish(_)));
+}
+
+#[tokio::test]
+async fn it_read_connect_will_message() {
+    const DATA: &[u8] = &[
+        0x10, 0x1a, 0x00, 0x04, 0x4d, 0x51, 0x54, 0x54, 0x04, 0x2e, 0x00, 0x3c, 0x00, 0x02, 0x63,
+        0x31, 0x00, 0x05, 0x77, 0x2f, 0x6c, 0x77, 0x74, 0x00, 0x03, 0x62, 0x79, 0x65,
+    ];
+
+    let packet = read_packet(DATA).await;
+
+    match packet {
+        ControlPacket::Connect(connect) => {
+            assert_eq!(connect.client_id, "c1");
+
+            let will_message = connect.will_message.unwrap();
+            assert_eq!(will_message.topic, "w/lwt");
+            assert_eq!(will_message.payload.as_ref(), b"bye");
+            assert_eq!(will_message.qos, QoS::AtLeastOnce);
+            assert!(will_message.retain);
+        }
+        _ => panic!("Invalid packet type"),
+    };
+}
+
+#[tokio::test]
+async fn it_read_connect_binary_password() {
+    const DATA: &[u8] = &[
+        0x10, 0x15, 0x00, 0x04, 0x4d, 0x51, 0x54, 0x54, 0x04, 0xc2, 0x00, 0x3c, 0x00, 0x02, 0x63,
+        0x31, 0x00, 0x01, 0x75, 0x00, 0x02, 0xff, 0x00,
+    ];
+
+    let packet = read_packet(DATA).await;
+
+    match packet {
+        ControlPacket::Connect(connect) => {
+            assert_eq!(connect.user_name, Some("u".to_string()));
+            assert_eq!(connect.password.as_deref(), Some(&[0xff, 0x00][..]));
+        }
+        _ => panic!("Invalid packet type"),
+    };
+}
+
+#[tokio::test]
+async fn it_read_connect_password_without_user_name() {
+    const DATA: &[u8] = &[
+        0x10, 0x12, 0x00, 0x04, 0x4d, 0x51, 0x54, 0x54, 0x04, 0x42, 0x00, 0x3c, 0x00, 0x02, 0x63,
+        0x31, 0x00, 0x02, 0x70, 0x77,
+    ];
+
+    let error = read_malformed_packet(DATA).await;
+
+    assert!(matches!(error, DecodeError::MalformedPacket(_)));
+}
+
+#[tokio::test]
+async fn it_read_unknown_packet_type() {
+    // CONNACK is sent only by the server
+    const DATA: &[u8] = &[0x20, 0x02, 0x00, 0x00];
+
+    let error = read_malformed_packet(DATA).await;
+
+    assert!(matches!(error, DecodeError::UnsupportedPacketType(2)));
+}
+
+#[tokio::test]
+async fn it_read_publish_invalid_qos() {
+    const DATA: &[u8] = &[0x36, 0x05, 0x00, 0x01, 0x61, 0x00, 0x01];
+
+    let error = read_malformed_packet(DATA).await;
+
+    assert!(matches!(error, DecodeError::InvalidQoS(3)));
+}
+
+#[tokio::test]
+async fn it_read_publish_topic_exceeding_remaining_length() {
+    const DATA: &[u8] = &[0x30, 0x02, 0x00, 0x05, 0x61, 0x2f, 0x62, 0x2f, 0x63];
+
+    let error = read_malformed_packet(DATA).await;
+
+    assert!(matches!(error, DecodeError::MalformedPacket(_)));
+}
+
+#[tokio::test]
+async fn it_read_publish_topic_length_checked_before_reading_topic() {
+    // the topic would be read past the end of the packet and of the data
+    const DATA: &[u8] = &[0x30, 0x02, 0xff, 0xff, 0xc0, 0x00];
+
+    let error = read_malformed_packet(DATA).await;
+
+    assert!(matches!(error, DecodeError::MalformedPacket(_)));
+}
+
+#[tokio::test]
+async fn it_read_subscribe_invalid_qos() {
+    const DATA: &[u8] = &[0x82, 0x06, 0x00, 0x01, 0x00, 0x01, 0x61, 0x03];
+
+    let error = read_malformed_packet(DATA).await;
+
+    assert!(matches!(error, DecodeError::InvalidQoS(3)));
+}
+
+#[tokio::test]
+async fn it_read_subscribe_without_topics() {
+    const DATA: &[u8] = &[0x82, 0x02, 0x00, 0x01];
+
+    let error = read_malformed_packet(DATA).await;
+
+    assert!(matches!(error, DecodeError::MalformedPacket(_)));
+}
+
+#[tokio::test]
+async fn it_read_subscribe_too_short() {
+    const DATA: &[u8] = &[0x82, 0x01, 0x00];
+
+    let error = read_malformed_packet(DATA).await;
+
+    assert!(matches!(error, DecodeError::MalformedPacket(_)));
+}
+
+#[tokio::test]
+async fn it_read_subscribe_invalid_flags() {
+    const DATA: &[u8] = &[0x80, 0x06, 0x00, 0x01, 0x00, 0x01, 0x61, 0x00];
+
+    let error = read_malformed_packet(DATA).await;
+
+    assert!(matches!(error, DecodeError::MalformedFixedHeader(0x80)));
+}
+
+#[tokio::test]
+async fn it_read_malformed_remaining_length() {
+    const DATA: &[u8] = &[0x30, 0xff, 0xff, 0xff, 0xff, 0x01];
+
+    let error = read_malformed_packet(DATA).await;
+
+    assert!(matches!(error, DecodeError::MalformedRemainingLength));
+}
+
+#[tokio::test]
+async fn it_read_truncated_packet() {
+    const DATA: &[u8] = &[0x30, 0x10, 0x00, 0x05, 0x61];
+
+    let error = read_malformed_packet(DATA).await;
+
+    assert!(matches!(error, DecodeError::Io(_)));
+}
+
 async fn read_packet(data: &[u8]) -> ControlPacket {
+    read_packet_with_limit(data, None).await.unwrap()
+}
+
+async fn read_packet_with_limit(
+    data: &[u8],
+    max_packet_size: Option<usize>,
+) -> Result<ControlPacket, DecodeError> {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
 
     let mut client = TcpStream::connect(listener.local_addr().unwrap())
@@ -295,5 +481,13 @@ async fn read_packet(data: &[u8]) -> ControlPacket {
     let (rx, _) = server.into_split();
     let mut mqtt_buffer = MqttBytesReadStream::new(4096, rx);
 
-    packet_decoder::read_packet(&mut mqtt_buffer).await.unwrap()
+    packet_decoder::read_packet(&mut mqtt_buffer, max_packet_size).await
+}
+
+async fn read_malformed_packet(mut data: &[u8]) -> DecodeError {
+    let mut mqtt_buffer = MqttBytesReadStream::new(4096, &mut data);
+
+    packet_decoder::read_packet(&mut mqtt_buffer, None)
+        .await
+        .unwrap_err()
 }